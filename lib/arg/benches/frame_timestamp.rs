@@ -0,0 +1,31 @@
+use arg::{FrameTimestamps, VideoInfo};
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+
+const FRAMES: u64 = 100_000;
+
+fn video_info() -> VideoInfo {
+    VideoInfo::from_duration_and_fps(0.0, 30000f64 / 1001f64)
+}
+
+fn frame_timestamp(c: &mut Criterion) {
+    let info = video_info();
+    let mut group = c.benchmark_group("frame_timestamp");
+    group.bench_function("frame_to_timestamp", |b| {
+        b.iter(|| {
+            (0..FRAMES)
+                .map(|n| info.frame_to_timestamp(black_box(n)))
+                .fold(0i64, i64::wrapping_add)
+        })
+    });
+    group.bench_function("next_frame_timestamp", |b| {
+        b.iter(|| {
+            FrameTimestamps::new(&info, 30_000, 1001, black_box(0))
+                .take(FRAMES as usize)
+                .fold(0i64, i64::wrapping_add)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, frame_timestamp);
+criterion_main!(benches);
@@ -5,6 +5,10 @@ fn main() {
     cbindgen::Builder::new()
         .with_crate(crate_dir)
         .with_language(cbindgen::Language::C)
+        .with_header(
+            "/* 以 `char *` 返回的字符串由 Rust 分配，必须使用 `free_cstring` 释放，不能使用 `free()`；\n \
+             * 以 `const char *` 返回的字符串是借用的，不能释放 */",
+        )
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("include/arg.h");
@@ -0,0 +1,14 @@
+//! 向 `parse_expr` 输入任意字节，检查解析器不会崩溃或栈溢出
+//!
+//! 运行：`cargo +nightly fuzz run fuzz_parse_expr`
+
+#![no_main]
+
+use arg::lexer::{Span, parse_expr};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        _ = parse_expr(Span::new(input));
+    }
+});
@@ -0,0 +1,100 @@
+//! # 配置文件
+//!
+//! 从 `pick-frame.toml` 读取常用选项的默认值，命令行中指定的选项优先。支持以下字段：
+//! - `format`：文件名格式，同 `--format`
+//! - `thread-count`：解码线程数，可以是数字或 `"auto"`，同 `--thread-count`
+//! - `from`、`to`：默认的起止时间，同 `--from`、`--to`
+//!
+//! 例如：
+//! ```toml
+//! format = "shot-{frame_index:05}.png"
+//! thread-count = 4
+//! to = "end - 10s"
+//! ```
+
+use serde::Deserialize;
+use std::path::Path;
+
+/// 未指定 `--config` 时在当前目录中查找的配置文件名
+pub const CONFIG_FILE_NAME: &str = "pick-frame.toml";
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+/// 配置文件中的选项，未填写的字段为 `None`
+pub struct Config {
+    /// 文件名格式
+    pub format: Option<String>,
+    /// 解码线程数
+    pub thread_count: Option<ThreadCountValue>,
+    /// 默认的起始时间
+    pub from: Option<String>,
+    /// 默认的结束时间
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+/// 配置文件中的线程数，可以写成数字或字符串
+pub enum ThreadCountValue {
+    /// 数字，例如 `4`
+    Count(u16),
+    /// 字符串，例如 `"auto"` 或 `"4"`
+    Text(String),
+}
+
+impl Config {
+    /// 解析TOML格式的配置
+    ///
+    /// # 参数
+    /// * `text` - 配置文件的内容
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|err| err.message().to_string())
+    }
+
+    /// 读取配置文件
+    ///
+    /// 指定了 `path` 时文件必须存在，否则在当前目录中查找 [`CONFIG_FILE_NAME`]，不存在时使用空配置
+    ///
+    /// # 参数
+    /// * `path` - `--config` 指定的路径
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => Path::new(path),
+            None if Path::new(CONFIG_FILE_NAME).is_file() => Path::new(CONFIG_FILE_NAME),
+            None => return Ok(Self::default()),
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("cannot read config `{}`: {err}", path.display()))?;
+        Self::from_toml(&text).map_err(|err| format!("invalid config `{}`: {err}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            "format = \"shot-%d.png\"\nthread-count = 4\nfrom = \"10s\"\nto = \"end - 1s\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                format: Some("shot-%d.png".to_string()),
+                thread_count: Some(ThreadCountValue::Count(4)),
+                from: Some("10s".to_string()),
+                to: Some("end - 1s".to_string()),
+            }
+        );
+        assert_eq!(
+            Config::from_toml("thread-count = \"auto\"")
+                .unwrap()
+                .thread_count,
+            Some(ThreadCountValue::Text("auto".to_string()))
+        );
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+        assert!(Config::from_toml("thread_count = 4").is_err());
+    }
+}
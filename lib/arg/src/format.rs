@@ -0,0 +1,316 @@
+//! # 文件名格式
+//!
+//! 校验并渲染 `--format` 参数。支持以下占位符：
+//! - 帧序号：`%d`、`%05d`、`{}`、`{:05}`、`{frame_index}`、`{frame_index:05}`
+//! - 以毫秒为单位的时间戳：`{timestamp_ms}`
+//! - 以秒为单位的时间戳：`{seconds}`
+//! - 帧率：`{fps}`
+//!
+//! 所有 `{}` 风格的占位符都可以通过 `:05` 这样的后缀指定宽度，以 `0` 开头时用 `0` 填充。
+//! 帧序号必须恰好出现一次，文件名中不能包含路径分隔符，
+//! 且扩展名必须是已知的图片格式。
+
+use std::fmt::{Display, Formatter};
+
+/// 支持的图片扩展名（不区分大小写）
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "bmp", "webp"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 文件名格式错误
+pub enum FormatError {
+    /// 缺少序号占位符
+    MissingSequenceNumber,
+    /// 序号占位符出现了多次
+    MultipleSequenceNumbers,
+    /// 包含路径分隔符
+    ContainsPathSeparator,
+    /// 未知的扩展名
+    UnknownExtension(String),
+    /// 无法识别的占位符
+    InvalidPlaceholder(String),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingSequenceNumber => {
+                f.write_str("missing sequence number, expected `%d` or `{}`")
+            }
+            Self::MultipleSequenceNumbers => {
+                f.write_str("sequence number must appear exactly once")
+            }
+            Self::ContainsPathSeparator => f.write_str("filename must not contain path separators"),
+            Self::UnknownExtension(ext) => write!(
+                f,
+                "unknown image extension `{ext}`, expected one of: {}",
+                IMAGE_EXTENSIONS.join(", ")
+            ),
+            Self::InvalidPlaceholder(placeholder) => {
+                write!(f, "invalid placeholder `{placeholder}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 占位符对应的字段
+enum Field {
+    /// 帧序号
+    FrameIndex,
+    /// 以毫秒为单位的时间戳
+    TimestampMs,
+    /// 以秒为单位的时间戳
+    Seconds,
+    /// 帧率
+    Fps,
+}
+
+impl Field {
+    /// 根据 `{}` 中的名称查找字段，空名称表示帧序号
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" | "frame_index" => Some(Self::FrameIndex),
+            "timestamp_ms" => Some(Self::TimestampMs),
+            "seconds" => Some(Self::Seconds),
+            "fps" => Some(Self::Fps),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 格式字符串中的一个片段
+enum Segment<'a> {
+    /// 原样输出的文本
+    Literal(&'a str),
+    /// 占位符，`width` 为宽度描述，例如 `05`
+    Field { field: Field, width: &'a str },
+}
+
+/// 判断宽度描述是否合法，只允许由数字组成
+fn is_width(spec: &str) -> bool {
+    spec.chars().all(|c| c.is_ascii_digit())
+}
+
+/// 将格式字符串拆分为片段
+///
+/// # 参数
+/// * `s` - 格式字符串
+///
+/// # 返回值
+/// 成功时返回片段列表，遇到无法识别的占位符时返回错误
+fn parse_segments(s: &str) -> Result<Vec<Segment<'_>>, FormatError> {
+    let mut segments = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '%' | '{' | '}' if rest[1..].starts_with(c) => {
+                segments.push(Segment::Literal(&rest[..1]));
+                rest = &rest[2..];
+            }
+            '%' => {
+                let invalid = || FormatError::InvalidPlaceholder(rest.chars().take(2).collect());
+                let end = rest.find('d').ok_or_else(invalid)?;
+                let width = &rest[1..end];
+                if !is_width(width) {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Field {
+                    field: Field::FrameIndex,
+                    width,
+                });
+                rest = &rest[end + 1..];
+            }
+            '{' => {
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| FormatError::InvalidPlaceholder(rest.to_string()))?;
+                let (name, width) = rest[1..end].split_once(':').unwrap_or((&rest[1..end], ""));
+                let field = Field::from_name(name)
+                    .filter(|_| is_width(width))
+                    .ok_or_else(|| FormatError::InvalidPlaceholder(rest[..=end].to_string()))?;
+                segments.push(Segment::Field { field, width });
+                rest = &rest[end + 1..];
+            }
+            '}' => return Err(FormatError::InvalidPlaceholder("}".to_string())),
+            _ => {
+                let end = rest.find(['%', '{', '}']).unwrap_or(rest.len());
+                segments.push(Segment::Literal(&rest[..end]));
+                rest = &rest[end..];
+            }
+        }
+    }
+    Ok(segments)
+}
+
+/// 将数值格式化为尽量短的小数，最多保留三位小数
+fn format_decimal(value: f64) -> String {
+    let text = format!("{value:.3}");
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// 校验 `--format` 传入的文件名格式
+///
+/// # 参数
+/// * `s` - 文件名格式
+///
+/// # 返回值
+/// 格式合法时返回 `Ok(())`
+pub fn validate_format_string(s: &str) -> Result<(), FormatError> {
+    if s.contains(['/', '\\']) {
+        return Err(FormatError::ContainsPathSeparator);
+    }
+    let count = parse_segments(s)?
+        .iter()
+        .filter(|segment| {
+            matches!(
+                segment,
+                Segment::Field {
+                    field: Field::FrameIndex,
+                    ..
+                }
+            )
+        })
+        .count();
+    match count {
+        0 => return Err(FormatError::MissingSequenceNumber),
+        1 => {}
+        _ => return Err(FormatError::MultipleSequenceNumbers),
+    }
+    let ext = s.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+    if !IMAGE_EXTENSIONS
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(ext))
+    {
+        return Err(FormatError::UnknownExtension(ext.to_string()));
+    }
+    Ok(())
+}
+
+/// 根据文件名格式生成文件名
+///
+/// 格式无法解析时原样返回，调用前应先通过 [`validate_format_string`] 校验
+///
+/// # 参数
+/// * `fmt` - 文件名格式
+/// * `frame_index` - 帧序号
+/// * `timestamp_ms` - 以毫秒为单位的时间戳
+/// * `fps` - 帧率
+///
+/// # 返回值
+/// 生成的文件名
+pub fn format_filename(fmt: &str, frame_index: u64, timestamp_ms: u64, fps: f64) -> String {
+    let Ok(segments) = parse_segments(fmt) else {
+        return fmt.to_string();
+    };
+    let mut output = String::with_capacity(fmt.len());
+    for segment in segments {
+        let (field, width) = match segment {
+            Segment::Literal(text) => {
+                output.push_str(text);
+                continue;
+            }
+            Segment::Field { field, width } => (field, width),
+        };
+        let value = match field {
+            Field::FrameIndex => frame_index.to_string(),
+            Field::TimestampMs => timestamp_ms.to_string(),
+            Field::Seconds => format_decimal(timestamp_ms as f64 / 1000f64),
+            Field::Fps => format_decimal(fps),
+        };
+        let len = width.parse::<usize>().unwrap_or_default();
+        if width.starts_with('0') {
+            output.push_str(&format!("{value:0>len$}"));
+        } else {
+            output.push_str(&format!("{value:>len$}"));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_format_string() {
+        assert_eq!(validate_format_string("frame-%d.jpg"), Ok(()));
+        assert_eq!(validate_format_string("frame-%05d.PNG"), Ok(()));
+        assert_eq!(validate_format_string("frame-{:05}.jpeg"), Ok(()));
+        assert_eq!(
+            validate_format_string("{frame_index}-{timestamp_ms}-{fps}.jpg"),
+            Ok(())
+        );
+        assert_eq!(
+            validate_format_string("frame.jpg"),
+            Err(FormatError::MissingSequenceNumber)
+        );
+        assert_eq!(
+            validate_format_string("{timestamp_ms}.jpg"),
+            Err(FormatError::MissingSequenceNumber)
+        );
+        // `%%` 是字面量的百分号，不算作序号
+        assert_eq!(
+            validate_format_string("frame-100%%d.jpg"),
+            Err(FormatError::MissingSequenceNumber)
+        );
+        assert_eq!(validate_format_string("frame-100%%-%d.jpg"), Ok(()));
+        assert_eq!(validate_format_string("{{%d}}.jpg"), Ok(()));
+        assert_eq!(
+            validate_format_string("%d-{}.jpg"),
+            Err(FormatError::MultipleSequenceNumbers)
+        );
+        assert_eq!(
+            validate_format_string("out/frame-%d.jpg"),
+            Err(FormatError::ContainsPathSeparator)
+        );
+        assert_eq!(
+            validate_format_string("frame-%d.mp4"),
+            Err(FormatError::UnknownExtension("mp4".to_string()))
+        );
+        assert_eq!(
+            validate_format_string("frame-%s.jpg"),
+            Err(FormatError::InvalidPlaceholder("%s".to_string()))
+        );
+        assert_eq!(
+            validate_format_string("frame-%d-{width}.jpg"),
+            Err(FormatError::InvalidPlaceholder("{width}".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_format_filename() {
+        assert_eq!(format_filename("frame-%d.jpg", 7, 0, 30.0), "frame-7.jpg");
+        assert_eq!(
+            format_filename("frame-%05d.jpg", 7, 0, 30.0),
+            "frame-00007.jpg"
+        );
+        assert_eq!(format_filename("frame-{}.jpg", 7, 0, 30.0), "frame-7.jpg");
+        assert_eq!(
+            format_filename("frame-{:05}.jpg", 7, 0, 30.0),
+            "frame-00007.jpg"
+        );
+        assert_eq!(
+            format_filename("frame-{frame_index:05}.jpg", 7, 0, 30.0),
+            "frame-00007.jpg"
+        );
+        assert_eq!(
+            format_filename("{frame_index:3}.jpg", 7, 0, 30.0),
+            "  7.jpg"
+        );
+        assert_eq!(
+            format_filename("{timestamp_ms:08}.jpg", 0, 1500, 30.0),
+            "00001500.jpg"
+        );
+        assert_eq!(format_filename("{seconds}.jpg", 0, 1500, 30.0), "1.5.jpg");
+        assert_eq!(format_filename("{seconds}.jpg", 0, 2000, 30.0), "2.jpg");
+        assert_eq!(format_filename("{fps}.jpg", 0, 0, 30.0), "30.jpg");
+        assert_eq!(format_filename("{fps}.jpg", 0, 0, 29.97), "29.97.jpg");
+        assert_eq!(
+            format_filename("{{100%%}}-{}.jpg", 1, 0, 30.0),
+            "{100%}-1.jpg"
+        );
+    }
+}
@@ -2,41 +2,79 @@
 //!
 //! 这个模块提供了一个用于解析特定领域语言（DSL）的词法分析器。
 //! DSL语言支持以下元素：
-//! - 关键字（end, from, to）
+//! - 关键字（end, from, to），其中 `dur`、`duration` 是 `end` 的别名
 //! - 帧索引（如 100f）
-//! - 时间戳（如 100s, 1:2:3, 100ms）
+//! - 时间戳（如 100s, 1:2:3, 100ms, 100us, 100ns, PT1M30S）
 //! - 操作符（+, -）
+//! - 函数（min, max, clamp）
+//! - 注释（以 `#` 或 `//` 开始直到行尾）
 //!
 //! 该分析器使用nom库进行解析，并包含表达式优化和验证功能。
 
 use nom::IResult;
+use nom::Input;
 use nom::Parser;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
-use nom::character::complete::space1;
+use nom::bytes::complete::take_while;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::multispace1;
 use nom::character::complete::u64;
+use nom::combinator::recognize;
 use nom::multi::many0;
+use nom::sequence::pair;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Debug;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::str::FromStr;
 use std::time::Duration;
 
 /// 用于跟踪输入字符串位置的span类型，包含行号和列号信息
 pub type Span<'a> = nom_locate::LocatedSpan<&'a str>;
 
+/// 解析单个标记的解析器类型
+type TokenParser<T> = Box<dyn Fn(Span) -> IResult<Span, T>>;
+
+/// 将nom错误转换为自定义错误的函数类型
+type ErrMapper = Box<
+    dyn Fn(
+        nom::Err<nom::error::Error<Span>>,
+    ) -> nom::Err<error::ParseError<nom::error::Error<Span>>>,
+>;
+
 trait Token {
+    /// 返回标记的标准写法
     fn token(&self) -> &'static str;
+
+    /// 返回标记的其他写法，默认没有别名
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// 返回标记的所有写法，标准写法在前
+    fn spellings(&self) -> Vec<&'static str> {
+        std::iter::once(self.token())
+            .chain(self.aliases().iter().copied())
+            .collect()
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// DSL中的关键字枚举
 ///
 /// 支持的关键字包括:
-/// - `End`: 表示结束
+/// - `End`: 表示结束，也可以写作 `dur` 或 `duration`
+/// - `Start`: 表示视频的开始，也可以写作 `begin`
 /// - `From`: 表示起始
 /// - `To`: 表示目标
 pub enum DSLKeywords {
     /// 结束关键字
     End,
+    /// 开始关键字
+    Start,
     /// 起始关键字
     From,
     /// 目标关键字
@@ -48,30 +86,122 @@ impl Token for DSLKeywords {
     fn token(&self) -> &'static str {
         match self {
             Self::End => "end",
+            Self::Start => "start",
             Self::From => "from",
             Self::To => "to",
         }
     }
+
+    /// 返回关键字的别名
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Self::End => &["dur", "duration"],
+            Self::Start => &["begin"],
+            _ => &[],
+        }
+    }
+}
+
+impl DSLKeywords {
+    /// 所有关键字
+    pub const ALL: [Self; 4] = [Self::End, Self::Start, Self::From, Self::To];
+
+    /// 返回所有关键字
+    pub fn all() -> &'static [Self] {
+        &Self::ALL
+    }
+
+    /// 返回所有关键字的字符串表示（不包括别名），顺序与 [`DSLKeywords::all`] 相同
+    pub fn all_tokens() -> &'static [&'static str] {
+        static TOKENS: std::sync::LazyLock<Vec<&'static str>> =
+            std::sync::LazyLock::new(|| DSLKeywords::all().iter().map(Token::token).collect());
+        &TOKENS
+    }
+}
+
+/// 获取所有关键字的所有写法，包括别名
+pub fn keyword_spellings() -> impl Iterator<Item = &'static str> {
+    DSLKeywords::all()
+        .iter()
+        .flat_map(|keyword| keyword.spellings())
+}
+
+/// 在候选词中查找与 `word` 最接近的一个，生成 "did you mean" 提示
+///
+/// 与首字母相同的候选词距离减一，距离超过2的候选词会被忽略，
+/// 若最接近的候选词不唯一则不给出提示
+pub fn did_you_mean<'a>(
+    word: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let mut suggests = candidates
+        .into_iter()
+        .map(|candidate| {
+            (
+                candidate,
+                strsim::damerau_levenshtein(word, candidate).saturating_sub(
+                    if candidate.chars().next() == word.chars().next() {
+                        1
+                    } else {
+                        0
+                    },
+                ),
+            )
+        })
+        .filter(|(_, dist)| *dist <= 2)
+        .collect::<Vec<_>>();
+    suggests.sort_by_key(|(_, dist)| *dist);
+    match suggests.as_slice() {
+        [(word, _)] => Some(format!("did you mean `{word}`?")),
+        [(word, dist1), (_, dist2), ..] if dist1 < dist2 => Some(format!("did you mean `{word}`?")),
+        _ => None,
+    }
 }
 
 /// 创建一个解析指定标记的解析器函数
 ///
+/// 标记的别名也会被接受，较长的写法优先匹配
+///
 /// # 参数
 /// * `token` - 需要解析的标记
 ///
 /// # 返回值
 /// 返回一个解析函数，该函数尝试匹配输入中的标记
-fn _parse<T>(token: T) -> Box<dyn Fn(Span) -> IResult<Span, T>>
+fn _parse<T>(token: T) -> TokenParser<T>
 where
     T: Token + Copy + 'static,
 {
     Box::new(move |input: Span| {
+        let mut spellings = token.spellings();
+        spellings.sort_by_key(|spelling| std::cmp::Reverse(spelling.len()));
+        for spelling in spellings {
+            if let Ok((input, _)) = tag::<_, _, nom::error::Error<Span>>(spelling).parse(input) {
+                return Ok((input, token));
+            }
+        }
         let (input, _) = tag(token.token())(input)?;
         Ok((input, token))
     })
 }
 
+/// 将时长序列化为整数纳秒，避免浮点秒数在不同实现间产生误差
+#[cfg(feature = "serde")]
+mod duration_nanos {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(dur: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let nanos = u64::try_from(dur.as_nanos()).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_u64(nanos)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_nanos)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// DSL中支持的数据类型枚举
 ///
 /// 包括帧索引、时间戳和关键字三种基本类型
@@ -79,9 +209,91 @@ pub enum DSLType {
     /// 帧索引，以f结尾，例如 100f
     FrameIndex(u64),
     /// 时间戳，可以是秒、毫秒或时:分:秒格式
-    Timestamp(Duration),
+    Timestamp(#[cfg_attr(feature = "serde", serde(with = "duration_nanos"))] Duration),
+    /// 丢帧时间码，例如 `00:10:00;02`，求值时按视频的帧率换算
+    Timecode(crate::Timecode),
     /// 关键字
     Keyword(DSLKeywords),
+    /// 变量，由 `--let` 定义，在求值时解析
+    Var(String),
+    /// 函数调用，例如 `min(end, from + 30s)`
+    Call {
+        /// 函数名
+        name: String,
+        /// 参数列表，每个参数都是一个子表达式
+        args: Vec<Expr>,
+    },
+}
+
+impl Display for DSLType {
+    /// 将类型格式化为可以重新解析的字符串，例如 `100f`、`2.5s`、`from`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FrameIndex(index) => write!(f, "{index}f"),
+            Self::Timestamp(dur) => {
+                let nanos = dur.subsec_nanos();
+                if nanos == 0 {
+                    write!(f, "{}s", dur.as_secs())
+                } else {
+                    let decimal = format!("{nanos:09}");
+                    write!(f, "{}.{}s", dur.as_secs(), decimal.trim_end_matches('0'))
+                }
+            }
+            Self::Timecode(timecode) => write!(f, "{timecode}"),
+            Self::Keyword(keyword) => f.write_str(keyword.token()),
+            Self::Var(name) => f.write_str(name),
+            Self::Call { name, args } => {
+                write!(f, "{name}(")?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl FromStr for DSLType {
+    type Err = String;
+
+    /// 解析单个项，例如 `100f`、`5s`、`end`，前后可以有空白，但不能包含多个项
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_item_str(s)?.ok_or_else(|| "expected an item, found nothing".to_string())
+    }
+}
+
+/// 检查解析后只剩下空白和注释
+fn expect_end(rest: Span, what: &str) -> Result<(), String> {
+    let rest = skip_ignored(rest).map_or(rest, |(rest, _)| rest);
+    if !rest.is_empty() {
+        return Err(format!("unexpected `{}` after the {what}", rest.fragment()));
+    }
+    Ok(())
+}
+
+/// 与 [`parse_item`] 相同，但直接接受字符串，不需要依赖 `nom_locate`
+///
+/// # 返回值
+/// 解析出的项，输入为空时为 `None`，解析失败或项后面还有其他内容时返回错误信息
+pub fn parse_item_str(input: &str) -> Result<Option<DSLType>, String> {
+    let (rest, item) =
+        parse_item(input.into()).map_err(|err| error::ExprError::from(err).to_string())?;
+    expect_end(rest, "item")?;
+    Ok(item.map(|item| item.content))
+}
+
+/// 与 [`parse_expr`] 相同，但直接接受字符串，不需要依赖 `nom_locate`
+///
+/// # 返回值
+/// 解析出的表达式（未经优化），解析失败或表达式后面还有其他内容时返回错误信息
+pub fn parse_expr_str(input: &str) -> Result<Expr, String> {
+    let (rest, expr) =
+        parse_expr(input.into()).map_err(|err| error::ExprError::from(err).to_string())?;
+    expect_end(rest, "expression")?;
+    Ok(expr)
 }
 
 /// 解析DSL中的关键字
@@ -94,6 +306,7 @@ pub enum DSLType {
 pub fn parse_keyword(input: Span) -> IResult<Span, DSLType> {
     let (input, keyword) = alt((
         _parse(DSLKeywords::End),
+        _parse(DSLKeywords::Start),
         _parse(DSLKeywords::From),
         _parse(DSLKeywords::To),
     ))
@@ -101,6 +314,45 @@ pub fn parse_keyword(input: Span) -> IResult<Span, DSLType> {
     Ok((input, DSLType::Keyword(keyword)))
 }
 
+/// 解析标识符
+///
+/// 标识符以字母或下划线开头，后跟字母、数字或下划线。
+/// 若标识符恰好是关键字则解析为关键字，否则解析为变量
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的关键字或变量
+pub fn parse_identifier(input: Span) -> IResult<Span, DSLType> {
+    let (input, ident) = recognize(pair(
+        take_while1(|c: char| c.is_ascii_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+    ))
+    .parse(input)?;
+    let ident = *ident.fragment();
+    let keyword = DSLKeywords::ALL
+        .into_iter()
+        .find(|keyword| keyword.spellings().contains(&ident));
+    Ok((
+        input,
+        keyword
+            .map(DSLType::Keyword)
+            .unwrap_or_else(|| DSLType::Var(ident.to_string())),
+    ))
+}
+
+/// 判断字符串是否可以作为变量名
+///
+/// # 参数
+/// * `name` - 需要判断的名称
+///
+/// # 返回值
+/// 是合法的标识符且不是关键字时返回true
+pub fn is_var_name(name: &str) -> bool {
+    matches!(parse_identifier(name.into()), Ok((rest, DSLType::Var(..))) if rest.is_empty())
+}
+
 /// 解析帧索引
 ///
 /// 帧索引格式为数字后跟字母f，例如 100f
@@ -148,18 +400,21 @@ fn parse_f64(input: Span) -> IResult<Span, f64> {
 /// * `input` - 输入的span
 ///
 /// # 返回值
-/// 返回解析结果，包含剩余输入和解析出的时间戳
+/// 返回解析结果，包含剩余输入和解析出的时间戳。
+/// 秒数超出 `Duration` 的范围时返回 `MapRes` 的 `Failure`，错误位置为时间戳之后
 pub fn parse_timestamp1(input: Span) -> IResult<Span, DSLType> {
     let (input, value) = parse_f64(input)?;
-    Ok((
-        tag("s")(input)?.0,
-        DSLType::Timestamp(Duration::from_secs_f64(value)),
-    ))
+    let (input, _) = tag("s")(input)?;
+    let dur = Duration::try_from_secs_f64(value).map_err(|_| {
+        nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::MapRes))
+    })?;
+    Ok((input, DSLType::Timestamp(dur)))
 }
 
 /// 解析时:分:秒格式的时间戳
 ///
-/// 支持格式如: 1:2, 1:2:3, 1:2.5 等
+/// 支持格式如: 1:2, 1:2:3, 1:2.5 等，小数部分最多3位，精确到毫秒。
+/// 小数点也可以写成 `,`，例如字幕中的 `00:01:23,500`
 ///
 /// # 参数
 /// * `input` - 输入的span
@@ -167,12 +422,50 @@ pub fn parse_timestamp1(input: Span) -> IResult<Span, DSLType> {
 /// # 返回值
 /// 返回解析结果，包含剩余输入和解析出的时间戳
 pub fn parse_timestamp2(input: Span) -> IResult<Span, DSLType> {
+    parse_timestamp2_with(input, true)
+}
+
+/// 解析 `hh:mm:ss;ff` 格式的丢帧时间码，例如 `00:10:00;02`
+///
+/// 只检查格式，字段是否在范围内在求值时按视频的帧率检查
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的时间码
+pub fn parse_timecode(input: Span) -> IResult<Span, DSLType> {
+    let (input, (hours, _, minutes, _, seconds, _, frames)) =
+        (u64, tag(":"), u64, tag(":"), u64, tag(";"), u64).parse(input)?;
+    Ok((
+        input,
+        DSLType::Timecode(crate::Timecode {
+            hours,
+            minutes,
+            seconds,
+            frames,
+        }),
+    ))
+}
+
+/// 解析时:分:秒格式的时间戳，`comma` 为false时 `,` 不作为小数点
+///
+/// 函数参数中的 `,` 是参数的分隔符，因此解析函数参数时不把 `,` 当作小数点
+fn parse_timestamp2_with(input: Span, comma: bool) -> IResult<Span, DSLType> {
+    // 记录每个字段的起始位置，累加溢出时指向对应的字段
+    let start = input;
     let (mut input, value) = u64(input)?;
-    let mut times = vec![value];
-    let mut ms: Option<u64> = None;
+    let mut times = vec![(value, start)];
+    let mut nanos = 0u32;
     let mut i = 0;
     loop {
         if i > 2 {
+            // 继续读取剩余的字段，使错误覆盖整个时间戳
+            let (input, _) = many0(pair(
+                alt((tag(":"), tag("."))),
+                nom::character::complete::digit1,
+            ))
+            .parse(input)?;
             return Err(nom::Err::Failure(nom::error::Error::new(
                 input,
                 nom::error::ErrorKind::Count,
@@ -181,31 +474,34 @@ pub fn parse_timestamp2(input: Span) -> IResult<Span, DSLType> {
         match tag::<&str, Span, nom::error::Error<Span>>(":")(input) {
             Ok(res) => {
                 input = res.0;
+                let start = input;
                 let res = u64(input)?;
                 input = res.0;
-                times.push(res.1);
+                times.push((res.1, start));
                 i += 1;
             }
             Err(..) => {
-                let Ok(res) = tag::<&str, Span, nom::error::Error<Span>>(".")(input) else {
+                let separator = if comma && input.starts_with(',') {
+                    ","
+                } else {
+                    "."
+                };
+                let Ok(res) = tag::<&str, Span, nom::error::Error<Span>>(separator)(input) else {
                     break;
                 };
-                let res = nom::character::complete::digit1(res.0)?;
-                input = res.0;
-                println!(
-                    "{}{}",
-                    res.1,
-                    "0".repeat(3usize.saturating_sub(res.1.len()))
-                );
-                ms = format!(
-                    "{}{}",
-                    res.1,
-                    "0".repeat(3usize.saturating_sub(res.1.len()))
-                )
-                .parse::<u64>()
-                .map(Some)
-                .unwrap_or_default();
-                println!("ms: {ms:?}");
+                let (rest, digits) = nom::character::complete::digit1(res.0)?;
+                // 只有 `时:分:秒` 格式限制为毫秒，没有 `:` 时交给其他解析器按秒处理
+                let value = crate::fraction_to_nanos(digits.fragment()).filter(|_| {
+                    times.len() < 2 || digits.len() <= crate::TIMESTAMP_FRACTION_DIGITS
+                });
+                let Some(value) = value else {
+                    return Err(nom::Err::Failure(nom::error::Error::new(
+                        rest,
+                        nom::error::ErrorKind::TooLarge,
+                    )));
+                };
+                input = rest;
+                nanos = value;
                 break;
             }
         }
@@ -217,12 +513,16 @@ pub fn parse_timestamp2(input: Span) -> IResult<Span, DSLType> {
             nom::error::ErrorKind::Fail,
         )));
     }
-    let secs = times.iter().enumerate().fold(0u64, |acc, (index, value)| {
-        acc + *value * 60u64.pow((len - index - 1) as u32)
-    });
-    let sec = Duration::from_secs(secs);
-    let time = sec + ms.map(Duration::from_millis).unwrap_or_default();
-    Ok((input, DSLType::Timestamp(time)))
+    let mut secs = 0u64;
+    for (index, (value, start)) in times.into_iter().enumerate() {
+        secs = value
+            .checked_mul(60u64.pow((len - index - 1) as u32))
+            .and_then(|value| secs.checked_add(value))
+            .ok_or_else(|| {
+                nom::Err::Failure(nom::error::Error::new(start, nom::error::ErrorKind::MapRes))
+            })?;
+    }
+    Ok((input, DSLType::Timestamp(Duration::new(secs, nanos))))
 }
 
 /// 解析毫秒级时间戳
@@ -242,7 +542,97 @@ pub fn parse_timestamp3(input: Span) -> IResult<Span, DSLType> {
     ))
 }
 
-#[derive(Debug)]
+/// 解析纳秒级时间戳
+///
+/// 格式为数字后跟ns，例如 500000000ns
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的时间戳
+pub fn parse_timestamp4(input: Span) -> IResult<Span, DSLType> {
+    let (input, value) = u64(input)?;
+    Ok((
+        tag("ns")(input)?.0,
+        DSLType::Timestamp(Duration::from_nanos(value)),
+    ))
+}
+
+/// 解析微秒级时间戳
+///
+/// 格式为数字后跟us，例如 500000us，也可以写作 `µs` 或 `μs`
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的时间戳
+pub fn parse_timestamp5(input: Span) -> IResult<Span, DSLType> {
+    let (input, value) = u64(input)?;
+    Ok((
+        alt((tag("us"), tag("\u{b5}s"), tag("\u{3bc}s")))
+            .parse(input)?
+            .0,
+        DSLType::Timestamp(Duration::from_micros(value)),
+    ))
+}
+
+/// 解析ISO 8601格式的时长
+///
+/// 格式为 `PT` 后依次跟可选的 `#H`、`#M`、`#S`，至少一项，秒数可以带小数，
+/// 例如 `PT90S`、`PT1M30S`、`PT1H2M3.5S`
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的时间戳
+pub fn parse_timestamp_iso(input: Span) -> IResult<Span, DSLType> {
+    let error = |input, code| nom::Err::Error(nom::error::Error::new(input, code));
+    let (mut input, _) = tag("PT")(input)?;
+    let mut secs = 0u64;
+    let mut nanos = 0u32;
+    let mut found = false;
+    for (unit, scale) in [("H", 3600u64), ("M", 60), ("S", 1)] {
+        let Ok((rest, value)) = u64::<Span, nom::error::Error<Span>>(input) else {
+            break;
+        };
+        let (rest, fraction) = if unit == "S" && rest.starts_with('.') {
+            let (rest, digits) = nom::character::complete::digit1(rest.take_from(1))?;
+            let fraction = crate::fraction_to_nanos(digits.fragment()).ok_or_else(|| {
+                nom::Err::Failure(nom::error::Error::new(
+                    rest,
+                    nom::error::ErrorKind::TooLarge,
+                ))
+            })?;
+            (rest, fraction)
+        } else {
+            (rest, 0)
+        };
+        let Ok((rest, _)) = tag::<&str, Span, nom::error::Error<Span>>(unit)(rest) else {
+            continue;
+        };
+        // 溢出时已经确定是时长，返回 `Failure` 避免被当作变量名
+        secs = value
+            .checked_mul(scale)
+            .and_then(|value| secs.checked_add(value))
+            .ok_or_else(|| {
+                nom::Err::Failure(nom::error::Error::new(rest, nom::error::ErrorKind::MapRes))
+            })?;
+        nanos = fraction;
+        input = rest;
+        found = true;
+    }
+    // `PT` 后没有任何一项，或者后面紧跟字母数字时不是时长，例如变量 `PTS`
+    if !found || input.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return Err(error(input, nom::error::ErrorKind::Verify));
+    }
+    Ok((input, DSLType::Timestamp(Duration::new(secs, nanos))))
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(unused)]
 /// 表示DSL中的一个项目，包含内容、偏移量和长度信息
 ///
@@ -278,6 +668,24 @@ impl<T: Debug> DSLItem<T> {
     pub fn set(&mut self, content: T) {
         self.content = content;
     }
+
+    /// 获取项目在源字符串中的位置
+    pub fn span(&self) -> SourceSpan {
+        SourceSpan {
+            offset: self.offset,
+            length: self.length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// 项目在源字符串中的位置，用于在求值出错时指出对应的项
+pub struct SourceSpan {
+    /// 在源字符串中的偏移量
+    pub offset: usize,
+    /// 长度
+    pub length: usize,
 }
 
 /// 将nom错误转换为自定义解析错误
@@ -300,12 +708,14 @@ fn map_err(
             offset,
             length: err.input.location_offset() - offset,
             source: Box::new(err),
+            line: None,
         }),
         nom::Err::Failure(err) => nom::Err::Failure(error::ParseError {
             kind,
             offset,
             length: err.input.location_offset() - offset,
             source: Box::new(err),
+            line: None,
         }),
         nom::Err::Incomplete(need) => nom::Err::Incomplete(need),
     }
@@ -318,13 +728,7 @@ fn map_err(
 ///
 /// # 返回值
 /// 返回一个错误转换函数
-fn map_err_build(
-    offset: usize,
-) -> Box<
-    dyn Fn(
-        nom::Err<nom::error::Error<Span>>,
-    ) -> nom::Err<error::ParseError<nom::error::Error<Span>>>,
-> {
+fn map_err_build(offset: usize) -> ErrMapper {
     Box::new(move |err| map_err(err, offset, error::ParseErrorKind::Nom))
 }
 
@@ -336,20 +740,127 @@ fn map_err_build(
 ///
 /// # 返回值
 /// 返回一个错误转换函数
-fn map_err_build2(
-    offset: usize,
-    kind: error::ParseErrorKind,
-) -> Box<
-    dyn Fn(
-        nom::Err<nom::error::Error<Span>>,
-    ) -> nom::Err<error::ParseError<nom::error::Error<Span>>>,
-> {
+fn map_err_build2(offset: usize, kind: error::ParseErrorKind) -> ErrMapper {
     Box::new(move |err| map_err(err, offset, kind))
 }
 
+/// 跳过空白（包括换行）和注释
+///
+/// 注释以 `#` 或 `//` 开始，一直持续到行尾，因此多行表达式中的每一行都可以带注释
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回跳过空白和注释后的剩余输入
+fn skip_ignored(input: Span) -> IResult<Span, ()> {
+    let (input, _) = many0(alt((multispace1, parse_comment))).parse(input)?;
+    Ok((input, ()))
+}
+
+/// 解析一条注释，注释以 `#` 或 `//` 开始，到行尾结束（不包括换行）
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和整条注释
+fn parse_comment(input: Span) -> IResult<Span, Span> {
+    recognize(pair(alt((tag("#"), tag("//"))), take_while(|c| c != '\n'))).parse(input)
+}
+
+/// 支持的函数名
+pub const FUNCTIONS: [&str; 5] = ["min", "max", "clamp", "keyframe_before", "keyframe_after"];
+
+/// 获取函数允许的参数数量范围
+///
+/// # 参数
+/// * `name` - 函数名
+///
+/// # 返回值
+/// 参数数量的最小值和最大值
+pub fn function_arity(name: &str) -> (usize, usize) {
+    match name {
+        "clamp" | "keyframe_before" | "keyframe_after" => (1, 1),
+        _ => (2, usize::MAX),
+    }
+}
+
+/// 构造函数调用相关的解析错误
+///
+/// # 参数
+/// * `input` - 出错位置的输入
+/// * `offset` - 错误开始的偏移量
+/// * `code` - 错误代码，用于区分具体的错误
+fn call_error(
+    input: Span,
+    offset: usize,
+    code: nom::error::ErrorKind,
+) -> nom::Err<error::ParseError<nom::error::Error<Span>>> {
+    map_err(
+        nom::Err::Failure(nom::error::Error::new(input, code)),
+        offset,
+        error::ParseErrorKind::Function,
+    )
+}
+
+/// 解析函数调用的参数列表
+///
+/// 参数是以逗号分隔的子表达式，数量需要满足 [`function_arity`]
+///
+/// # 参数
+/// * `input` - 以 `(` 开头的输入
+/// * `name` - 函数名
+/// * `offset` - 函数名在源字符串中的偏移量
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的函数调用
+fn parse_call<'a>(
+    input: Span<'a>,
+    name: String,
+    offset: usize,
+    budget: &mut usize,
+) -> error::ParseExprResult<Span<'a>, DSLType> {
+    if !FUNCTIONS.contains(&name.as_str()) {
+        return Err(call_error(input, offset, nom::error::ErrorKind::Tag));
+    }
+    let mut input = input.take_from(1);
+    let mut args = vec![];
+    loop {
+        let (rest, _) = skip_ignored(input).map_err(map_err_build(input.location_offset()))?;
+        if rest.is_empty() || rest.starts_with([',', ')']) {
+            return Err(call_error(
+                rest,
+                rest.location_offset(),
+                nom::error::ErrorKind::Verify,
+            ));
+        }
+        let (rest, arg) = parse_terms(rest, true, budget)?;
+        args.push(arg);
+        let (rest, _) = skip_ignored(rest).map_err(map_err_build(rest.location_offset()))?;
+        if rest.starts_with(',') {
+            input = rest.take_from(1);
+        } else if rest.starts_with(')') {
+            input = rest.take_from(1);
+            break;
+        } else {
+            return Err(call_error(
+                rest,
+                rest.location_offset(),
+                nom::error::ErrorKind::Char,
+            ));
+        }
+    }
+    let (min, max) = function_arity(&name);
+    if args.len() < min || args.len() > max {
+        return Err(call_error(input, offset, nom::error::ErrorKind::ManyMN));
+    }
+    Ok((input, DSLType::Call { name, args }))
+}
+
 /// 解析单个DSL项
 ///
-/// 尝试解析各种类型的DSL项，包括关键字、帧索引和时间戳
+/// 尝试解析各种类型的DSL项，包括关键字、帧索引和时间戳。函数调用中的项数同样受 [`MAX_EXPR_ITEMS`] 限制
 ///
 /// # 参数
 /// * `input` - 输入的span
@@ -357,14 +868,44 @@ fn map_err_build2(
 /// # 返回值
 /// 返回解析结果，包含剩余输入和解析出的DSL项（如果存在）
 pub fn parse_item(input: Span) -> error::ParseExprResult<Span, Option<DSLItem<DSLType>>> {
-    let (input, _) = many0(space1)
-        .parse(input)
-        .map_err(map_err_build(input.location_offset()))?;
+    parse_item_within(input, false, &mut { MAX_EXPR_ITEMS })
+}
+
+/// 解析单个DSL项，每解析出一项（包括函数参数中的项）`budget` 减一，为0时返回错误
+///
+/// `nested` 为true时表示在函数参数中，此时 `,` 不作为时间戳的小数点
+fn parse_item_within<'a>(
+    input: Span<'a>,
+    nested: bool,
+    budget: &mut usize,
+) -> error::ParseExprResult<Span<'a>, Option<DSLItem<DSLType>>> {
+    let (input, _) = skip_ignored(input).map_err(map_err_build(input.location_offset()))?;
     if input.is_empty() {
         return Ok((input, None));
     }
     let offset = input.location_offset();
-    match parse_timestamp2(input) {
+    if *budget == 0 {
+        return Err(map_err(
+            nom::Err::Failure(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::TooLarge,
+            )),
+            offset,
+            error::ParseErrorKind::TooManyItems,
+        ));
+    }
+    *budget -= 1;
+    if let Ok((input, item)) = parse_timecode(input) {
+        return Ok((
+            input,
+            Some(DSLItem {
+                offset,
+                content: item,
+                length: input.location_offset() - offset,
+            }),
+        ));
+    }
+    match parse_timestamp2_with(input, !nested) {
         Ok((input, item)) => {
             return Ok((
                 input,
@@ -376,26 +917,79 @@ pub fn parse_item(input: Span) -> error::ParseExprResult<Span, Option<DSLItem<DS
             ));
         }
         Err(e) => match e {
-            nom::Err::Failure(ref err) if err.code == nom::error::ErrorKind::Count => {
+            // 字段过多或小数部分超过3位
+            nom::Err::Failure(ref err)
+                if matches!(
+                    err.code,
+                    nom::error::ErrorKind::Count | nom::error::ErrorKind::TooLarge
+                ) =>
+            {
                 return Err(map_err_build(input.location_offset())(e));
             }
+            // 错误位置为溢出的字段的开头，错误覆盖该字段的数字
+            nom::Err::Failure(err) if err.code == nom::error::ErrorKind::MapRes => {
+                let offset = err.input.location_offset();
+                let length = err
+                    .input
+                    .fragment()
+                    .bytes()
+                    .take_while(u8::is_ascii_digit)
+                    .count();
+                return Err(nom::Err::Failure(error::ParseError {
+                    kind: error::ParseErrorKind::Overflow,
+                    offset,
+                    length,
+                    source: Box::new(err),
+                    line: None,
+                }));
+            }
             _ => {}
         },
     }
 
-    let (input, item) =
-        match alt((parse_frame_index, parse_timestamp1, parse_timestamp3)).parse(input) {
-            Ok(res) => res,
-            Err(e) => match e {
-                nom::Err::Error(err) if err.code == nom::error::ErrorKind::Digit => {
-                    parse_keyword(input).map_err(map_err_build2(
-                        input.location_offset(),
-                        error::ParseErrorKind::Keywords,
-                    ))?
+    let (input, item) = match alt((
+        parse_frame_index,
+        parse_timestamp1,
+        parse_timestamp4,
+        parse_timestamp5,
+        parse_timestamp3,
+    ))
+    .parse(input)
+    {
+        Ok(res) => res,
+        Err(e) => match e {
+            // 秒数溢出，错误覆盖整个时间戳
+            nom::Err::Failure(ref err) if err.code == nom::error::ErrorKind::MapRes => {
+                return Err(map_err(e, offset, error::ParseErrorKind::Overflow));
+            }
+            nom::Err::Error(err) if err.code == nom::error::ErrorKind::Digit => {
+                match parse_timestamp_iso(input) {
+                    Ok(res) => res,
+                    Err(nom::Err::Failure(err)) if err.code == nom::error::ErrorKind::MapRes => {
+                        return Err(map_err(
+                            nom::Err::Failure(err),
+                            offset,
+                            error::ParseErrorKind::Overflow,
+                        ));
+                    }
+                    Err(e @ nom::Err::Failure(..)) => {
+                        return Err(map_err_build(input.location_offset())(e));
+                    }
+                    Err(..) => match parse_identifier(input) {
+                        Ok((rest, DSLType::Var(name))) if rest.starts_with('(') => {
+                            parse_call(rest, name, offset, budget)?
+                        }
+                        Ok(res) => res,
+                        Err(..) => parse_keyword(input).map_err(map_err_build2(
+                            input.location_offset(),
+                            error::ParseErrorKind::Keywords,
+                        ))?,
+                    },
                 }
-                _ => return Err(map_err_build(input.location_offset())(e)),
-            },
-        };
+            }
+            _ => return Err(map_err_build(input.location_offset())(e)),
+        },
+    };
     Ok((
         input,
         Some(DSLItem {
@@ -407,6 +1001,7 @@ pub fn parse_item(input: Span) -> error::ParseExprResult<Span, Option<DSLItem<DS
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// DSL中的操作符枚举
 ///
 /// 支持加法和减法两种操作符
@@ -418,6 +1013,46 @@ pub enum DSLOp {
 }
 
 impl DSLOp {
+    /// 将 `value` 加到或减到 `accumulator` 上
+    ///
+    /// # 参数
+    /// * `accumulator` - 当前的结果
+    /// * `value` - 操作数
+    ///
+    /// # 返回值
+    /// 运算后的结果
+    pub fn apply(&self, accumulator: i64, value: i64) -> i64 {
+        match self {
+            Self::Add => accumulator + value,
+            Self::Sub => accumulator - value,
+        }
+    }
+
+    /// 将字符转换为操作符，`'+'` 为 [`DSLOp::Add`]，`'-'` 为 [`DSLOp::Sub`]，其他字符返回 `None`
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Self::Add),
+            '-' => Some(Self::Sub),
+            _ => None,
+        }
+    }
+
+    /// 与 [`DSLOp::apply`] 相同，但溢出时取 `i64` 的最大值或最小值
+    pub fn apply_saturating(&self, accumulator: i64, value: i64) -> i64 {
+        match self {
+            Self::Add => accumulator.saturating_add(value),
+            Self::Sub => accumulator.saturating_sub(value),
+        }
+    }
+
+    /// 与 [`DSLOp::apply`] 相同，但溢出时返回 `None`
+    pub fn apply_checked(&self, accumulator: i64, value: i64) -> Option<i64> {
+        match self {
+            Self::Add => accumulator.checked_add(value),
+            Self::Sub => accumulator.checked_sub(value),
+        }
+    }
+
     /// 获取相反的操作符
     ///
     /// # 返回值
@@ -444,17 +1079,38 @@ impl Token for DSLOp {
     }
 }
 
-/// 解析DSL中的操作符
-///
-/// 尝试解析加法(+)或减法(-)操作符
-///
-/// # 参数
-/// * `input` - 输入的span
-///
-/// # 返回值
+impl Display for DSLOp {
+    /// 格式化为操作符本身，即 `+` 或 `-`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.token())
+    }
+}
+
+impl TryFrom<char> for DSLOp {
+    type Error = String;
+
+    /// 见 [`DSLOp::from_char`]
+    fn try_from(c: char) -> Result<Self, Self::Error> {
+        Self::from_char(c).ok_or_else(|| format!("unknown operator: '{c}'"))
+    }
+}
+
+/// 解析操作符本身，不跳过前面的空白
+fn parse_op_token(input: Span) -> IResult<Span, DSLOp> {
+    alt((_parse(DSLOp::Add), _parse(DSLOp::Sub))).parse(input)
+}
+
+/// 解析DSL中的操作符
+///
+/// 尝试解析加法(+)或减法(-)操作符
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
 /// 返回解析结果，包含剩余输入和解析出的操作符（如果存在）
 pub fn parse_op(input: Span) -> error::ParseExprResult<Span, Option<DSLItem<DSLOp>>> {
-    let (input, _) = many0(space1).parse(input).map_err(map_err_build2(
+    let (input, _) = skip_ignored(input).map_err(map_err_build2(
         input.location_offset(),
         error::ParseErrorKind::Op,
     ))?;
@@ -462,12 +1118,10 @@ pub fn parse_op(input: Span) -> error::ParseExprResult<Span, Option<DSLItem<DSLO
         return Ok((input, None));
     }
     let offset = input.location_offset();
-    let (input, op) = alt((_parse(DSLOp::Add), _parse(DSLOp::Sub)))
-        .parse(input)
-        .map_err(map_err_build2(
-            input.location_offset(),
-            error::ParseErrorKind::Op,
-        ))?;
+    let (input, op) = parse_op_token(input).map_err(map_err_build2(
+        input.location_offset(),
+        error::ParseErrorKind::Op,
+    ))?;
     Ok((
         input,
         Some(DSLItem {
@@ -478,15 +1132,104 @@ pub fn parse_op(input: Span) -> error::ParseExprResult<Span, Option<DSLItem<DSLO
     ))
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// 表示完整的DSL表达式
 ///
-/// 包含项列表和操作符列表
+/// 包含项列表和操作符列表，每个项都有对应的操作符，两者长度始终相同。
+/// 第一个项的操作符由解析器补上，为长度为0的加法
 pub struct Expr {
     /// 表达式中的项列表
-    pub items: Vec<DSLItem<DSLType>>,
+    items: Vec<DSLItem<DSLType>>,
     /// 表达式中的操作符列表
-    pub ops: Vec<DSLItem<DSLOp>>,
+    ops: Vec<DSLItem<DSLOp>>,
+}
+
+impl Expr {
+    /// 在表达式末尾添加一个项及其操作符
+    ///
+    /// # 参数
+    /// * `op` - 项前面的操作符
+    /// * `item` - 添加的项
+    pub fn push(&mut self, op: DSLItem<DSLOp>, item: DSLItem<DSLType>) {
+        self.ops.push(op);
+        self.items.push(item);
+    }
+
+    /// 按顺序获取表达式中的操作符和对应的项，包含位置信息
+    pub fn iter_terms(&self) -> impl Iterator<Item = (&DSLItem<DSLOp>, &DSLItem<DSLType>)> {
+        debug_assert_eq!(self.ops.len(), self.items.len());
+        self.ops.iter().zip(self.items.iter())
+    }
+
+    /// 获取表达式中的项
+    pub fn items(&self) -> &[DSLItem<DSLType>] {
+        &self.items
+    }
+
+    /// 获取表达式中的操作符，第一个操作符可能是解析器补上的
+    pub fn ops(&self) -> &[DSLItem<DSLOp>] {
+        &self.ops
+    }
+
+    /// 表达式是否不包含任何项
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// 计算表达式中常量时间的净值，即所有时间戳和帧数项按符号相加的结果
+    ///
+    /// 关键字、变量和函数调用不计算在内，帧数按 `fps` 换算为时间，`fps` 不是正数时忽略帧数项
+    ///
+    /// # 参数
+    /// * `fps` - 帧率
+    ///
+    /// # 返回值
+    /// 净值的符号和绝对值，净值为0时符号为加法
+    pub fn total_constant_duration(&self, fps: f64) -> (DSLOp, Duration) {
+        let nanos = self
+            .iter_terms()
+            .map(|(op, item)| {
+                let nanos = match item.content {
+                    DSLType::Timestamp(time) => time.as_nanos() as i128,
+                    DSLType::FrameIndex(frame) if fps > 0.0 => {
+                        (frame as f64 * 1e9 / fps).round() as i128
+                    }
+                    _ => 0,
+                };
+                match op.content {
+                    DSLOp::Add => nanos,
+                    DSLOp::Sub => -nanos,
+                }
+            })
+            .sum::<i128>();
+        let op = if nanos < 0 { DSLOp::Sub } else { DSLOp::Add };
+        let nanos = nanos.unsigned_abs();
+        let duration = Duration::new(
+            u64::try_from(nanos / 1_000_000_000).unwrap_or(u64::MAX),
+            (nanos % 1_000_000_000) as u32,
+        );
+        (op, duration)
+    }
+
+    /// 计算表达式中帧数项的净值，即所有帧数项按符号相加的结果，超出 `i64` 范围时取边界值
+    pub fn total_constant_frames(&self) -> i64 {
+        let frames = self
+            .iter_terms()
+            .map(|(op, item)| match (op.content, &item.content) {
+                (DSLOp::Add, DSLType::FrameIndex(frame)) => *frame as i128,
+                (DSLOp::Sub, DSLType::FrameIndex(frame)) => -(*frame as i128),
+                _ => 0,
+            })
+            .sum::<i128>();
+        frames.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+    }
+
+    /// 移除指定位置的项及其操作符
+    fn remove(&mut self, index: usize) {
+        self.ops.remove(index);
+        self.items.remove(index);
+    }
 }
 
 /// 解析完整的DSL表达式
@@ -499,124 +1242,339 @@ pub struct Expr {
 /// # 返回值
 /// 返回解析结果，包含剩余输入和解析出的表达式
 pub fn parse_expr(input: Span) -> error::ParseExprResult<Span, Expr> {
-    let (mut input, Some(item)) = parse_item(input)? else {
+    parse_expr_with_limit(input, MAX_EXPR_ITEMS)
+}
+
+/// [`parse_expr`] 允许的最大项数，函数调用及其参数中的项都计算在内
+pub const MAX_EXPR_ITEMS: usize = 64;
+
+/// 解析DSL表达式，项数超过 `max_items` 时返回错误
+///
+/// 用于限制恶意输入占用的内存和函数嵌套的深度
+///
+/// # 参数
+/// * `input` - 输入的span
+/// * `max_items` - 允许的最大项数，函数调用及其参数中的项都计算在内
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的表达式
+pub fn parse_expr_with_limit(input: Span, max_items: usize) -> error::ParseExprResult<Span, Expr> {
+    parse_terms(input, false, &mut { max_items }).map_err(|err| err.map(|err| err.with_line(input)))
+}
+
+/// 解析完整的DSL表达式，并拒绝空表达式
+///
+/// 与 [`parse_expr`] 相同，但输入为空、只有空白或只有注释时返回错误
+///
+/// # 参数
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的表达式
+pub fn parse_expr_complete(input: Span) -> error::ParseExprResult<Span, Expr> {
+    let (rest, expr) = parse_expr(input)?;
+    if expr.is_empty() {
+        return Err(map_err(
+            nom::Err::Failure(nom::error::Error::new(rest, nom::error::ErrorKind::Eof)),
+            input.location_offset(),
+            error::ParseErrorKind::Empty,
+        )
+        .map(|err| err.with_line(input)));
+    }
+    Ok((rest, expr))
+}
+
+/// 解析由项和操作符交替组成的表达式
+///
+/// # 参数
+/// * `input` - 输入的span
+/// * `nested` - 是否为函数参数，为true时遇到 `,` 或 `)` 会停止解析
+/// * `budget` - 剩余允许解析的项数
+///
+/// # 返回值
+/// 返回解析结果，包含剩余输入和解析出的表达式
+fn parse_terms<'a>(
+    input: Span<'a>,
+    nested: bool,
+    budget: &mut usize,
+) -> error::ParseExprResult<Span<'a>, Expr> {
+    let (mut input, Some(item)) = parse_item_within(input, nested, budget)? else {
         return Ok((input, Expr::default()));
     };
-    let mut items = vec![item];
-    let mut ops = vec![];
+    let mut expr = Expr::default();
+    let implicit = DSLItem {
+        content: DSLOp::Add,
+        offset: item.offset,
+        length: 0,
+    };
+    expr.push(implicit, item);
     while !input.is_empty() {
+        if nested {
+            let (rest, _) = skip_ignored(input).map_err(map_err_build(input.location_offset()))?;
+            if rest.starts_with([',', ')']) {
+                input = rest;
+                break;
+            }
+        }
         let res = parse_op(input)?;
+        input = res.0;
         let Some(op) = res.1 else {
             break;
         };
-        input = res.0;
         let offset = op.offset;
-        ops.push(op);
 
-        let res = parse_item(input)?;
+        let res = parse_item_within(input, nested, budget)?;
         let Some(item) = res.1 else {
             return Err(map_err_build(offset)(nom::Err::Failure(
                 nom::error::Error::new(input, nom::error::ErrorKind::Escaped),
             )));
         };
         input = res.0;
-        items.push(item);
+        expr.push(op, item);
     }
-    Ok((input, Expr { items, ops }))
+    Ok((input, expr))
 }
 
-/// 安全地从枚举中提取值的宏
+#[derive(Debug, Clone, PartialEq)]
+/// 词法单元的种类
+pub enum TokenKind {
+    /// 项，函数调用作为一个整体
+    Item(DSLType),
+    /// 操作符
+    Op(DSLOp),
+    /// 连续的空白（包括换行）
+    Whitespace,
+    /// 注释，不包括行尾的换行
+    Comment,
+    /// 无法识别的内容，持续到下一个空白、注释或操作符之前
+    Unknown,
+}
+
+/// 将输入拆分为词法单元，不构建表达式
 ///
-/// 假设输入值一定是指定的变体，否则会导致未定义行为
+/// 与解析器共用相同的解析函数，因此词法单元的边界与 [`parse_expr`] 一致。
+/// 所有词法单元首尾相接，覆盖整个输入。
+/// 遇到无法恢复的错误（例如不完整的函数调用）时返回错误并结束迭代
 ///
 /// # 参数
-/// * `$($name:ident)::` - 枚举变体的路径
-/// * `$val:expr` - 要提取值的表达式
-macro_rules! get {
-    ($($name:ident)::*, $val:expr) => {
-        match $val {
-            $($name)::*(v) => v,
-            _ => unreachable!(),
+/// * `input` - 输入的span
+///
+/// # 返回值
+/// 返回词法单元的迭代器
+pub fn tokenize(
+    input: Span,
+) -> impl Iterator<Item = Result<DSLItem<TokenKind>, error::ParseError<nom::error::Error<Span>>>> {
+    let mut rest = Some(input);
+    std::iter::from_fn(move || {
+        let input = rest.take().filter(|input| !input.is_empty())?;
+        let offset = input.location_offset();
+        let (input, kind) = if let Ok((input, _)) = multispace1::<_, nom::error::Error<_>>(input) {
+            (input, TokenKind::Whitespace)
+        } else if let Ok((input, _)) = parse_comment(input) {
+            (input, TokenKind::Comment)
+        } else if let Ok((input, op)) = parse_op_token(input) {
+            (input, TokenKind::Op(op))
+        } else {
+            match parse_item(input) {
+                Ok((input, Some(item))) => (input, TokenKind::Item(item.content)),
+                Ok((_, None)) | Err(nom::Err::Incomplete(..)) => return None,
+                Err(nom::Err::Failure(err)) => return Some(Err(err)),
+                Err(nom::Err::Error(..)) => {
+                    let fragment = input.fragment();
+                    let first = fragment.chars().next().map_or(0, char::len_utf8);
+                    let length = fragment[first..]
+                        .find(|c: char| c.is_whitespace() || matches!(c, '#' | '+' | '-'))
+                        .map_or(fragment.len(), |length| length + first);
+                    (input.take_from(length), TokenKind::Unknown)
+                }
+            }
+        };
+        rest = Some(input);
+        Some(Ok(DSLItem {
+            content: kind,
+            offset,
+            length: input.location_offset() - offset,
+        }))
+    })
+}
+
+/// 可以合并的数值项的种类，同一种类的项在优化时合并为一个
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TermCategory {
+    /// 帧数
+    Frames,
+    /// 时长
+    Time,
+}
+
+impl TermCategory {
+    /// 该种类的项合并时溢出的错误
+    fn overflow_error(self) -> error::OptimizeError {
+        match self {
+            Self::Frames => error::OptimizeError::FrameIndexOverflow,
+            Self::Time => error::OptimizeError::TimestampOverflow,
         }
-    };
+    }
+}
+
+impl DSLType {
+    /// 项所属的可合并种类，关键字、变量和函数调用不能合并，返回 `None`
+    ///
+    /// 新的数值项只需要在这里和 [`DSLType::add_checked`]、[`DSLType::sub_checked`]
+    /// 中添加对应的分支即可参与优化
+    fn category(&self) -> Option<TermCategory> {
+        match self {
+            Self::FrameIndex(..) => Some(TermCategory::Frames),
+            Self::Timestamp(..) => Some(TermCategory::Time),
+            Self::Timecode(..) | Self::Keyword(..) | Self::Var(..) | Self::Call { .. } => None,
+        }
+    }
+
+    /// 将两个同类的数值项相加
+    ///
+    /// # 返回值
+    /// 相加后的项，类型不同、不是数值项或溢出时返回 `None`
+    pub fn add_checked(&self, other: &DSLType) -> Option<DSLType> {
+        match (self, other) {
+            (Self::FrameIndex(a), Self::FrameIndex(b)) => a.checked_add(*b).map(Self::FrameIndex),
+            (Self::Timestamp(a), Self::Timestamp(b)) => a.checked_add(*b).map(Self::Timestamp),
+            _ => None,
+        }
+    }
+
+    /// 从该项中减去同类的数值项
+    ///
+    /// # 返回值
+    /// 相减后的项，类型不同、不是数值项或结果为负时返回 `None`
+    pub fn sub_checked(&self, other: &DSLType) -> Option<DSLType> {
+        match (self, other) {
+            (Self::FrameIndex(a), Self::FrameIndex(b)) => a.checked_sub(*b).map(Self::FrameIndex),
+            (Self::Timestamp(a), Self::Timestamp(b)) => a.checked_sub(*b).map(Self::Timestamp),
+            _ => None,
+        }
+    }
+
+    /// 将同一种类的 `other` 合并到该项中
+    ///
+    /// # 参数
+    /// * `other` - 后出现的项
+    /// * `same_op` - 两者的操作符是否相同
+    ///
+    /// # 返回值
+    /// 合并后的项，以及该项的操作符是否需要反转
+    fn fold(
+        &self,
+        other: &Self,
+        category: TermCategory,
+        same_op: bool,
+    ) -> Result<(Self, bool), error::OptimizeError> {
+        let folded = if same_op {
+            self.add_checked(other).map(|sum| (sum, false))
+        } else {
+            // 后出现的值不小于先出现的值时，结果的符号与后出现的项相同
+            other
+                .sub_checked(self)
+                .map(|diff| (diff, true))
+                .or_else(|| self.sub_checked(other).map(|diff| (diff, false)))
+        };
+        folded.ok_or_else(|| category.overflow_error())
+    }
 }
 
 /// 优化DSL表达式
 ///
-/// 合并相同类型的项（帧索引与帧索引，时间戳与时间戳），简化表达式
+/// 合并相同种类的项（帧索引与帧索引，时间戳与时间戳），简化表达式，
+/// 每个种类的项合并到该种类第一次出现的位置
 ///
 /// # 参数
 /// * `expr` - 需要优化的表达式引用
-pub fn optimize_expr(expr: &mut Expr) {
-    expr.ops.insert(
-        0,
-        DSLItem {
-            content: DSLOp::Add,
-            offset: 0,
-            length: 0,
-        },
-    );
+///
+/// # 返回值
+/// 合并后的帧数或时长溢出时返回 [`error::OptimizeError`]
+pub fn optimize_expr(expr: &mut Expr) -> Result<(), error::OptimizeError> {
+    for item in expr.items.iter_mut() {
+        if let DSLType::Call { ref mut args, .. } = item.content {
+            args.iter_mut().try_for_each(optimize_expr)?;
+        }
+    }
     if expr.items.len() < 2 {
-        return;
+        return Ok(());
     }
-    let mut frame_index: Option<usize> = None;
-    let mut time_index: Option<usize> = None;
+    let mut firsts: Vec<(TermCategory, usize)> = Vec::new();
     let mut index = 0;
     while index < expr.items.len() {
-        match expr.items[index].content {
-            DSLType::FrameIndex(this) => match frame_index {
-                Some(first_index) => {
-                    let first = get!(DSLType::FrameIndex, expr.items[first_index].content);
-                    if expr.ops[first_index] == expr.ops[index] {
-                        expr.items[first_index].set(DSLType::FrameIndex(first + this));
-                    } else {
-                        if first > this {
-                            expr.items[first_index].set(DSLType::FrameIndex(first - this));
-                        } else {
-                            expr.ops[first_index].content.reverse();
-                            expr.items[first_index].set(DSLType::FrameIndex(this - first));
-                        }
-                    }
-                    expr.ops.remove(index);
-                    expr.items.remove(index);
-                    continue;
-                }
-                None => frame_index = Some(index),
-            },
-            DSLType::Timestamp(this) => match time_index {
-                Some(first_index) => {
-                    let first = get!(DSLType::Timestamp, expr.items[first_index].content);
-                    if expr.ops[first_index] == expr.ops[index] {
-                        expr.items[first_index].set(DSLType::Timestamp(first + this));
-                    } else {
-                        if first > this {
-                            expr.items[first_index].set(DSLType::Timestamp(first - this));
-                        } else {
-                            expr.ops[first_index].content.reverse();
-                            expr.items[first_index].set(DSLType::Timestamp(this - first));
-                        }
-                    }
-                    expr.ops.remove(index);
-                    expr.items.remove(index);
-                    continue;
+        let Some(category) = expr.items[index].content.category() else {
+            index += 1;
+            continue;
+        };
+        match firsts.iter().find(|(kind, _)| *kind == category) {
+            Some(&(_, first_index)) => {
+                let same_op = expr.ops[first_index] == expr.ops[index];
+                let (folded, reverse) = expr.items[first_index].content.fold(
+                    &expr.items[index].content,
+                    category,
+                    same_op,
+                )?;
+                expr.items[first_index].set(folded);
+                if reverse {
+                    expr.ops[first_index].content.reverse();
                 }
-                None => time_index = Some(index),
-            },
-            DSLType::Keyword(..) => {}
+                expr.remove(index);
+            }
+            None => {
+                firsts.push((category, index));
+                index += 1;
+            }
         }
-        index += 1;
     }
+    Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// 经过验证的DSL表达式
 ///
-/// 仅包含类型，不包含位置信息
+/// 每个项保留了在源字符串中的位置，以便求值出错时指出对应的项
 pub struct CheckedExpr {
-    /// 表达式中的项列表
-    pub items: Vec<DSLType>,
-    /// 表达式中的操作符列表
-    pub ops: Vec<DSLOp>,
+    /// 表达式中的项、项前面的操作符及项的位置
+    terms: Vec<(DSLType, DSLOp, SourceSpan)>,
+}
+
+/// 将操作符和项格式化为表达式字符串
+///
+/// 解析器不接受以 `-` 开头的表达式，因此第一个项的操作符为减法时以 `0s - ` 开头输出，
+/// 该项为帧序号时以 `0f - ` 开头，避免混用单位
+///
+/// # 参数
+/// * `f` - 输出的格式化器
+/// * `terms` - 操作符和对应的项
+fn write_terms<'a>(
+    f: &mut Formatter<'_>,
+    terms: impl Iterator<Item = (DSLOp, &'a DSLType)>,
+) -> std::fmt::Result {
+    for (index, (op, item)) in terms.enumerate() {
+        match (index, op) {
+            (0, DSLOp::Add) => write!(f, "{item}")?,
+            (0, DSLOp::Sub) => match item {
+                DSLType::FrameIndex(..) => write!(f, "0f - {item}")?,
+                _ => write!(f, "0s - {item}")?,
+            },
+            _ => write!(f, " {op} {item}")?,
+        }
+    }
+    Ok(())
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write_terms(f, self.terms())
+    }
+}
+
+impl Display for CheckedExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write_terms(f, self.terms())
+    }
 }
 
 /// 验证DSL表达式的语义正确性
@@ -628,122 +1586,902 @@ pub struct CheckedExpr {
 ///
 /// # 返回值
 /// 验证成功返回CheckedExpr，失败返回错误信息
-pub fn check_expr(expr: &Expr) -> Result<CheckedExpr, String> {
+pub fn check_expr(expr: &Expr) -> Result<CheckedExpr, error::CheckError> {
+    check_expr_with(expr, &CheckOptions::default())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 表达式的语义规则，用于 [`check_expr_with`]
+///
+/// 默认值即命令行使用的规则
+pub struct CheckOptions {
+    /// 允许使用的关键字
+    pub allowed_keywords: HashSet<DSLKeywords>,
+    /// 每个关键字最多出现的次数，按加减抵消后的次数计算
+    pub max_keyword_occurrences: usize,
+    /// 是否允许 `from` 和 `to` 出现在同一个表达式中
+    pub allow_from_and_to: bool,
+    /// 是否允许所有项都是减法，即结果为负的常量
+    pub allow_negative_constant: bool,
+    /// 是否允许空表达式
+    pub allow_empty: bool,
+    /// 是否要求结果为正数，只在帧数和时间都能确定不为正时报错
+    pub require_positive: bool,
+    /// 表达式是否表示视频中的位置，为真时没有关键字或变量作为基准而混用单位会给出
+    /// [`Lint::UnanchoredMixedUnits`]
+    pub positional: bool,
+    /// 以关键字为基准的变量，只有这些变量可以作为基准，见 [`anchored_vars`]
+    pub anchored_vars: HashSet<String>,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            allowed_keywords: DSLKeywords::ALL.into_iter().collect(),
+            max_keyword_occurrences: 1,
+            allow_from_and_to: false,
+            allow_negative_constant: false,
+            allow_empty: true,
+            require_positive: false,
+            positional: true,
+            anchored_vars: HashSet::new(),
+        }
+    }
+}
+
+/// 按指定的规则验证表达式
+///
+/// 函数参数会按相同的规则递归检查
+///
+/// # 参数
+/// * `expr` - 需要验证的表达式引用
+/// * `options` - 语义规则
+///
+/// # 返回值
+/// 验证成功返回CheckedExpr，失败返回违反的规则
+pub fn check_expr_with(
+    expr: &Expr,
+    options: &CheckOptions,
+) -> Result<CheckedExpr, error::CheckError> {
+    use error::CheckError;
+
+    if expr.is_empty() && !options.allow_empty {
+        return Err(CheckError::Empty);
+    }
     let mut counter = HashMap::<DSLKeywords, isize>::new();
     let mut has_add = false;
-    for (item, op) in expr.items.iter().zip(expr.ops.iter()) {
-        match item.content {
-            DSLType::Keyword(word) => {
-                if *op == DSLOp::Add {
-                    *counter.entry(word).or_default() += 1;
-                } else {
-                    *counter.entry(word).or_default() -= 1;
-                }
+    for (op, item) in expr.iter_terms() {
+        if let DSLType::Call { ref args, .. } = item.content {
+            for arg in args {
+                check_expr_with(arg, options)?;
+            }
+        }
+        if let DSLType::Keyword(word) = item.content {
+            if !options.allowed_keywords.contains(&word) {
+                return Err(CheckError::KeywordNotAllowed(word));
+            }
+            if *op == DSLOp::Add {
+                *counter.entry(word).or_default() += 1;
+            } else {
+                *counter.entry(word).or_default() -= 1;
             }
-            _ => {}
         }
         if *op == DSLOp::Add {
             has_add = true;
         }
     }
-    if !has_add && !expr.ops.is_empty() {
-        return Err("Overflow: all is sub".to_string());
+    if !has_add && !expr.is_empty() && !options.allow_negative_constant {
+        return Err(CheckError::NegativeConstant);
     }
-    if counter.values().any(|v| v.abs() > 1) {
-        return Err("Too many keywords".to_string());
+    let mut counts = counter.into_iter().collect::<Vec<_>>();
+    counts.sort_by_key(|(word, _)| word.token());
+    if let Some((word, _)) = counts
+        .into_iter()
+        .find(|(_, count)| count.unsigned_abs() > options.max_keyword_occurrences)
+    {
+        return Err(CheckError::TooManyKeywords(word));
+    }
+    if !options.allow_from_and_to && expr.references_from() && expr.references_to() {
+        return Err(CheckError::CircularReference);
     }
-    if counter.contains_key(&DSLKeywords::From) && counter.contains_key(&DSLKeywords::To) {
-        return Err("circular references".to_string());
+    if options.require_positive && !is_maybe_positive(expr) {
+        return Err(CheckError::NotPositive);
     }
     Ok(CheckedExpr {
-        items: expr
-            .items
-            .iter()
-            .map(|item| item.content.clone())
-            .collect::<_>(),
-        ops: expr.ops.iter().map(|item| item.content).collect::<_>(),
+        terms: expr
+            .iter_terms()
+            .map(|(op, item)| (item.content.clone(), op.content, item.span()))
+            .collect(),
     })
 }
 
-/// 解析错误处理模块
+/// 表达式的结果是否可能为正数
 ///
-/// 提供了自定义的解析错误类型和相关工具
-pub mod error {
-    use std::error::Error;
-    use std::fmt::Formatter;
-
-    #[derive(Debug, Clone, Copy, PartialEq)]
-    /// 解析错误的种类
-    pub enum ParseErrorKind {
-        /// 来自nom库的基本解析错误
-        Nom,
-        /// 操作符相关的解析错误
-        Op,
-        /// 关键字相关的解析错误
-        Keywords,
+/// 帧数和时间分别求和，含有关键字、变量或函数调用时无法确定，视为可能为正数
+fn is_maybe_positive(expr: &Expr) -> bool {
+    let mut frames = 0i128;
+    let mut nanos = 0i128;
+    for (op, item) in expr.terms() {
+        let sign = if op == DSLOp::Add { 1 } else { -1 };
+        match item {
+            DSLType::FrameIndex(index) => frames += sign * *index as i128,
+            DSLType::Timestamp(dur) => nanos += sign * dur.as_nanos() as i128,
+            _ => return true,
+        }
     }
+    frames > 0 || nanos > 0
+}
 
-    /// 解析表达式的返回类型
-    pub type ParseExprResult<I, O, E = ParseError<nom::error::Error<I>>> =
-        Result<(I, O), nom::Err<E>>;
-
-    #[derive(Debug)]
-    /// 自定义解析错误类型
+impl DSLType {
+    /// 获取该项引用的其他表达式名称，函数调用会递归获取参数中的引用
     ///
-    /// 包含错误位置信息和原始错误
-    pub struct ParseError<T>
-    where
-        T: Error,
-    {
-        /// 错误在输入中的偏移量
-        pub offset: usize,
-        /// 错误的长度
-        pub length: usize,
-        /// 源错误
-        pub source: Box<T>,
-        /// 错误类型
-        pub kind: ParseErrorKind,
-    }
-
-    impl<T> std::fmt::Display for ParseError<T>
-    where
-        T: Error,
-    {
-        /// 格式化错误信息
-        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            write!(
-                f,
-                "in 1:{}(length {}): {}",
-                self.offset, self.offset, self.source
-            )
+    /// 关键字 `from`、`to` 分别对应 `from`、`to`，变量对应变量名
+    pub fn references(&self) -> Vec<&str> {
+        match self {
+            Self::Keyword(DSLKeywords::From) => vec!["from"],
+            Self::Keyword(DSLKeywords::To) => vec!["to"],
+            Self::Var(name) => vec![name.as_str()],
+            Self::Call { args, .. } => args
+                .iter()
+                .flat_map(|arg| {
+                    arg.items()
+                        .iter()
+                        .flat_map(|item| item.content.references())
+                })
+                .collect(),
+            _ => vec![],
         }
     }
-    impl<T> Error for ParseError<T> where T: Error {}
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Expr {
+    /// 按顺序获取表达式中的操作符和项，不包含位置信息
+    pub fn terms(&self) -> impl Iterator<Item = (DSLOp, &DSLType)> {
+        self.iter_terms()
+            .map(|(op, item)| (op.content, &item.content))
+    }
 
-    #[test]
-    fn test_keyword_parser() {
-        let keywords = vec![
-            ("end", DSLKeywords::End),
-            ("from", DSLKeywords::From),
-            ("to", DSLKeywords::To),
-        ];
-        for (word, keyword) in keywords {
-            let (_, k) = parse_keyword(word.into()).unwrap();
-            assert_eq!(DSLType::Keyword(keyword), k);
-        }
-        assert!(parse_keyword("hello".into()).is_err());
+    /// 按顺序获取表达式中的操作符、项及项的位置
+    pub fn spanned_terms(&self) -> impl Iterator<Item = (DSLOp, &DSLType, SourceSpan)> {
+        self.iter_terms()
+            .map(|(op, item)| (op.content, &item.content, item.span()))
     }
 
-    #[test]
-    fn test_frame_parser() {
-        let (_, val) = parse_frame_index("100f".into()).unwrap();
-        match val {
-            DSLType::FrameIndex(v) => assert_eq!(v, 100),
+    /// 表达式（包括函数参数）中是否引用了 `from`
+    pub fn references_from(&self) -> bool {
+        self.references_keyword("from")
+    }
+
+    /// 表达式（包括函数参数）中是否引用了 `to`
+    pub fn references_to(&self) -> bool {
+        self.references_keyword("to")
+    }
+
+    fn references_keyword(&self, name: &str) -> bool {
+        self.items
+            .iter()
+            .any(|item| item.content.references().contains(&name))
+    }
+}
+
+impl CheckedExpr {
+    /// 获取表达式引用的其他表达式名称
+    ///
+    /// 关键字 `from`、`to` 分别对应 `from`、`to`，变量对应变量名
+    pub fn references(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().flat_map(|(item, ..)| item.references())
+    }
+
+    /// 按顺序获取表达式中的操作符和项
+    pub fn terms(&self) -> impl Iterator<Item = (DSLOp, &DSLType)> {
+        self.terms.iter().map(|(item, op, _)| (*op, item))
+    }
+
+    /// 按顺序获取表达式中的操作符、项及项的位置
+    pub fn spanned_terms(&self) -> impl Iterator<Item = (DSLOp, &DSLType, SourceSpan)> {
+        self.terms.iter().map(|(item, op, span)| (*op, item, *span))
+    }
+
+    /// 表达式（包括函数参数）中是否引用了 `from`
+    pub fn references_from(&self) -> bool {
+        self.references().any(|name| name == "from")
+    }
+
+    /// 表达式（包括函数参数）中是否引用了 `to`
+    pub fn references_to(&self) -> bool {
+        self.references().any(|name| name == "to")
+    }
+}
+
+/// 帧序号的合理上限，超过时给出警告（60fps下约为46小时）
+pub const MAX_PLAUSIBLE_FRAME_INDEX: u64 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+/// 不影响解析结果的可疑写法
+pub enum Lint {
+    /// 同一个表达式中混用了帧序号和时间戳，结果依赖帧率
+    MixedUnits,
+    /// 没有关键字或以关键字为基准的变量作为基准，却混用了帧序号和时间戳，例如 `100f - 5s`
+    UnanchoredMixedUnits,
+    /// 帧序号超过 [`MAX_PLAUSIBLE_FRAME_INDEX`]
+    LargeFrameIndex(u64),
+}
+
+/// 按默认的规则检查表达式中的可疑写法，见 [`lint_expr_with`]
+pub fn lint_expr(expr: &Expr) -> Vec<DSLItem<Lint>> {
+    lint_expr_with(expr, &CheckOptions::default())
+}
+
+/// 检查表达式中的可疑写法，函数参数会一起检查
+///
+/// # 参数
+/// * `expr` - 需要检查的表达式
+/// * `options` - 语义规则，只使用 [`CheckOptions::positional`] 和 [`CheckOptions::anchored_vars`]
+///
+/// # 返回值
+/// 按位置排序的lint结果，位置指向相关的帧序号
+pub fn lint_expr_with(expr: &Expr, options: &CheckOptions) -> Vec<DSLItem<Lint>> {
+    fn visit<'a>(expr: &'a Expr, items: &mut Vec<&'a DSLItem<DSLType>>) {
+        for item in expr.items() {
+            match item.content {
+                DSLType::Call { ref args, .. } => args.iter().for_each(|arg| visit(arg, items)),
+                _ => items.push(item),
+            }
+        }
+    }
+    let mut items = vec![];
+    visit(expr, &mut items);
+    let lint = |item: &DSLItem<DSLType>, content| DSLItem {
+        content,
+        offset: item.offset,
+        length: item.length,
+    };
+    let mut lints = vec![];
+    let has_timestamp = items
+        .iter()
+        .any(|item| matches!(item.content, DSLType::Timestamp(..)));
+    let first_frame = items
+        .iter()
+        .find(|item| matches!(item.content, DSLType::FrameIndex(..)));
+    if let (true, Some(item)) = (has_timestamp, first_frame) {
+        let anchored = items
+            .iter()
+            .any(|item| is_anchor(&item.content, &options.anchored_vars));
+        if options.positional && !anchored {
+            lints.push(lint(item, Lint::UnanchoredMixedUnits));
+        } else {
+            lints.push(lint(item, Lint::MixedUnits));
+        }
+    }
+    for item in items.iter() {
+        if let DSLType::FrameIndex(index) = item.content
+            && index > MAX_PLAUSIBLE_FRAME_INDEX
+        {
+            lints.push(lint(item, Lint::LargeFrameIndex(index)));
+        }
+    }
+    lints.sort_by_key(|lint| lint.offset);
+    lints
+}
+
+/// 项是否为基准，即关键字、`anchored_vars` 中的变量或参数中含有基准的函数调用
+fn is_anchor(item: &DSLType, anchored_vars: &HashSet<String>) -> bool {
+    match item {
+        DSLType::Keyword(..) => true,
+        DSLType::Var(name) => anchored_vars.contains(name),
+        DSLType::Call { args, .. } => args.iter().any(|arg| {
+            arg.items()
+                .iter()
+                .any(|item| is_anchor(&item.content, anchored_vars))
+        }),
+        _ => false,
+    }
+}
+
+/// 找出以关键字为基准的变量，用于 [`CheckOptions::anchored_vars`]
+///
+/// 变量引用了关键字或其他以关键字为基准的变量时，才以关键字为基准
+pub fn anchored_vars(vars: &HashMap<String, CheckedExpr>) -> HashSet<String> {
+    let mut anchored = HashSet::new();
+    loop {
+        let found = vars
+            .iter()
+            .filter(|(name, expr)| {
+                !anchored.contains(*name)
+                    && expr.terms().any(|(_, item)| is_anchor(item, &anchored))
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            return anchored;
+        }
+        anchored.extend(found);
+    }
+}
+
+/// 在表达式的引用关系中查找循环引用
+///
+/// # 参数
+/// * `deps` - 每个表达式名称及其引用的名称
+///
+/// # 返回值
+/// 存在循环引用时返回构成循环的路径（首尾相同），否则返回None
+pub fn find_cycle<'a>(deps: &HashMap<&'a str, Vec<&'a str>>) -> Option<Vec<&'a str>> {
+    fn visit<'a>(
+        node: &'a str,
+        deps: &HashMap<&'a str, Vec<&'a str>>,
+        path: &mut Vec<&'a str>,
+        done: &mut Vec<&'a str>,
+    ) -> Option<Vec<&'a str>> {
+        if let Some(start) = path.iter().position(|item| *item == node) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(node);
+            return Some(cycle);
+        }
+        if done.contains(&node) {
+            return None;
+        }
+        path.push(node);
+        for next in deps.get(node).into_iter().flatten() {
+            if let Some(cycle) = visit(next, deps, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.push(node);
+        None
+    }
+
+    let mut nodes = deps.keys().copied().collect::<Vec<_>>();
+    nodes.sort_unstable();
+    let mut done = vec![];
+    nodes
+        .into_iter()
+        .find_map(|node| visit(node, deps, &mut vec![], &mut done))
+}
+
+impl FromStr for CheckedExpr {
+    type Err = error::ExprError;
+
+    /// 依次执行解析、优化和验证，得到可直接使用的表达式
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (_, mut expr) = parse_expr(s.into())?;
+        optimize_expr(&mut expr).map_err(error::ExprError::Optimize)?;
+        check_expr(&expr).map_err(error::ExprError::Check)
+    }
+}
+
+/// 解析错误处理模块
+///
+/// 提供了自定义的解析错误类型和相关工具
+pub mod error {
+    use std::error::Error;
+    use std::fmt::Formatter;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    /// 解析错误的种类
+    pub enum ParseErrorKind {
+        /// 来自nom库的基本解析错误
+        Nom,
+        /// 操作符相关的解析错误
+        Op,
+        /// 关键字相关的解析错误
+        Keywords,
+        /// 函数调用相关的解析错误
+        Function,
+        /// 表达式为空
+        Empty,
+        /// 表达式的项数超过了上限
+        TooManyItems,
+        /// 时间戳的字段累加后超出了范围
+        Overflow,
+    }
+
+    /// 解析表达式的返回类型
+    pub type ParseExprResult<I, O, E = ParseError<nom::error::Error<I>>> =
+        Result<(I, O), nom::Err<E>>;
+
+    #[derive(Debug)]
+    /// 自定义解析错误类型
+    ///
+    /// 包含错误位置信息和原始错误
+    pub struct ParseError<T>
+    where
+        T: Error,
+    {
+        /// 错误在输入中的偏移量
+        pub offset: usize,
+        /// 错误的长度
+        pub length: usize,
+        /// 源错误
+        pub source: Box<T>,
+        /// 错误类型
+        pub kind: ParseErrorKind,
+        /// 错误所在的行，由 [`super::parse_expr`] 等入口函数填充
+        pub line: Option<SourceLine>,
+    }
+
+    impl<T> ParseError<T>
+    where
+        T: Error,
+    {
+        /// 记录错误所在的行
+        ///
+        /// # 参数
+        /// * `input` - 解析时的完整输入
+        pub fn with_line(mut self, input: super::Span) -> Self {
+            self.line = Some(SourceLine::locate(input, self.offset));
+            self
+        }
+
+        /// 错误位置的行号和列号（均从1开始，列号按字符计算）
+        pub fn line_column(&self) -> (u32, usize) {
+            match self.line {
+                Some(ref line) => (line.number, line.column(self.offset)),
+                None => (1, self.offset + 1),
+            }
+        }
+    }
+
+    impl<T> std::fmt::Display for ParseError<T>
+    where
+        T: Error,
+    {
+        /// 格式化错误信息
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let (line, column) = self.line_column();
+            write!(
+                f,
+                "in {line}:{column}(length {}): {}",
+                self.length, self.source
+            )
+        }
+    }
+    impl<T> Error for ParseError<T>
+    where
+        T: Error + 'static,
+    {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(self.source.as_ref())
+        }
+    }
+
+    /// 不借用输入的解析错误，可以放入 `Box<dyn Error>` 等错误链中
+    pub type OwnedParseError = ParseError<nom::error::Error<String>>;
+
+    impl ParseError<nom::error::Error<super::Span<'_>>> {
+        /// 复制出错位置之后的剩余输入，转换为不借用输入的错误
+        ///
+        /// 源错误借用了输入，只有转换后 `source()` 才能返回源错误
+        pub fn into_owned(self) -> OwnedParseError {
+            ParseError {
+                offset: self.offset,
+                length: self.length,
+                source: Box::new(nom::error::Error::new(
+                    self.source.input.fragment().to_string(),
+                    self.source.code,
+                )),
+                kind: self.kind,
+                line: self.line,
+            }
+        }
+
+        /// 生成不依赖终端的诊断信息
+        ///
+        /// 部分信息（例如函数名）需要错误所在的行，没有记录时会省略
+        pub fn to_diagnostic(&self) -> Diagnostic {
+            use nom::error::ErrorKind;
+
+            let text = |offset: usize| {
+                self.line
+                    .as_ref()
+                    .map(|line| line.rest(offset))
+                    .unwrap_or_default()
+            };
+            let diagnostic =
+                |message: String, length: usize, label: &str, help: Option<String>| Diagnostic {
+                    message,
+                    offset: self.offset,
+                    length,
+                    label: Some(label.to_string()),
+                    help,
+                };
+            match self.source.code {
+                _ if self.kind == ParseErrorKind::Empty => diagnostic(
+                    "empty expression".to_string(),
+                    self.length.max(1),
+                    "expected an expression",
+                    Some(
+                        "use `0f` for the first frame or `end` for the end of the video"
+                            .to_string(),
+                    ),
+                ),
+                _ if self.kind == ParseErrorKind::Overflow => diagnostic(
+                    "timestamp is too large".to_string(),
+                    self.length,
+                    "overflows here",
+                    None,
+                ),
+                _ if self.kind == ParseErrorKind::TooManyItems => diagnostic(
+                    "expression has too many items".to_string(),
+                    1,
+                    "limit reached here",
+                    Some("split the expression into variables with `--let`".to_string()),
+                ),
+                code if self.kind == ParseErrorKind::Function => {
+                    let name = text(self.offset)
+                        .split('(')
+                        .next()
+                        .unwrap_or_default()
+                        .trim();
+                    match code {
+                        ErrorKind::Tag => diagnostic(
+                            format!("unknown function: `{name}`"),
+                            self.length,
+                            "unknown function",
+                            super::did_you_mean(name, super::FUNCTIONS),
+                        ),
+                        ErrorKind::ManyMN => diagnostic(
+                            match super::function_arity(name) {
+                                (1, 1) => format!("function `{name}` expects exactly one argument"),
+                                (min, max) if min == max => {
+                                    format!("function `{name}` expects exactly {min} arguments")
+                                }
+                                (min, _) => {
+                                    format!("function `{name}` expects at least {min} arguments")
+                                }
+                            },
+                            self.length,
+                            "wrong number of arguments",
+                            None,
+                        ),
+                        ErrorKind::Verify => diagnostic(
+                            "missing argument".to_string(),
+                            1,
+                            "expected an expression",
+                            None,
+                        ),
+                        _ => diagnostic("expected `,` or `)`".to_string(), 1, "here", None),
+                    }
+                }
+                // `时:分:秒` 格式的时间戳精确到毫秒，ISO 8601 时长精确到纳秒
+                ErrorKind::TooLarge if text(self.offset).starts_with("PT") => diagnostic(
+                    "too many fractional digits, at most 9 are supported".to_string(),
+                    self.length,
+                    "too precise",
+                    Some("nanoseconds are the smallest supported unit".to_string()),
+                ),
+                ErrorKind::TooLarge => diagnostic(
+                    format!(
+                        "too many fractional digits, at most {} are supported",
+                        crate::TIMESTAMP_FRACTION_DIGITS
+                    ),
+                    self.length,
+                    "too precise",
+                    Some("write sub-millisecond times in seconds, e.g. `62.1234s`".to_string()),
+                ),
+                ErrorKind::Count => {
+                    let literal = text(self.offset);
+                    let literal = &literal[..self.length.min(literal.len())];
+                    let literal = literal.split('.').next().unwrap_or_default();
+                    let kept = literal.splitn(4, ':').take(3).collect::<Vec<_>>().join(":");
+                    diagnostic(
+                        "too many `:`-separated fields (expected mm:ss or hh:mm:ss)".to_string(),
+                        self.length,
+                        "too many fields",
+                        Some(format!(
+                            "drop the extra fields, e.g. `{kept}`; timecodes with frames (hh:mm:ss:ff) are not supported"
+                        )),
+                    )
+                }
+                ErrorKind::Tag if self.kind == ParseErrorKind::Op => diagnostic(
+                    "missing operation, expected `+` or `-`".to_string(),
+                    1,
+                    "here",
+                    None,
+                ),
+                ErrorKind::Tag => {
+                    let word = nom::character::complete::alpha1::<_, nom::error::Error<_>>(
+                        self.source.input,
+                    )
+                    .map(|(_, word)| word.to_string())
+                    .ok();
+                    let (msg, help) = if self.kind == ParseErrorKind::Keywords {
+                        let help = word
+                            .as_ref()
+                            .and_then(|word| super::did_you_mean(word, super::keyword_spellings()));
+                        ("unknown keyword", help)
+                    } else {
+                        ("invalid token", None)
+                    };
+                    let word = word.map(|word| format!(": `{word}`")).unwrap_or_default();
+                    Diagnostic {
+                        offset: self.offset + self.length,
+                        ..diagnostic(
+                            format!("{msg}{word}"),
+                            word.len().saturating_sub(4).max(1),
+                            msg,
+                            help,
+                        )
+                    }
+                }
+                ErrorKind::Escaped => diagnostic(
+                    format!(
+                        "escaped operation: `{}`",
+                        text(self.offset).chars().next().unwrap_or_default()
+                    ),
+                    self.length,
+                    "escaped operation",
+                    None,
+                ),
+                code => diagnostic(
+                    code.description().to_string(),
+                    self.length.max(1),
+                    "here",
+                    None,
+                ),
+            }
+        }
+
+        /// 生成单行的纯文本错误信息，例如 `1:7: unknown keyword: `ned``
+        pub fn summary(&self) -> String {
+            let diagnostic = self.to_diagnostic();
+            let (line, column) = match self.line {
+                Some(ref line) => (line.number, line.column(diagnostic.offset)),
+                None => (1, diagnostic.offset + 1),
+            };
+            format!("{line}:{column}: {}", diagnostic.message)
+        }
+
+        /// 生成多行的纯文本错误信息，格式与终端输出相同但不带颜色
+        pub fn render_plain(&self) -> String {
+            use std::fmt::Write;
+            use unicode_width::UnicodeWidthStr;
+
+            let diagnostic = self.to_diagnostic();
+            let mut out = String::new();
+            _ = writeln!(out, "error: {}", diagnostic.message);
+            let Some(ref line) = self.line else {
+                return out;
+            };
+            let line_no = line.number.to_string();
+            let pad = " ".repeat(line_no.len() + 1);
+            let column = line.column(diagnostic.offset);
+            _ = writeln!(out, "{pad}--> {}:{column}", line.number);
+            _ = writeln!(out, "{pad} |");
+            _ = writeln!(out, " {line_no} | {}", line.text);
+            let before = &line.text[..line.text.len() - line.rest(diagnostic.offset).len()];
+            let marked = line.rest(diagnostic.offset);
+            let mut end = diagnostic.length.min(marked.len());
+            while !marked.is_char_boundary(end) {
+                end += 1;
+            }
+            let carets = marked[..end].width().max(diagnostic.length.min(1));
+            _ = writeln!(
+                out,
+                "{pad} | {}{} {}",
+                " ".repeat(before.width()),
+                "^".repeat(carets),
+                diagnostic.label.unwrap_or_default()
+            );
+            if let Some(help) = diagnostic.help {
+                _ = writeln!(out, "{pad} |");
+                _ = writeln!(out, "{pad} = help: {help}");
+            }
+            out
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    /// 错误所在的源代码行
+    pub struct SourceLine {
+        /// 行的内容，不包括换行
+        pub text: String,
+        /// 行号，从1开始
+        pub number: u32,
+        /// 行首在输入中的字节偏移量
+        pub offset: usize,
+    }
+
+    impl SourceLine {
+        /// 从完整的输入中取出 `offset` 所在的行
+        ///
+        /// # 参数
+        /// * `input` - 解析时的完整输入
+        /// * `offset` - 字节偏移量
+        pub fn locate(input: super::Span, offset: usize) -> Self {
+            let base = input.location_offset();
+            let text = *input.fragment();
+            let mut rel = offset.saturating_sub(base).min(text.len());
+            while !text.is_char_boundary(rel) {
+                rel -= 1;
+            }
+            let start = text[..rel].rfind('\n').map_or(0, |index| index + 1);
+            let end = text[rel..]
+                .find('\n')
+                .map_or(text.len(), |index| rel + index);
+            Self {
+                text: text[start..end].trim_end_matches('\r').to_string(),
+                number: input.location_line() + text[..start].matches('\n').count() as u32,
+                offset: base + start,
+            }
+        }
+
+        /// `offset` 在这一行中的列号，从1开始，按字符计算
+        pub fn column(&self, offset: usize) -> usize {
+            let before = self.text.len() - self.rest(offset).len();
+            self.text[..before].chars().count() + 1
+        }
+
+        /// 这一行中从 `offset` 开始到行尾的内容
+        fn rest(&self, offset: usize) -> &str {
+            let mut rel = offset.saturating_sub(self.offset).min(self.text.len());
+            while !self.text.is_char_boundary(rel) {
+                rel -= 1;
+            }
+            &self.text[rel..]
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    /// 不依赖终端的诊断信息，由 [`ParseError::to_diagnostic`] 生成
+    pub struct Diagnostic {
+        /// 错误信息
+        pub message: String,
+        /// 标记开始的字节偏移量
+        pub offset: usize,
+        /// 标记的字节长度
+        pub length: usize,
+        /// 标记旁边的说明
+        pub label: Option<String>,
+        /// 修改建议
+        pub help: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    /// 不依赖输入生命周期的表达式错误
+    ///
+    /// 覆盖从解析到语义检查的整个流程
+    pub enum ExprError {
+        /// 语法错误，保留错误位置以便输出诊断信息
+        Syntax {
+            /// 错误在输入中的偏移量
+            offset: usize,
+            /// 错误的长度
+            length: usize,
+            /// 错误类型
+            kind: ParseErrorKind,
+            /// nom的错误代码
+            code: nom::error::ErrorKind,
+        },
+        /// 优化时合并常量溢出
+        Optimize(OptimizeError),
+        /// 语义检查错误
+        Check(CheckError),
+    }
+
+    impl<'a> From<nom::Err<ParseError<nom::error::Error<super::Span<'a>>>>> for ExprError {
+        fn from(value: nom::Err<ParseError<nom::error::Error<super::Span<'a>>>>) -> Self {
+            match value {
+                nom::Err::Error(err) | nom::Err::Failure(err) => Self::Syntax {
+                    offset: err.offset,
+                    length: err.length,
+                    kind: err.kind,
+                    code: err.source.code,
+                },
+                nom::Err::Incomplete(..) => Self::Syntax {
+                    offset: 0,
+                    length: 0,
+                    kind: ParseErrorKind::Nom,
+                    code: nom::error::ErrorKind::Complete,
+                },
+            }
+        }
+    }
+
+    impl std::fmt::Display for ExprError {
+        /// 格式化错误信息
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Syntax {
+                    offset,
+                    length,
+                    code,
+                    ..
+                } => write!(
+                    f,
+                    "in 1:{}(length {length}): {}",
+                    offset + 1,
+                    code.description()
+                ),
+                Self::Optimize(err) => err.fmt(f),
+                Self::Check(err) => err.fmt(f),
+            }
+        }
+    }
+    impl Error for ExprError {}
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    /// 语义检查错误，表示违反了 [`super::CheckOptions`] 中的哪条规则
+    pub enum CheckError {
+        /// 表达式为空
+        Empty,
+        /// 使用了不允许的关键字
+        KeywordNotAllowed(super::DSLKeywords),
+        /// 关键字出现的次数过多
+        TooManyKeywords(super::DSLKeywords),
+        /// `from` 和 `to` 出现在同一个表达式中
+        CircularReference,
+        /// 所有项都是减法
+        NegativeConstant,
+        /// 结果不为正数
+        NotPositive,
+    }
+
+    impl std::fmt::Display for CheckError {
+        /// 格式化错误信息
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            use super::Token;
+            match self {
+                Self::Empty => f.write_str("empty expression"),
+                Self::KeywordNotAllowed(word) => {
+                    write!(f, "keyword `{}` is not allowed here", word.token())
+                }
+                Self::TooManyKeywords(word) => {
+                    write!(f, "Too many keywords: `{}`", word.token())
+                }
+                Self::CircularReference => f.write_str("circular references"),
+                Self::NegativeConstant => f.write_str("Overflow: all is sub"),
+                Self::NotPositive => f.write_str("the expression must be positive"),
+            }
+        }
+    }
+    impl Error for CheckError {}
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    /// 优化错误，表示合并常量项时发生了溢出
+    pub enum OptimizeError {
+        /// 合并后的帧数超过了 `u64::MAX`
+        FrameIndexOverflow,
+        /// 合并后的时长超过了 `Duration` 的表示范围
+        TimestampOverflow,
+    }
+
+    impl std::fmt::Display for OptimizeError {
+        /// 格式化错误信息
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::FrameIndexOverflow => f.write_str("frame index overflow"),
+                Self::TimestampOverflow => f.write_str("timestamp overflow"),
+            }
+        }
+    }
+    impl Error for OptimizeError {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_parser() {
+        let keywords = [
+            ("end", DSLKeywords::End),
+            ("from", DSLKeywords::From),
+            ("to", DSLKeywords::To),
+        ];
+        for (word, keyword) in keywords {
+            let (_, k) = parse_keyword(word.into()).unwrap();
+            assert_eq!(DSLType::Keyword(keyword), k);
+        }
+        assert!(parse_keyword("hello".into()).is_err());
+    }
+
+    #[test]
+    fn test_frame_parser() {
+        let (_, val) = parse_frame_index("100f".into()).unwrap();
+        match val {
+            DSLType::FrameIndex(v) => assert_eq!(v, 100),
             _ => panic!("Error type"),
         }
         assert!(parse_frame_index("100".into()).is_err());
@@ -812,27 +2550,174 @@ mod tests {
     }
 
     #[test]
-    fn test_timestamp_parser3() {
-        let (_, val) = parse_timestamp3("100ms".into()).unwrap();
-        match val {
-            DSLType::Timestamp(v) => assert_eq!(v, Duration::from_millis(100)),
-            _ => panic!("Error type"),
-        }
-        let (_, val) = parse_timestamp3("114514ms".into()).unwrap();
-        match val {
-            DSLType::Timestamp(v) => assert_eq!(v, Duration::from_millis(114514)),
-            _ => panic!("Error type"),
-        }
-        assert!(parse_timestamp3("100.0ms".into()).is_err());
-        assert!(parse_timestamp3("100d".into()).is_err());
+    fn test_total_constant() {
+        let expr = |input: &str| parse_expr(input.into()).unwrap().1;
+        assert_eq!(
+            expr("end - 5s + 3s").total_constant_duration(25.0),
+            (DSLOp::Sub, Duration::from_secs(2))
+        );
+        assert_eq!(
+            expr("from + 50f - 1s + x").total_constant_duration(25.0),
+            (DSLOp::Add, Duration::from_secs(1))
+        );
+        assert_eq!(
+            expr("end - 1f").total_constant_duration(30000.0 / 1001.0),
+            (DSLOp::Sub, Duration::from_nanos(33_366_667))
+        );
+        assert_eq!(
+            expr("end").total_constant_duration(25.0),
+            (DSLOp::Add, Duration::ZERO)
+        );
+        assert_eq!(expr("end - 5s + 3s").total_constant_frames(), 0);
+        assert_eq!(expr("to - 100f + 30f + 1s").total_constant_frames(), -70);
+        assert_eq!(expr("min(end, 10f) + 5f").total_constant_frames(), 5);
     }
 
     #[test]
-    fn test_item_parser() {
-        let keywords = vec![
-            ("end", DSLKeywords::End),
-            ("from", DSLKeywords::From),
-            ("to", DSLKeywords::To),
+    fn test_timestamp_iso() {
+        for (input, expected) in [
+            ("PT90S", Duration::from_secs(90)),
+            ("PT1M30S", Duration::from_secs(90)),
+            ("PT1H2M3.5S", Duration::from_millis(3_723_500)),
+            ("PT2H", Duration::from_secs(7200)),
+            ("PT1H30S", Duration::from_secs(3630)),
+        ] {
+            let (rest, val) = parse_timestamp_iso(input.into()).unwrap();
+            assert!(rest.is_empty(), "{input}");
+            assert_eq!(val, DSLType::Timestamp(expected), "{input}");
+        }
+        for input in ["PT", "PT1", "PT1S2M", "PTS", "PT1Sx"] {
+            assert!(parse_timestamp_iso(input.into()).is_err(), "{input}");
+        }
+        let (_, expr) = parse_expr("end - PT1M30S".into()).unwrap();
+        assert_eq!(
+            expr.items[1].content,
+            DSLType::Timestamp(Duration::from_secs(90))
+        );
+        assert_eq!(expr.items[1].length, 7);
+        let (_, expr) = parse_expr("PTS".into()).unwrap();
+        assert_eq!(expr.items[0].content, DSLType::Var("PTS".to_string()));
+
+        // 溢出是错误而不是变量名
+        let Err(nom::Err::Failure(err)) = parse_expr("end - PT18446744073709551615H".into()) else {
+            panic!("expected failure");
+        };
+        assert_eq!(err.kind, error::ParseErrorKind::Overflow);
+        let diagnostic = err.to_diagnostic();
+        assert_eq!(diagnostic.message, "timestamp is too large");
+        assert_eq!((diagnostic.offset, diagnostic.length), (6, 23));
+    }
+
+    #[test]
+    fn test_timestamp_comma() {
+        let time = |input: &str| match parse_expr(input.into()).unwrap().1.items[..] {
+            [
+                DSLItem {
+                    content: DSLType::Timestamp(time),
+                    ..
+                },
+            ] => time,
+            ref items => panic!("unexpected items for {input:?}: {items:?}"),
+        };
+        assert_eq!(time("0:01:23,5"), time("0:01:23.5"));
+        assert_eq!(time("00:01:23,500"), Duration::from_millis(83_500));
+        // 函数参数中的 `,` 仍然是分隔符
+        let (_, expr) = parse_expr("min(0:01,5s, 2s)".into()).unwrap();
+        let DSLType::Call { ref args, .. } = expr.items[0].content else {
+            panic!("expected a call");
+        };
+        assert_eq!(args.len(), 3);
+    }
+
+    #[test]
+    fn test_timestamp_parser5() {
+        for (input, expected) in [
+            ("1000000us", Duration::from_secs(1)),
+            ("500000us", Duration::from_millis(500)),
+            ("1us", Duration::from_micros(1)),
+            ("1\u{b5}s", Duration::from_micros(1)),
+            ("1\u{3bc}s", Duration::from_micros(1)),
+        ] {
+            let (rest, val) = parse_timestamp5(input.into()).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(val, DSLType::Timestamp(expected));
+            let (rest, item) = parse_item(input.into()).unwrap();
+            assert!(rest.is_empty());
+            let item = item.unwrap();
+            assert_eq!(item.content, DSLType::Timestamp(expected));
+            assert_eq!(item.length, input.len());
+        }
+        assert!(parse_timestamp5("1ms".into()).is_err());
+        let (_, expr) = parse_expr("end - 2\u{b5}s + 1s".into()).unwrap();
+        assert_eq!(expr.items()[2].offset, 13);
+    }
+
+    #[test]
+    fn test_timestamp_fraction() {
+        for (input, nanos) in [
+            ("1:02.1", 100_000_000),
+            ("1:02.12", 120_000_000),
+            ("1:02.123", 123_000_000),
+        ] {
+            let (rest, val) = parse_timestamp2(input.into()).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(
+                val,
+                DSLType::Timestamp(Duration::new(62, nanos)),
+                "{input:?}"
+            );
+        }
+        let Err(nom::Err::Failure(err)) = parse_timestamp2("1:02.1234".into()) else {
+            panic!("expected failure");
+        };
+        assert_eq!(err.code, nom::error::ErrorKind::TooLarge);
+        assert_eq!(err.input.location_offset(), 9);
+    }
+
+    #[test]
+    fn test_timestamp_parser3() {
+        let (_, val) = parse_timestamp3("100ms".into()).unwrap();
+        match val {
+            DSLType::Timestamp(v) => assert_eq!(v, Duration::from_millis(100)),
+            _ => panic!("Error type"),
+        }
+        let (_, val) = parse_timestamp3("114514ms".into()).unwrap();
+        match val {
+            DSLType::Timestamp(v) => assert_eq!(v, Duration::from_millis(114514)),
+            _ => panic!("Error type"),
+        }
+        assert!(parse_timestamp3("100.0ms".into()).is_err());
+        assert!(parse_timestamp3("100d".into()).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_parser4() {
+        for (input, expected) in [
+            ("1000000000ns", Duration::from_secs(1)),
+            ("500000000ns", Duration::from_millis(500)),
+            ("1ns", Duration::from_nanos(1)),
+        ] {
+            let (rest, val) = parse_timestamp4(input.into()).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(val, DSLType::Timestamp(expected));
+            let (_, item) = parse_item(input.into()).unwrap();
+            assert_eq!(item.unwrap().content, DSLType::Timestamp(expected));
+        }
+        assert!(parse_timestamp4("1ms".into()).is_err());
+        let (rest, item) = parse_item("1ms".into()).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            item.unwrap().content,
+            DSLType::Timestamp(Duration::from_millis(1))
+        );
+    }
+
+    #[test]
+    fn test_item_parser() {
+        let keywords = [
+            ("end", DSLKeywords::End),
+            ("from", DSLKeywords::From),
+            ("to", DSLKeywords::To),
         ];
         for (word, keyword) in keywords {
             let (_, k) = parse_item(word.into()).unwrap();
@@ -893,16 +2778,106 @@ mod tests {
             _ => panic!("Error type"),
         }
 
-        assert!(parse_item("hello".into()).is_err());
+        let (_, val) = parse_item("hello".into()).unwrap();
+        assert_eq!(val.unwrap().content, DSLType::Var("hello".to_string()));
+        assert!(parse_item("+".into()).is_err());
         assert!(parse_item("100".into()).is_err());
         assert!(parse_item("100d".into()).is_err());
         assert!(parse_item("1:2:3:4".into()).is_err());
     }
 
+    #[test]
+    fn test_fraction_digits_limit() {
+        // 与 `Time::from_str` 一致，小数部分最多3位（毫秒）
+        for input in ["1:2.1", "1:2.12", "1:2.123"] {
+            let dsl = parse_timestamp2(input.into()).unwrap().1;
+            let DSLType::Timestamp(dsl) = dsl else {
+                panic!("expected a timestamp for {input:?}");
+            };
+            let Ok(crate::Time::Time(plain)) = input.parse::<crate::Time>() else {
+                panic!("expected a time for {input:?}");
+            };
+            assert_eq!(dsl, plain, "{input:?}");
+        }
+        assert!("1:2.1234".parse::<crate::Time>().is_err());
+        let Err(nom::Err::Failure(err)) = parse_expr("end - 1:2.1234".into()) else {
+            panic!("expected failure");
+        };
+        let diagnostic = err.to_diagnostic();
+        assert_eq!(
+            diagnostic.message,
+            "too many fractional digits, at most 3 are supported"
+        );
+        assert_eq!((diagnostic.offset, diagnostic.length), (6, 8));
+        // ISO 8601 时长仍然精确到纳秒
+        assert!(parse_expr("PT1.123456789S".into()).is_ok());
+    }
+
+    #[test]
+    fn test_timestamp_overflow() {
+        let (_, val) = parse_timestamp2("5124095576030431:0:15".into()).unwrap();
+        assert_eq!(val, DSLType::Timestamp(Duration::from_secs(u64::MAX)));
+
+        for (input, offset) in [("9999999999999999:0:0", 0), ("1:999999999999999999:0", 2)] {
+            let Err(nom::Err::Failure(err)) = parse_timestamp2(input.into()) else {
+                panic!("expected failure for {input:?}");
+            };
+            assert_eq!(err.input.location_offset(), offset, "{input:?}");
+        }
+
+        let Err(nom::Err::Failure(err)) = parse_expr("end - 9999999999999999:0:0".into()) else {
+            panic!("expected failure");
+        };
+        assert_eq!(err.kind, error::ParseErrorKind::Overflow);
+        let diagnostic = err.to_diagnostic();
+        assert_eq!(diagnostic.message, "timestamp is too large");
+        assert_eq!((diagnostic.offset, diagnostic.length), (6, 16));
+
+        // 秒数超出 `Duration` 的范围
+        let Err(nom::Err::Failure(err)) = parse_timestamp1("18446744073709551615.5s".into()) else {
+            panic!("expected failure");
+        };
+        assert_eq!(err.code, nom::error::ErrorKind::MapRes);
+        for input in ["18446744073709551615s", "end - 18446744073709551615.5s"] {
+            let Err(nom::Err::Failure(err)) = parse_expr(input.into()) else {
+                panic!("expected failure for {input:?}");
+            };
+            assert_eq!(err.kind, error::ParseErrorKind::Overflow, "{input:?}");
+            let diagnostic = err.to_diagnostic();
+            assert_eq!(diagnostic.message, "timestamp is too large", "{input:?}");
+            assert_eq!(
+                diagnostic.offset + diagnostic.length,
+                input.len(),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_expr_item_limit() {
+        let long = vec!["1f"; MAX_EXPR_ITEMS].join(" + ");
+        assert_eq!(
+            parse_expr(long.as_str().into()).unwrap().1.items.len(),
+            MAX_EXPR_ITEMS
+        );
+        let too_long = format!("{long} + 1f");
+        let Err(nom::Err::Failure(err)) = parse_expr(too_long.as_str().into()) else {
+            panic!("expected failure");
+        };
+        assert_eq!(err.kind, error::ParseErrorKind::TooManyItems);
+        assert_eq!(err.to_diagnostic().offset, long.len() + 3);
+
+        // 函数参数中的项同样计入
+        assert!(parse_expr_with_limit("min(1f, 2f)".into(), 3).is_ok());
+        assert!(parse_expr_with_limit("min(1f, 2f)".into(), 2).is_err());
+        let nested = format!("{}1f{}", "clamp(".repeat(10_000), ")".repeat(10_000));
+        assert!(parse_expr(nested.as_str().into()).is_err());
+    }
+
     #[test]
     fn test_expr_parser() {
         let (_, expr) = parse_expr("end + from - to + 1f - 2s + 3ms - 4:5".into()).unwrap();
-        let items = vec![
+        let items = [
             DSLType::Keyword(DSLKeywords::End),
             DSLType::Keyword(DSLKeywords::From),
             DSLType::Keyword(DSLKeywords::To),
@@ -917,6 +2892,7 @@ mod tests {
         assert_eq!(
             expr.ops,
             vec![
+                DSLOp::Add,
                 DSLOp::Add,
                 DSLOp::Sub,
                 DSLOp::Add,
@@ -930,22 +2906,909 @@ mod tests {
 
     #[test]
     fn test_expr_opt() {
-        // end + from - to + 1f - 246.997s
-        let (_, mut expr) = parse_expr("end + from - to + 1f - 2s + 3ms - 4:5".into()).unwrap();
-        optimize_expr(&mut expr);
-        let items = vec![
-            DSLType::Keyword(DSLKeywords::End),
-            DSLType::Keyword(DSLKeywords::From),
-            DSLType::Keyword(DSLKeywords::To),
-            DSLType::FrameIndex(1),
-            DSLType::Timestamp(Duration::from_secs(247) - Duration::from_millis(3)),
-        ];
-        for (item, expr_item) in items.iter().zip(expr.items.iter()) {
-            assert_eq!(expr_item, item);
+        let mut expr = parse_expr_str("end + from - to + 1f - 2s + 3ms - 4:5").unwrap();
+        optimize_expr(&mut expr).unwrap();
+        let expected = parse_expr_str("end + from - to + 1f - 246.997s").unwrap();
+        assert_eq!(expr, expected);
+        // 不同的写法合并后相同
+        let mut split = parse_expr_str("end - 1s - 500ms").unwrap();
+        optimize_expr(&mut split).unwrap();
+        assert_eq!(split, parse_expr_str("end - 1.5s").unwrap());
+    }
+
+    #[test]
+    fn test_expr_comment() {
+        let (_, expr) = parse_expr("end - 30s # skip credits".into()).unwrap();
+        let (_, plain) = parse_expr("end - 30s".into()).unwrap();
+        assert_eq!(expr, plain);
+
+        let (input, expr) = parse_expr("5s # hi + 3s".into()).unwrap();
+        assert!(input.is_empty());
+        assert_eq!(
+            expr.items,
+            vec![DSLType::Timestamp(Duration::from_secs_f64(5f64))]
+        );
+        assert_eq!(expr.ops, vec![DSLOp::Add]);
+
+        let (_, expr) = parse_expr("end # note\n".into()).unwrap();
+        assert_eq!(expr.items, vec![DSLType::Keyword(DSLKeywords::End)]);
+
+        let Err(nom::Err::Error(err) | nom::Err::Failure(err)) =
+            parse_expr("end - 1d # comment".into())
+        else {
+            panic!("expected error");
+        };
+        assert_eq!(err.offset, 6);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_round_trip() {
+        fn round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+            serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
         }
+        let timestamp = DSLType::Timestamp(Duration::new(1, 500));
         assert_eq!(
-            expr.ops,
-            vec![DSLOp::Add, DSLOp::Add, DSLOp::Sub, DSLOp::Add, DSLOp::Sub,]
+            serde_json::to_string(&timestamp).unwrap(),
+            r#"{"Timestamp":1000000500}"#
+        );
+        for item in [
+            timestamp,
+            DSLType::FrameIndex(100),
+            DSLType::Var("intro".to_string()),
+            DSLType::Keyword(DSLKeywords::Start),
+        ] {
+            assert_eq!(round_trip(&item), item);
+        }
+        for keyword in DSLKeywords::all() {
+            assert_eq!(round_trip(keyword), *keyword);
+        }
+        for op in [DSLOp::Add, DSLOp::Sub] {
+            assert_eq!(round_trip(&op), op);
+        }
+
+        let (_, expr) = parse_expr("min(end, from + 30s) - 1.5s + 10f".into()).unwrap();
+        assert_eq!(round_trip(&expr), expr);
+        let checked = "end - 2s - 3s".parse::<CheckedExpr>().unwrap();
+        let copy = round_trip(&checked);
+        assert_eq!(copy.to_string(), checked.to_string());
+        assert_eq!(
+            copy.spanned_terms().collect::<Vec<_>>(),
+            checked.spanned_terms().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_timecode_parser() {
+        let timecode = crate::Timecode {
+            hours: 0,
+            minutes: 10,
+            seconds: 0,
+            frames: 2,
+        };
+        let (rest, item) = parse_item("00:10:00;02 + 1s".into()).unwrap();
+        assert_eq!(rest.fragment(), &" + 1s");
+        let item = item.unwrap();
+        assert_eq!(item.content, DSLType::Timecode(timecode));
+        assert_eq!((item.offset, item.length), (0, 11));
+        assert_eq!(item.content.to_string(), "00:10:00;02");
+        assert_eq!(
+            "end - 00:10:00;02"
+                .parse::<CheckedExpr>()
+                .unwrap()
+                .to_string(),
+            "end - 00:10:00;02"
+        );
+        // 没有 `;` 时仍按时:分:秒解析
+        assert_eq!(
+            parse_item("00:10:00".into()).unwrap().1.unwrap().content,
+            DSLType::Timestamp(Duration::from_secs(600))
+        );
+    }
+
+    #[test]
+    fn test_type_from_str() {
+        assert_eq!("100f".parse::<DSLType>(), Ok(DSLType::FrameIndex(100)));
+        assert_eq!(
+            "5s".parse::<DSLType>(),
+            Ok(DSLType::Timestamp(Duration::from_secs(5)))
+        );
+        assert_eq!(
+            " end ".parse::<DSLType>(),
+            Ok(DSLType::Keyword(DSLKeywords::End))
+        );
+        assert!("".parse::<DSLType>().is_err());
+        assert!("5s + 1s".parse::<DSLType>().is_err());
+        assert!("5x".parse::<DSLType>().is_err());
+
+        for input in [
+            "100f",
+            "5s",
+            "1.5s",
+            "100ms",
+            "250us",
+            "10ns",
+            "1:30",
+            "1:02:03.5",
+            "PT1M30.5S",
+            "end",
+            "duration",
+            "start",
+            "from",
+            "to",
+            "intro",
+            "min(end, from + 30s)",
+        ] {
+            let item = input.parse::<DSLType>().unwrap();
+            let text = item.to_string();
+            assert_eq!(text.parse::<DSLType>(), Ok(item), "{input} -> {text}");
+        }
+    }
+
+    #[test]
+    fn test_keyword_all() {
+        let all = DSLKeywords::all();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all.iter().collect::<HashSet<_>>().len(), all.len());
+        assert_eq!(DSLKeywords::all_tokens(), ["end", "start", "from", "to"]);
+    }
+
+    #[test]
+    fn test_keyword_alias() {
+        for input in ["end", "dur", "duration"] {
+            let (rest, item) = parse_identifier(input.into()).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(item, DSLType::Keyword(DSLKeywords::End));
+            let (rest, item) = parse_keyword(input.into()).unwrap();
+            assert!(rest.is_empty(), "{input:?}");
+            assert_eq!(item, DSLType::Keyword(DSLKeywords::End));
+        }
+        assert!(!is_var_name("duration"));
+        assert!(is_var_name("durations"));
+        let (_, expr) = parse_expr("duration - 5s".into()).unwrap();
+        assert_eq!(expr.to_string(), "end - 5s");
+        let (_, expr) = parse_expr("begin + 5s".into()).unwrap();
+        assert_eq!(expr.to_string(), "start + 5s");
+        assert!(!is_var_name("start"));
+        assert!(is_var_name("started"));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        for input in [
+            "end - 30s # skip\n+ from",
+            "min(end, from + 30s)// limit",
+            "end ? 5s + 1d",
+            "  \t\n",
+            "1:02:03 - 10f+intro",
+            "",
+        ] {
+            let tokens = tokenize(input.into())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let text = tokens
+                .iter()
+                .map(|token| &input[token.offset..token.offset + token.length])
+                .collect::<String>();
+            assert_eq!(text, input);
+        }
+
+        let kinds = tokenize("end - 30s # skip\n".into())
+            .map(|token| token.unwrap().content)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Item(DSLType::Keyword(DSLKeywords::End)),
+                TokenKind::Whitespace,
+                TokenKind::Op(DSLOp::Sub),
+                TokenKind::Whitespace,
+                TokenKind::Item(DSLType::Timestamp(Duration::from_secs(30))),
+                TokenKind::Whitespace,
+                TokenKind::Comment,
+                TokenKind::Whitespace,
+            ]
+        );
+
+        let kinds = tokenize("end ? 5s".into())
+            .map(|token| token.unwrap())
+            .filter(|token| token.content == TokenKind::Unknown)
+            .map(|token| (token.offset, token.length))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [(4, 1)]);
+
+        let input = "min(end, from + 30s) - 1f # note";
+        let (_, expr) = parse_expr(input.into()).unwrap();
+        let expected = expr
+            .items
+            .iter()
+            .map(|item| (item.offset, item.length))
+            .chain(
+                expr.ops
+                    .iter()
+                    .filter(|op| op.length > 0)
+                    .map(|op| (op.offset, op.length)),
+            );
+        let mut expected = expected.collect::<Vec<_>>();
+        expected.sort();
+        let tokens = tokenize(input.into())
+            .map(|token| token.unwrap())
+            .filter(|token| matches!(token.content, TokenKind::Item(..) | TokenKind::Op(..)))
+            .map(|token| (token.offset, token.length))
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, expected);
+
+        let mut tokens = tokenize("end + min(end".into());
+        assert!(tokens.by_ref().take(4).all(|token| token.is_ok()));
+        assert!(tokens.next().unwrap().is_err());
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn test_too_many_time_fields() {
+        for (input, length) in [("1:02:03:04", 10), ("1:2:3:4:5.25", 12)] {
+            let Err(nom::Err::Failure(err)) = parse_timestamp2(input.into()) else {
+                panic!("expected failure for {input:?}");
+            };
+            assert_eq!(err.code, nom::error::ErrorKind::Count);
+            assert_eq!(err.input.location_offset(), length);
+        }
+        let Err(nom::Err::Failure(err)) = parse_expr("end - 1:02:03:04 + 1f".into()) else {
+            panic!("expected failure");
+        };
+        assert_eq!((err.offset, err.length), (6, 10));
+    }
+
+    #[test]
+    fn test_check_options() {
+        use error::CheckError;
+
+        let check = |input: &str, options: &CheckOptions| {
+            let (_, expr) = parse_expr(input.into()).unwrap();
+            check_expr_with(&expr, options)
+        };
+
+        let permissive = CheckOptions {
+            max_keyword_occurrences: 2,
+            allow_from_and_to: true,
+            allow_negative_constant: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check("end + end", &CheckOptions::default()).unwrap_err(),
+            CheckError::TooManyKeywords(DSLKeywords::End)
+        );
+        assert!(check("end + end", &permissive).is_ok());
+        assert!(check("from + to", &permissive).is_ok());
+        assert_eq!(
+            check("end + end + end", &permissive).unwrap_err(),
+            CheckError::TooManyKeywords(DSLKeywords::End)
+        );
+
+        let restrictive = CheckOptions {
+            allowed_keywords: [DSLKeywords::End, DSLKeywords::From].into(),
+            allow_empty: false,
+            ..Default::default()
+        };
+        assert!(check("from + 10s", &restrictive).is_ok());
+        assert_eq!(
+            check("to - 10s", &restrictive).unwrap_err(),
+            CheckError::KeywordNotAllowed(DSLKeywords::To)
+        );
+        assert_eq!(
+            check("min(end, to)", &restrictive).unwrap_err(),
+            CheckError::KeywordNotAllowed(DSLKeywords::To)
+        );
+        let (_, expr) = parse_expr("# nothing".into()).unwrap();
+        assert_eq!(
+            check_expr_with(&expr, &restrictive).unwrap_err(),
+            CheckError::Empty
+        );
+        assert!(check_expr(&expr).is_ok());
+
+        let positive = CheckOptions {
+            allowed_keywords: HashSet::new(),
+            require_positive: true,
+            ..Default::default()
+        };
+        assert!(check("30f", &positive).is_ok());
+        assert!(check("1s - 500ms", &positive).is_ok());
+        assert!(check("1s - 10f", &positive).is_ok());
+        assert!(check("step_var + 0f", &positive).is_ok());
+        assert_eq!(check("0f", &positive).unwrap_err(), CheckError::NotPositive);
+        assert_eq!(
+            check("1s - 2s", &positive).unwrap_err(),
+            CheckError::NotPositive
+        );
+        assert_eq!(
+            check("end", &positive).unwrap_err(),
+            CheckError::KeywordNotAllowed(DSLKeywords::End)
+        );
+    }
+
+    #[test]
+    fn test_references_keyword() {
+        let refs = |input: &str| {
+            let (_, expr) = parse_expr(input.into()).unwrap();
+            let checked = check_expr(&expr).unwrap();
+            assert_eq!(expr.references_from(), checked.references_from());
+            assert_eq!(expr.references_to(), checked.references_to());
+            (expr.references_from(), expr.references_to())
+        };
+        assert_eq!(refs("from + 10s"), (true, false));
+        assert_eq!(refs("to - 10s"), (false, true));
+        assert_eq!(refs("min(end, from + 30s)"), (true, false));
+        assert_eq!(refs("end - 10s"), (false, false));
+        assert_eq!(refs("intro + 1f"), (false, false));
+        let (_, expr) = parse_expr("max(from, to)".into()).unwrap();
+        assert!(expr.references_from() && expr.references_to());
+        assert!(check_expr(&expr).is_err());
+    }
+
+    #[test]
+    fn test_lint_expr() {
+        let lints = |input: &str| {
+            let (_, expr) = parse_expr(input.into()).unwrap();
+            lint_expr(&expr)
+                .into_iter()
+                .map(|lint| (lint.content, lint.offset))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lints("from + 100f + 5s"), [(Lint::MixedUnits, 7)]);
+        assert_eq!(lints("min(end, 5s) - 10f"), [(Lint::MixedUnits, 15)]);
+        assert!(lints("from + 100f - 2f").is_empty());
+        assert!(lints("end - 5s").is_empty());
+        assert_eq!(lints("99999999f"), [(Lint::LargeFrameIndex(99_999_999), 0)]);
+        assert_eq!(lints("100f - 5s"), [(Lint::UnanchoredMixedUnits, 0)]);
+        assert_eq!(lints("5s + max(1f, 2s)"), [(Lint::UnanchoredMixedUnits, 9)]);
+        assert_eq!(lints("from + 100f - 5s"), [(Lint::MixedUnits, 7)]);
+        // 变量本身没有基准时不能作为基准
+        assert_eq!(
+            lints("intro + 100f - 5s"),
+            [(Lint::UnanchoredMixedUnits, 8)]
         );
+        assert!(lints("100f - 50f").is_empty());
+
+        let (_, expr) = parse_expr("100f - 5s".into()).unwrap();
+        let relative = CheckOptions {
+            positional: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            lint_expr_with(&expr, &relative)
+                .into_iter()
+                .map(|lint| lint.content)
+                .collect::<Vec<_>>(),
+            [Lint::MixedUnits]
+        );
+
+        let checked = |input: &str| check_expr(&parse_expr(input.into()).unwrap().1).unwrap();
+        let vars = HashMap::from([
+            ("intro".to_string(), checked("end - 30s")),
+            ("outro".to_string(), checked("intro + 10s")),
+            ("gap".to_string(), checked("5s")),
+        ]);
+        let anchored = anchored_vars(&vars);
+        assert_eq!(
+            anchored,
+            HashSet::from(["intro".to_string(), "outro".to_string()])
+        );
+        let options = CheckOptions {
+            anchored_vars: anchored,
+            ..Default::default()
+        };
+        for (input, expected) in [
+            ("outro + 100f - 5s", Lint::MixedUnits),
+            ("gap + 100f - 5s", Lint::UnanchoredMixedUnits),
+        ] {
+            let (_, expr) = parse_expr(input.into()).unwrap();
+            assert_eq!(
+                lint_expr_with(&expr, &options)
+                    .into_iter()
+                    .map(|lint| lint.content)
+                    .collect::<Vec<_>>(),
+                [expected]
+            );
+        }
+    }
+
+    #[test]
+    fn test_line_comment() {
+        let (_, plain) = parse_expr("from + 5s".into()).unwrap();
+        for input in [
+            "from + 5s # skip intro",
+            "from + 5s // skip intro",
+            "from # start\n+ 5s # skip intro\n",
+            "// start\nfrom\n  + 5s",
+        ] {
+            let (rest, expr) = parse_expr(input.into()).unwrap();
+            assert!(rest.is_empty(), "{input:?}");
+            assert_eq!(expr.items, plain.items, "{input:?}");
+            assert_eq!(expr.ops, plain.ops, "{input:?}");
+        }
+
+        let Err(nom::Err::Error(err) | nom::Err::Failure(err)) =
+            parse_expr("end # comment\n- 1d".into())
+        else {
+            panic!("expected error");
+        };
+        assert_eq!(err.offset, 16);
+    }
+
+    #[test]
+    fn test_type_display() {
+        assert_eq!(DSLType::FrameIndex(100).to_string(), "100f");
+        assert_eq!(
+            DSLType::Timestamp(Duration::from_millis(2500)).to_string(),
+            "2.5s"
+        );
+        assert_eq!(
+            DSLType::Timestamp(Duration::from_secs(62)).to_string(),
+            "62s"
+        );
+        assert_eq!(
+            DSLType::Timestamp(Duration::from_millis(3)).to_string(),
+            "0.003s"
+        );
+        assert_eq!(DSLType::Keyword(DSLKeywords::End).to_string(), "end");
+        assert_eq!(DSLType::Keyword(DSLKeywords::From).to_string(), "from");
+        assert_eq!(DSLType::Keyword(DSLKeywords::To).to_string(), "to");
+        assert_eq!(DSLOp::Add.to_string(), "+");
+        assert_eq!(DSLOp::Sub.to_string(), "-");
+    }
+
+    #[test]
+    fn test_op_from_char() {
+        assert_eq!(DSLOp::from_char('+'), Some(DSLOp::Add));
+        assert_eq!(DSLOp::from_char('-'), Some(DSLOp::Sub));
+        assert_eq!(DSLOp::try_from('+'), Ok(DSLOp::Add));
+        assert_eq!(DSLOp::try_from('-'), Ok(DSLOp::Sub));
+        for op in [DSLOp::Add, DSLOp::Sub] {
+            let c = op.to_string().chars().next().unwrap();
+            assert_eq!(DSLOp::from_char(c), Some(op));
+        }
+        for c in ['*', '/', '−', ' ', 'a', '0'] {
+            assert_eq!(DSLOp::from_char(c), None);
+            assert_eq!(DSLOp::try_from(c), Err(format!("unknown operator: '{c}'")));
+        }
+    }
+
+    #[test]
+    fn test_op_apply() {
+        assert_eq!(DSLOp::Add.apply(100, 50), 150);
+        assert_eq!(DSLOp::Sub.apply(50, 100), -50);
+        assert_eq!(DSLOp::Add.apply_saturating(i64::MAX - 1, 10), i64::MAX);
+        assert_eq!(DSLOp::Sub.apply_saturating(i64::MIN + 1, 10), i64::MIN);
+        assert_eq!(DSLOp::Sub.apply_saturating(50, 100), -50);
+        assert_eq!(DSLOp::Add.apply_checked(i64::MAX - 1, 10), None);
+        assert_eq!(DSLOp::Sub.apply_checked(50, 100), Some(-50));
+    }
+
+    #[test]
+    fn test_expr_opt_fold() {
+        let optimized = |input: &str| {
+            let (_, mut expr) = parse_expr(input.into()).unwrap();
+            optimize_expr(&mut expr).unwrap();
+            expr.to_string()
+        };
+        assert_eq!(optimized("100us + 200us"), "0.0003s");
+        assert_eq!(optimized("end - 100us + 300us"), "end + 0.0002s");
+        assert_eq!(optimized("10f + 1s + 5f - 1500ms"), "15f - 0.5s");
+        assert_eq!(optimized("from - 2f + x + 2f"), "from + 0f + x");
+        assert_eq!(optimized("max(1s + 2s, 3f - 1f)"), "max(3s, 2f)");
+    }
+
+    #[test]
+    fn test_parse_str() {
+        let expr = parse_expr_str("end - 30s # credits").unwrap();
+        assert_eq!(expr, parse_expr("end - 30s".into()).unwrap().1);
+        assert_eq!(expr.to_string(), "end - 30s");
+        assert!(parse_expr_str("").unwrap().is_empty());
+        let err = parse_expr_str("end - 1d").unwrap_err();
+        assert!(!err.is_empty());
+
+        assert_eq!(parse_item_str(" 100f "), Ok(Some(DSLType::FrameIndex(100))));
+        assert_eq!(parse_item_str("  # nothing"), Ok(None));
+        assert_eq!(
+            parse_item_str("100f 5s"),
+            Err("unexpected `5s` after the item".to_string())
+        );
+        assert!(parse_item_str("+").is_err());
+    }
+
+    #[test]
+    fn test_expr_clone() {
+        let mut expr = parse_expr_str("10f + 5f - 1s").unwrap();
+        let original = expr.clone();
+        assert_eq!(original, expr);
+        optimize_expr(&mut expr).unwrap();
+        assert_ne!(original, expr);
+        assert_eq!(original.to_string(), "10f + 5f - 1s");
+
+        let checked: CheckedExpr = "end - 2s + x".parse().unwrap();
+        assert_eq!(checked.clone(), checked);
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = DSLType::FrameIndex(u64::MAX);
+        assert_eq!(max.add_checked(&DSLType::FrameIndex(1)), None);
+        assert_eq!(
+            max.add_checked(&DSLType::FrameIndex(0)),
+            Some(DSLType::FrameIndex(u64::MAX))
+        );
+        assert_eq!(
+            DSLType::FrameIndex(u64::MAX - 1).add_checked(&DSLType::FrameIndex(1)),
+            Some(max.clone())
+        );
+        assert_eq!(
+            max.sub_checked(&DSLType::FrameIndex(u64::MAX)),
+            Some(DSLType::FrameIndex(0))
+        );
+        assert_eq!(
+            DSLType::FrameIndex(1).sub_checked(&DSLType::FrameIndex(2)),
+            None
+        );
+
+        let secs = |s| DSLType::Timestamp(Duration::from_secs(s));
+        assert_eq!(secs(1).add_checked(&secs(2)), Some(secs(3)));
+        assert_eq!(secs(3).sub_checked(&secs(1)), Some(secs(2)));
+        assert_eq!(
+            DSLType::Timestamp(Duration::MAX).add_checked(&DSLType::Timestamp(Duration::MAX)),
+            None
+        );
+
+        assert_eq!(secs(1).add_checked(&DSLType::FrameIndex(1)), None);
+        let end = DSLType::Keyword(DSLKeywords::End);
+        assert_eq!(end.add_checked(&end), None);
+        assert_eq!(end.sub_checked(&end), None);
+    }
+
+    #[test]
+    fn test_expr_opt_overflow() {
+        let max = u64::MAX;
+        let (_, mut expr) = parse_expr(format!("{max}f + 1f").as_str().into()).unwrap();
+        assert_eq!(
+            optimize_expr(&mut expr),
+            Err(error::OptimizeError::FrameIndexOverflow)
+        );
+
+        let (_, mut expr) = parse_expr(format!("{max}f - 1f").as_str().into()).unwrap();
+        assert_eq!(optimize_expr(&mut expr), Ok(()));
+        assert_eq!(expr.items[0].content, DSLType::FrameIndex(max - 1));
+
+        let (_, mut expr) = parse_expr("1s + 1s".into()).unwrap();
+        expr.items[0].set(DSLType::Timestamp(Duration::MAX));
+        assert_eq!(
+            optimize_expr(&mut expr),
+            Err(error::OptimizeError::TimestampOverflow)
+        );
+        assert_eq!(
+            format!("end - min({max}f + 1f, 1f)")
+                .parse::<CheckedExpr>()
+                .unwrap_err(),
+            error::ExprError::Optimize(error::OptimizeError::FrameIndexOverflow)
+        );
+    }
+
+    #[test]
+    fn test_expr_display() {
+        let (_, expr) = parse_expr("1f + 2s - 1:2.5".into()).unwrap();
+        assert_eq!(expr.to_string(), "1f + 2s - 62.5s");
+
+        let (_, mut expr) = parse_expr("end - 1f - 2s + 3ms - 4:5".into()).unwrap();
+        optimize_expr(&mut expr).unwrap();
+        let checked = check_expr(&expr).unwrap();
+        let text = checked.to_string();
+        assert_eq!(text, "end - 1f - 246.997s");
+
+        let (_, reparsed) = parse_expr(text.as_str().into()).unwrap();
+        assert!(reparsed.terms().eq(checked.terms()));
+
+        // 以减法开头的表达式输出后可以重新解析，结果不变
+        fn item<T: Debug>(content: T, offset: usize) -> DSLItem<T> {
+            DSLItem {
+                content,
+                offset,
+                length: 1,
+            }
+        }
+        for (first, zero) in [
+            (DSLType::Timestamp(Duration::from_secs(5)), "0s - 5s"),
+            (DSLType::FrameIndex(5), "0f - 5f"),
+        ] {
+            let mut expr = Expr::default();
+            expr.push(item(DSLOp::Sub, 0), item(first, 0));
+            expr.push(
+                item(DSLOp::Add, 2),
+                item(DSLType::Keyword(DSLKeywords::End), 4),
+            );
+            let text = expr.to_string();
+            assert_eq!(text, format!("{zero} + end"));
+            let reparsed = parse_expr_str(&text).unwrap();
+            assert_eq!(reparsed.to_string(), text);
+            let mut expected = expr.clone();
+            let mut reparsed = reparsed;
+            optimize_expr(&mut expected).unwrap();
+            optimize_expr(&mut reparsed).unwrap();
+            assert!(reparsed.terms().eq(expected.terms()), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_expr_terms() {
+        // 未优化的表达式每个项也有对应的操作符，检查时不会丢掉最后一个项
+        let (_, expr) = parse_expr("end + end".into()).unwrap();
+        assert_eq!(expr.items().len(), expr.ops().len());
+        assert_eq!(expr.terms().count(), 2);
+        assert!(check_expr(&expr).is_err());
+
+        let mut expr = Expr::default();
+        assert!(expr.is_empty());
+        fn item<T: Debug>(content: T, offset: usize) -> DSLItem<T> {
+            DSLItem {
+                content,
+                offset,
+                length: 1,
+            }
+        }
+        expr.push(item(DSLOp::Add, 0), item(DSLType::FrameIndex(1), 0));
+        expr.push(
+            item(DSLOp::Sub, 2),
+            item(DSLType::Keyword(DSLKeywords::End), 4),
+        );
+        assert_eq!(
+            expr.iter_terms()
+                .map(|(op, item)| (op.content, item.offset))
+                .collect::<Vec<_>>(),
+            [(DSLOp::Add, 0), (DSLOp::Sub, 4)]
+        );
+        assert_eq!(expr.to_string(), "1f - end");
+    }
+
+    #[test]
+    fn test_checked_expr_from_str() {
+        let expr = "end - 5s".parse::<CheckedExpr>().unwrap();
+        assert_eq!(
+            expr.spanned_terms().collect::<Vec<_>>(),
+            vec![
+                (
+                    DSLOp::Add,
+                    &DSLType::Keyword(DSLKeywords::End),
+                    SourceSpan {
+                        offset: 0,
+                        length: 3
+                    }
+                ),
+                (
+                    DSLOp::Sub,
+                    &DSLType::Timestamp(Duration::from_secs(5)),
+                    SourceSpan {
+                        offset: 6,
+                        length: 2
+                    }
+                )
+            ]
+        );
+
+        match "end - 1d".parse::<CheckedExpr>() {
+            Err(error::ExprError::Syntax { offset, .. }) => assert_eq!(offset, 6),
+            res => panic!("unexpected result: {res:?}"),
+        }
+        assert!(matches!(
+            "end + end".parse::<CheckedExpr>(),
+            Err(error::ExprError::Check(error::CheckError::TooManyKeywords(
+                DSLKeywords::End
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_var_parser() {
+        let (_, expr) = parse_expr("intro + 20s - end_credits".into()).unwrap();
+        assert_eq!(
+            expr.items,
+            vec![
+                DSLType::Var("intro".to_string()),
+                DSLType::Timestamp(Duration::from_secs(20)),
+                DSLType::Var("end_credits".to_string()),
+            ]
+        );
+        let (_, expr) = parse_expr("ending".into()).unwrap();
+        assert_eq!(expr.items, vec![DSLType::Var("ending".to_string())]);
+
+        assert!(is_var_name("intro"));
+        assert!(is_var_name("_a1"));
+        assert!(!is_var_name("end"));
+        assert!(!is_var_name("1a"));
+        assert!(!is_var_name("a-b"));
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let mut deps = HashMap::new();
+        deps.insert("from", vec!["intro"]);
+        deps.insert("to", vec!["from"]);
+        deps.insert("intro", vec![]);
+        assert_eq!(find_cycle(&deps), None);
+
+        deps.insert("intro", vec!["to"]);
+        assert_eq!(find_cycle(&deps), Some(vec!["from", "intro", "to", "from"]));
+
+        deps.clear();
+        deps.insert("a", vec!["a"]);
+        assert_eq!(find_cycle(&deps), Some(vec!["a", "a"]));
+    }
+
+    #[test]
+    fn test_multiline_expr() {
+        let (input, expr) = parse_expr("end\n  - 5s\n  + 1f".into()).unwrap();
+        assert!(input.is_empty());
+        assert_eq!(
+            expr.items,
+            vec![
+                DSLType::Keyword(DSLKeywords::End),
+                DSLType::Timestamp(Duration::from_secs(5)),
+                DSLType::FrameIndex(1),
+            ]
+        );
+        assert_eq!(expr.items[2].offset, 15);
+    }
+
+    #[test]
+    fn test_call_parser() {
+        let (_, expr) = parse_expr("min(end, from + 30s) - 1f".into()).unwrap();
+        assert_eq!(expr.items.len(), 2);
+        let DSLType::Call { ref name, ref args } = expr.items[0].content else {
+            panic!("Error type");
+        };
+        assert_eq!(name, "min");
+        assert_eq!(args.len(), 2);
+        assert_eq!(args[0].items, vec![DSLType::Keyword(DSLKeywords::End)]);
+        assert_eq!(
+            args[1].items,
+            vec![
+                DSLType::Keyword(DSLKeywords::From),
+                DSLType::Timestamp(Duration::from_secs(30)),
+            ]
+        );
+        assert_eq!(expr.to_string(), "min(end, from + 30s) - 1f");
+
+        let (_, expr) = parse_expr("max( 1s , max(2s, 3s) )".into()).unwrap();
+        assert_eq!(expr.to_string(), "max(1s, max(2s, 3s))");
+
+        let checked = "max(end - 5s, from)".parse::<CheckedExpr>().unwrap();
+        assert_eq!(checked.references().collect::<Vec<_>>(), vec!["from"]);
+    }
+
+    #[test]
+    fn test_call_error() {
+        let Err(nom::Err::Failure(err)) = parse_expr("end - mni(1s, 2s)".into()) else {
+            panic!("expected error");
+        };
+        assert_eq!(err.kind, error::ParseErrorKind::Function);
+        assert_eq!(err.source.code, nom::error::ErrorKind::Tag);
+        assert_eq!((err.offset, err.length), (6, 3));
+
+        let Err(nom::Err::Failure(err)) = parse_expr("min(1s)".into()) else {
+            panic!("expected error");
+        };
+        assert_eq!(err.source.code, nom::error::ErrorKind::ManyMN);
+        let Err(nom::Err::Failure(err)) = parse_expr("clamp(1s, 2s)".into()) else {
+            panic!("expected error");
+        };
+        assert_eq!(err.source.code, nom::error::ErrorKind::ManyMN);
+        assert!(parse_expr("clamp(end + 10s)".into()).is_ok());
+        let Err(nom::Err::Failure(err)) = parse_expr("min(1s, )".into()) else {
+            panic!("expected error");
+        };
+        assert_eq!(err.source.code, nom::error::ErrorKind::Verify);
+        let Err(nom::Err::Failure(err)) = parse_expr("min(1s, 2s".into()) else {
+            panic!("expected error");
+        };
+        assert_eq!(err.source.code, nom::error::ErrorKind::Char);
+    }
+
+    #[test]
+    fn test_plain_error() {
+        fn error(input: &str) -> error::ParseError<nom::error::Error<Span<'_>>> {
+            match parse_expr_complete(input.into()) {
+                Err(nom::Err::Error(err) | nom::Err::Failure(err)) => err,
+                res => panic!("unexpected result: {res:?}"),
+            }
+        }
+
+        let err = error("end - 10x");
+        assert_eq!(err.summary(), "1:9: invalid token: `x`");
+        assert_eq!(
+            err.render_plain(),
+            "error: invalid token: `x`\n  \
+             --> 1:9\n   \
+             |\n \
+             1 | end - 10x\n   \
+             |         ^ invalid token\n"
+        );
+        assert_eq!(err.to_string(), "in 1:7(length 2): error Tag at: x");
+
+        let err = error("end\n  10s");
+        assert_eq!(
+            err.render_plain(),
+            "error: missing operation, expected `+` or `-`\n  \
+             --> 2:3\n   \
+             |\n \
+             2 |   10s\n   \
+             |   ^ here\n"
+        );
+
+        let err = error("  # nothing");
+        assert_eq!(err.summary(), "1:1: empty expression");
+
+        let err = error("end - mni(1s, 2s)");
+        assert_eq!(
+            err.render_plain(),
+            "error: unknown function: `mni`\n  \
+             --> 1:7\n   \
+             |\n \
+             1 | end - mni(1s, 2s)\n   \
+             |       ^^^ unknown function\n   \
+             |\n   \
+             = help: did you mean `min`?\n"
+        );
+        assert_eq!(
+            error("clamp(1s, 2s)").summary(),
+            "1:1: function `clamp` expects exactly one argument"
+        );
+        assert_eq!(error("min(1s, )").summary(), "1:9: missing argument");
+
+        let err = error("end - 1:02:03:04");
+        assert_eq!(
+            err.to_diagnostic().help.as_deref(),
+            Some(
+                "drop the extra fields, e.g. `1:02:03`; timecodes with frames (hh:mm:ss:ff) are not supported"
+            )
+        );
+        assert_eq!(
+            error("end 5s").summary(),
+            "1:5: missing operation, expected `+` or `-`"
+        );
+        assert_eq!(error("end - ").summary(), "1:5: escaped operation: `-`");
+        assert!(
+            error("片头 ＋ 5s")
+                .render_plain()
+                .contains(" 1 | 片头 ＋ 5s\n   | ^^ unknown keyword\n")
+        );
+    }
+
+    #[test]
+    fn test_error_source_chain() {
+        use std::error::Error;
+
+        let Err(nom::Err::Failure(err)) = parse_expr("end - mni(1s, 2s)".into()) else {
+            panic!("expected error");
+        };
+        let err: Box<dyn Error + Send + Sync> = Box::new(err.into_owned());
+        let mut source = err.source();
+        let inner = loop {
+            let err = source.expect("nom error in source chain");
+            if let Some(inner) = err.downcast_ref::<nom::error::Error<String>>() {
+                break inner;
+            }
+            source = err.source();
+        };
+        assert_eq!(inner.code, nom::error::ErrorKind::Tag);
+        assert_eq!(inner.input, "(1s, 2s)");
+    }
+
+    #[test]
+    fn test_empty_expr() {
+        for input in ["", "   ", "\t\n", "  # only a comment"] {
+            let Err(nom::Err::Failure(err)) = parse_expr_complete(input.into()) else {
+                panic!("expected error for {input:?}");
+            };
+            assert_eq!(err.kind, error::ParseErrorKind::Empty);
+            assert_eq!(err.offset, 0);
+        }
+        let (_, expr) = parse_expr_complete("0f".into()).unwrap();
+        assert_eq!(expr.items, vec![DSLType::FrameIndex(0)]);
     }
 }
@@ -1,13 +1,27 @@
+mod config;
+pub mod format;
 #[cfg(feature = "dsl")]
-mod lexer;
+pub mod lexer;
 #[cfg(feature = "dsl")]
 mod tui;
 
-use clap::Parser;
-use std::{ffi::CString, os::raw::c_char, time::Duration};
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, ValueHint, parser::ValueSource};
+#[cfg(feature = "dsl")]
+use nom::Input;
+#[cfg(feature = "dsl")]
+use std::collections::HashMap;
+#[cfg(feature = "dsl")]
+use std::rc::Rc;
+use std::{
+    ffi::{CStr, CString, c_int, c_void},
+    os::raw::c_char,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 const AV_NOPTS_VALUE: i64 = i64::MIN;
 
+/// 创建视频信息，参数无效时返回空指针，可以通过 `video_info_last_error` 获取原因
 #[unsafe(no_mangle)]
 pub extern "C" fn create_video_info(
     fps: f64,
@@ -16,439 +30,5508 @@ pub extern "C" fn create_video_info(
     start_time: i64,
     duration: i64,
 ) -> *mut VideoInfo {
-    Box::into_raw(Box::new(VideoInfo {
+    create_video_info_extended(fps, time_base_den, time_base_num, start_time, duration, 1)
+}
+
+/// 与 `create_video_info` 相同，但额外记录视频流的数量
+#[unsafe(no_mangle)]
+pub extern "C" fn create_video_info_extended(
+    fps: f64,
+    time_base_den: i64,
+    time_base_num: i64,
+    start_time: i64,
+    duration: i64,
+    stream_count: u32,
+) -> *mut VideoInfo {
+    new_video_info(VideoInfo {
         fps,
+        fps_rational: None,
+        rounding: Rounding::default(),
         duration,
         start_time,
         time_base_den,
         time_base_num,
-    }))
+        stream_count,
+        nb_frames: None,
+    })
 }
 
+/// 与 `create_video_info` 相同，但帧率以分数给出，例如 FFmpeg 的 `avg_frame_rate`
+///
+/// 所有转换都直接使用该分数，不会有浮点误差
 #[unsafe(no_mangle)]
-pub extern "C" fn free_video_info(info: *mut VideoInfo) {
-    if info.is_null() {
-        return;
-    }
-    unsafe {
-        let _ = Box::from_raw(info);
-    }
+pub extern "C" fn create_video_info_rational(
+    fps_num: i64,
+    fps_den: i64,
+    time_base_den: i64,
+    time_base_num: i64,
+    start_time: i64,
+    duration: i64,
+) -> *mut VideoInfo {
+    create_video_info_rational_extended(
+        fps_num,
+        fps_den,
+        time_base_den,
+        time_base_num,
+        start_time,
+        duration,
+        1,
+    )
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct VideoInfo {
-    pub fps: f64,
-    pub time_base_den: i64,
-    pub time_base_num: i64,
-    pub start_time: i64,
-    pub duration: i64,
+/// 与 `create_video_info_rational` 相同，但额外记录视频流的数量
+#[unsafe(no_mangle)]
+pub extern "C" fn create_video_info_rational_extended(
+    fps_num: i64,
+    fps_den: i64,
+    time_base_den: i64,
+    time_base_num: i64,
+    start_time: i64,
+    duration: i64,
+    stream_count: u32,
+) -> *mut VideoInfo {
+    new_video_info(VideoInfo {
+        fps: fps_num as f64 / fps_den as f64,
+        fps_rational: Some((fps_num, fps_den)),
+        rounding: Rounding::default(),
+        duration,
+        start_time,
+        time_base_den,
+        time_base_num,
+        stream_count,
+        nb_frames: None,
+    })
 }
 
-impl VideoInfo {
-    pub fn frame_to_timestamp(&self, frame_index: u64) -> i64 {
-        let seconds = frame_index as f64 / self.fps;
-        let tb_val = self.time_base_num as f64 / self.time_base_den as f64;
-        let mut target_ts = (seconds / tb_val).ceil() as i64;
-        if self.start_time != AV_NOPTS_VALUE {
-            target_ts += self.start_time;
-        }
-        target_ts
-    }
+/// 设置时间转换为时间戳时的舍入方式，默认为四舍五入
+#[unsafe(no_mangle)]
+pub extern "C" fn set_video_info_rounding(info: &mut VideoInfo, rounding: Rounding) {
+    info.rounding = rounding;
+}
 
-    pub fn milliseconds_to_timestamp(&self, ms: u64) -> i64 {
-        let seconds = ms as f64 / 1000f64;
-        let tb_val = self.time_base_num as f64 / self.time_base_den as f64;
-        let mut target_ts = (seconds / tb_val).ceil() as i64;
-        if self.start_time != AV_NOPTS_VALUE {
-            target_ts += self.start_time;
-        }
-        target_ts
+/// 检查视频信息并放到堆上，无效时记录错误并返回空指针
+fn new_video_info(info: VideoInfo) -> *mut VideoInfo {
+    let result = info.validate();
+    LAST_VIDEO_INFO_ERROR.set(result.err());
+    match result {
+        Ok(()) => Box::into_raw(Box::new(info)),
+        Err(_) => std::ptr::null_mut(),
     }
+}
 
-    pub fn end_to_timestamp(&self) -> i64 {
-        self.duration
-    }
+thread_local! {
+    /// 当前线程最近一次创建视频信息时的错误
+    static LAST_VIDEO_INFO_ERROR: std::cell::Cell<Option<VideoInfoError>> =
+        const { std::cell::Cell::new(None) };
 }
 
-#[repr(C)]
-#[derive(Debug)]
-pub enum TimeTypeKind {
-    Frame = 0,
-    Millisecond = 1,
-    End = 2,
+/// 获取当前线程最近一次调用 `create_video_info` 的错误码，成功时为0
+///
+/// 错误码见 [`VideoInfoError::code`]
+#[unsafe(no_mangle)]
+pub extern "C" fn video_info_last_error() -> c_int {
+    LAST_VIDEO_INFO_ERROR.get().map_or(0, VideoInfoError::code)
 }
 
-impl Default for TimeTypeKind {
-    fn default() -> Self {
-        Self::Millisecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 视频信息中的参数无效
+pub enum VideoInfoError {
+    /// 帧率不是有限的正数
+    InvalidFps,
+    /// 时间基的分母为0
+    ZeroTimeBaseDen,
+    /// 时间基的分子不是正数
+    InvalidTimeBaseNum,
+    /// 时长为负数
+    NegativeDuration,
+}
+
+impl VideoInfoError {
+    /// 错误码，依次为1到4
+    pub fn code(self) -> c_int {
+        match self {
+            Self::InvalidFps => 1,
+            Self::ZeroTimeBaseDen => 2,
+            Self::InvalidTimeBaseNum => 3,
+            Self::NegativeDuration => 4,
+        }
     }
 }
 
-#[derive(Debug, Default)]
-pub struct PaserTimeType {
-    pub kind: TimeTypeKind,
-    pub value: u64,
+impl std::fmt::Display for VideoInfoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidFps => f.write_str("fps must be a finite positive number"),
+            Self::ZeroTimeBaseDen => f.write_str("time base denominator must not be 0"),
+            Self::InvalidTimeBaseNum => f.write_str("time base numerator must be positive"),
+            Self::NegativeDuration => f.write_str("duration must not be negative"),
+        }
+    }
 }
 
-pub struct ArgParseResultContext {
-    pub input: *const c_char,
-    pub output: *const c_char,
-    pub thread_count: u16,
-    pub format: *const c_char,
+impl std::error::Error for VideoInfoError {}
 
-    start: TimeType,
-    end: TimeType,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// SMPTE丢帧时间码，格式为 `hh:mm:ss;ff`，例如 `00:10:00;02`
+pub struct Timecode {
+    pub hours: u64,
+    pub minutes: u64,
+    pub seconds: u64,
+    pub frames: u64,
 }
 
-enum TimeType {
-    Parser(PaserTimeType),
-    #[cfg(feature = "dsl")]
-    DSL(lexer::CheckedExpr),
+impl Timecode {
+    /// 按丢帧规则换算为帧序号：除每十分钟外，每分钟开头丢弃 `dropped` 个帧号
+    ///
+    /// # 参数
+    /// * `nominal` - 标称帧率，29.97fps 为30
+    /// * `dropped` - 每分钟丢弃的帧号数，29.97fps 为2
+    ///
+    /// # 返回值
+    /// 字段超出范围、帧号已被丢弃或结果溢出时返回错误
+    pub fn to_frame_index(&self, nominal: u64, dropped: u64) -> Result<u64, TimecodeError> {
+        if self.minutes >= 60 || self.seconds >= 60 || self.frames >= nominal {
+            return Err(TimecodeError::FieldOutOfRange);
+        }
+        if self.seconds == 0 && !self.minutes.is_multiple_of(10) && self.frames < dropped {
+            return Err(TimecodeError::DroppedFrame);
+        }
+        let total_minutes = self
+            .hours
+            .checked_mul(60)
+            .and_then(|minutes| minutes.checked_add(self.minutes))
+            .ok_or(TimecodeError::Overflow)?;
+        // 标称帧数总是不少于丢弃的帧号数，相减不会溢出
+        total_minutes
+            .checked_mul(60)
+            .and_then(|seconds| seconds.checked_add(self.seconds))
+            .and_then(|seconds| seconds.checked_mul(nominal))
+            .and_then(|frames| frames.checked_add(self.frames))
+            .map(|frames| frames - dropped * (total_minutes - total_minutes / 10))
+            .ok_or(TimecodeError::Overflow)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Time {
-    Frame(u64),
-    Time(Duration),
-    End,
+impl std::fmt::Display for Timecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02};{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
 }
 
-impl std::str::FromStr for Time {
-    type Err = String;
+impl std::str::FromStr for Timecode {
+    type Err = TimecodeError;
+
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.to_lowercase() == "end" {
-            return Ok(Self::End);
-        }
-        if let Ok(frame) = s.parse::<u64>() {
-            return Ok(Self::Frame(frame));
-        }
-        if s.ends_with('s') {
-            let sub = s.chars().take(s.len() - 1).collect::<String>();
-            let Ok(v) = sub.parse::<f64>() else {
-                return Err(format!("Wrong second format: '{sub}'"));
-            };
-            return Ok(Self::Time(Duration::from_secs_f64(v)));
-        }
-        let segments = s.split(':').collect::<Vec<_>>();
-        if segments.len() > 3 || segments.len() < 2 {
-            return Err("Wrong time format".to_string());
-        }
-        let mut segs = segments.iter();
-        let hour = if segments.len() == 3 {
-            segs.next()
-                .unwrap()
-                .parse::<u64>()
-                .map_err(|err| err.to_string())?
-        } else {
-            0
-        };
-        let min = segs
-            .next()
-            .unwrap()
-            .parse::<u64>()
-            .map_err(|err| err.to_string())?;
-        let mut secs = segs.next().unwrap().split('.');
-        let sec = secs
-            .next()
-            .unwrap()
-            .parse::<u64>()
-            .map_err(|err| err.to_string())?;
-        let mm = if let Some(mm) = secs.next() {
-            let a = format!("{mm:0<3}");
-            if a.len() > 3 {
-                return Err("millis rank must less than 4".to_string());
-            }
-            a.parse::<u64>().map_err(|err| err.to_string())?
-        } else {
-            0
+        let (time, frames) = s.split_once(';').ok_or(TimecodeError::InvalidFormat)?;
+        let fields = time
+            .split(':')
+            .chain([frames])
+            .map(|field| match field.bytes().all(|b| b.is_ascii_digit()) {
+                true => field
+                    .parse::<u64>()
+                    .map_err(|_| TimecodeError::InvalidFormat),
+                false => Err(TimecodeError::InvalidFormat),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let [hours, minutes, seconds, frames] = fields[..] else {
+            return Err(TimecodeError::InvalidFormat);
         };
-        let sec = Duration::from_secs(
-            hour.saturating_mul(3600)
-                .saturating_add(min.saturating_mul(60))
-                .saturating_add(sec),
-        );
-        let mm = Duration::from_millis(mm);
-        Ok(Self::Time(sec.saturating_add(mm)))
+        Ok(Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+        })
     }
 }
 
-impl From<Time> for PaserTimeType {
-    fn from(value: Time) -> Self {
-        match value {
-            Time::Time(t) => Self {
-                kind: TimeTypeKind::Millisecond,
-                value: t.as_millis() as u64,
-            },
-            Time::Frame(f) => Self {
-                kind: TimeTypeKind::Frame,
-                value: f,
-            },
-            Time::End => Self {
-                kind: TimeTypeKind::End,
-                value: 0,
-            },
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 丢帧时间码无法转换为时间戳
+pub enum TimecodeError {
+    /// 不是 `hh:mm:ss;ff` 格式
+    InvalidFormat,
+    /// 丢帧时间码只适用于 29.97fps 和 59.94fps
+    UnsupportedFps,
+    /// 分、秒或帧超出范围
+    FieldOutOfRange,
+    /// 该帧号按丢帧规则被跳过，例如 29.97fps 的 `00:01:00;00`
+    DroppedFrame,
+    /// 时间码对应的帧序号或时间戳溢出
+    Overflow,
+}
+
+impl std::fmt::Display for TimecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidFormat => f.write_str("timecode must look like `hh:mm:ss;ff`"),
+            Self::UnsupportedFps => {
+                f.write_str("drop-frame timecodes need a 29.97 or 59.94 fps video")
+            }
+            Self::FieldOutOfRange => f.write_str("timecode field out of range"),
+            Self::DroppedFrame => {
+                f.write_str("frame number is skipped by drop-frame counting in this minute")
+            }
+            Self::Overflow => f.write_str("timecode is too large"),
         }
     }
 }
 
-impl From<Time> for TimeType {
-    fn from(value: Time) -> Self {
-        Self::Parser(value.into())
+impl std::error::Error for TimecodeError {}
+
+/// 设置视频的关键帧时间戳表，供 `keyframe_before`/`keyframe_after` 函数使用
+///
+/// 会复制并排序 `keyframes`，传入空指针时清除关键帧表
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn set_keyframes(
+    res_ctx: &mut ArgParseResultContext,
+    keyframes: *const i64,
+    len: usize,
+) {
+    if keyframes.is_null() {
+        res_ctx.set_keyframes(&[]);
+    } else {
+        res_ctx.set_keyframes(unsafe { std::slice::from_raw_parts(keyframes, len) });
     }
 }
 
-#[derive(Debug, Clone)]
-enum ThreadCount {
-    Auto,
-    Custom(u16),
+/// 设置视频流的总帧数，用于检查帧序号是否超出范围，为0时表示未知
+#[unsafe(no_mangle)]
+pub extern "C" fn set_video_info_nb_frames(info: &mut VideoInfo, nb_frames: u64) {
+    info.set_nb_frames((nb_frames != 0).then_some(nb_frames));
 }
 
-impl From<ThreadCount> for u16 {
-    fn from(value: ThreadCount) -> Self {
-        match value {
-            ThreadCount::Auto => 0,
-            ThreadCount::Custom(v) => v,
-        }
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn free_video_info(info: *mut VideoInfo) {
+    if info.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(info);
     }
 }
 
-impl std::str::FromStr for ThreadCount {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.eq_ignore_ascii_case("auto") {
-            Ok(Self::Auto)
-        } else {
-            s.parse::<u16>()
-                .map(Self::Custom)
-                .map_err(|err| err.to_string())
-        }
+/// 复制一份独立的视频信息，传入空指针时返回空指针
+///
+/// 返回的视频信息需要通过 [`free_video_info`] 释放
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_clone(info: *const VideoInfo) -> *mut VideoInfo {
+    match unsafe { info.as_ref() } {
+        Some(info) => Box::into_raw(Box::new(*info)),
+        None => std::ptr::null_mut(),
     }
 }
 
-#[derive(Debug, Parser)]
-#[command(
-    about = "A simple video frame picker\n\nTips:\n\t`xxx` is frame index\n\t`xx:xx.xx` is timestamp\n\t`end` is the end of video\n\t`xx.xxs` is seconds-base timestamp"
-)]
-struct Cli {
-    #[arg(short, long, help = "The video path")]
-    input: String,
-    #[cfg(feature = "dsl")]
-    #[arg(
-        short,
-        long,
-        value_name = "expr",
-        help = "time expression",
-        default_value = "0f"
-    )]
-    from: String,
-    #[cfg(not(feature = "dsl"))]
-    #[arg(
-        short,
-        long,
-        help = "possible format: [xxx, xx.xxs, xx:xx.xx, end]",
-        default_value = "0"
-    )]
-    from: Time,
-    #[cfg(feature = "dsl")]
-    #[arg(
-        short,
-        long,
-        value_name = "expr",
-        help = "time expression",
-        default_value = "end"
-    )]
-    to: String,
-    #[cfg(not(feature = "dsl"))]
-    #[arg(
-        short,
-        long,
-        help = "possible format: [xxx, xx.xxs, xx:xx.xx, end]",
-        default_value = "end"
-    )]
-    to: Time,
-    #[arg(
-        long,
-        value_name = "Auto|num",
-        help = "thread count for codec",
-        default_value = "auto"
-    )]
-    thread_count: ThreadCount,
-    #[arg(long, help = "filename format", default_value = "frame-%d.jpg")]
-    format: String,
-    #[arg(help = "Output path", default_value = ".")]
-    output: String,
+/// 与 [`video_info_clone`] 相同，复制一份独立的视频信息，传入空指针时返回空指针
+///
+/// 返回的视频信息需要通过 [`free_video_info`] 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn create_video_info_copy(src: *const VideoInfo) -> *mut VideoInfo {
+    video_info_clone(src)
 }
 
-#[cfg(feature = "dsl")]
-macro_rules! err {
-    ($info:expr) => {{
-        println!("{} {}", "error:".bright_red(), $info);
-        std::process::exit(1);
-    }};
-    ($info:expr, $code:literal) => {{
-        use colored::Colorize;
-        println!("{} {}", "error:".bright_red(), $info);
-        std::process::exit($code);
-    }};
+/// 比较两个视频信息的所有字段是否相同，两个都是空指针时视为相同
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_equal(a: *const VideoInfo, b: *const VideoInfo) -> bool {
+    unsafe { a.as_ref() == b.as_ref() }
+}
+
+/// 读取视频信息的字段，传入空指针时返回 `default`
+fn video_info_field<T>(info: *const VideoInfo, default: T, f: impl FnOnce(&VideoInfo) -> T) -> T {
+    unsafe { info.as_ref() }.map_or(default, f)
 }
 
+/// 获取视频的帧率，传入空指针时返回0
 #[unsafe(no_mangle)]
-pub extern "C" fn parse() -> *mut ArgParseResultContext {
-    let cli = Cli::parse();
-    #[cfg(feature = "dsl")]
-    {
-        let (_, mut from_expr) = tui::handle_error(
-            &cli.from,
-            "from",
-            lexer::parse_expr(cli.from.as_str().into()),
-        );
-        lexer::optimize_expr(&mut from_expr);
-        let from_expr = lexer::check_expr(&from_expr)
-            .map_err(|err| err!(err, 2))
-            .unwrap();
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_get_fps(info: *const VideoInfo) -> f64 {
+    video_info_field(info, 0f64, |info| info.fps)
+}
 
-        let (_, mut to_expr) =
-            tui::handle_error(&cli.to, "to", lexer::parse_expr(cli.to.as_str().into()));
-        lexer::optimize_expr(&mut to_expr);
-        let to_expr = lexer::check_expr(&to_expr)
-            .map_err(|err| err!(err, 2))
-            .unwrap();
-
-        let ref_to = from_expr.items.iter().any(|item| match item {
-            lexer::DSLType::Keyword(lexer::DSLKeywords::To) => true,
-            _ => false,
-        });
-        let ref_from = to_expr.items.iter().any(|item| match item {
-            lexer::DSLType::Keyword(lexer::DSLKeywords::From) => true,
-            _ => false,
-        });
-        if ref_from && ref_to {
-            err!(
-                "circular references, arg from ref `to` and arg to ref `from`".bright_white(),
-                2
-            );
-        }
-
-        Box::into_raw(Box::new(ArgParseResultContext {
-            input: CString::new(cli.input).unwrap_or_default().into_raw(),
-            output: CString::new(cli.output).unwrap_or_default().into_raw(),
-            format: CString::new(cli.format).unwrap_or_default().into_raw(),
-            thread_count: cli.thread_count.into(),
-            start: TimeType::DSL(from_expr),
-            end: TimeType::DSL(to_expr),
-        }))
-    }
-    #[cfg(not(feature = "dsl"))]
-    Box::into_raw(Box::new(ArgParseResultContext {
-        input: CString::new(cli.input).unwrap_or_default().into_raw(),
-        output: CString::new(cli.output).unwrap_or_default().into_raw(),
-        start: cli.from.into(),
-        end: cli.to.into(),
-        thread_count: cli.thread_count.into(),
-        format: CString::new(cli.format).unwrap_or_default().into_raw(),
-    }))
+/// 获取视频的时长（以时间基为单位），传入空指针时返回0
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_get_duration(info: *const VideoInfo) -> i64 {
+    video_info_field(info, 0, |info| info.duration)
 }
 
+/// 获取视频的开始时间，传入空指针时返回0
 #[unsafe(no_mangle)]
-pub extern "C" fn get_input(res_ctx: &ArgParseResultContext) -> *const c_char {
-    res_ctx.input
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_get_start_time(info: *const VideoInfo) -> i64 {
+    video_info_field(info, 0, |info| info.start_time)
 }
 
+/// 获取时间基的分子，传入空指针时返回0
 #[unsafe(no_mangle)]
-pub extern "C" fn get_output(res_ctx: &ArgParseResultContext) -> *const c_char {
-    res_ctx.output
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_get_time_base_num(info: *const VideoInfo) -> i64 {
+    video_info_field(info, 0, |info| info.time_base_num)
 }
 
+/// 获取时间基的分母，传入空指针时返回0
 #[unsafe(no_mangle)]
-pub extern "C" fn get_thread_count(res_ctx: &ArgParseResultContext) -> u16 {
-    res_ctx.thread_count
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_get_time_base_den(info: *const VideoInfo) -> i64 {
+    video_info_field(info, 0, |info| info.time_base_den)
 }
 
+/// 将视频信息序列化为JSON，见 [`VideoInfo::to_json`]
+///
+/// 返回的字符串需要通过 [`free_string`] 释放
+#[cfg(feature = "serde")]
 #[unsafe(no_mangle)]
-pub extern "C" fn get_format(res_ctx: &ArgParseResultContext) -> *const c_char {
-    res_ctx.format
+pub extern "C" fn video_info_to_json(info: &VideoInfo) -> *mut c_char {
+    CString::new(info.to_json())
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
 }
 
+/// 从JSON中读取视频信息，JSON无效或参数无效时返回空指针
+///
+/// 参数无效的原因可以通过 `video_info_last_error` 获取，
+/// 返回的视频信息需要通过 [`free_video_info`] 释放
+#[cfg(feature = "serde")]
 #[unsafe(no_mangle)]
-pub extern "C" fn get_from_timestamp(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
-    match res_ctx.start {
-        TimeType::Parser(ref per) => match per.kind {
-            TimeTypeKind::End => info.end_to_timestamp(),
-            TimeTypeKind::Frame => info.frame_to_timestamp(per.value),
-            TimeTypeKind::Millisecond => info.milliseconds_to_timestamp(per.value),
-        },
-        #[cfg(feature = "dsl")]
-        TimeType::DSL(ref expr) => {
-            let mut pts = 0i64;
-            for (op, item) in expr.ops.iter().zip(expr.items.iter()) {
-                let item = match item {
-                    lexer::DSLType::Keyword(keyword) => match keyword {
-                        lexer::DSLKeywords::To => get_to_timestamp(res_ctx, info),
-                        lexer::DSLKeywords::End => info.end_to_timestamp(),
-                        _ => unreachable!(),
-                    },
-                    lexer::DSLType::FrameIndex(index) => info.frame_to_timestamp(*index),
-                    lexer::DSLType::Timestamp(dur) => {
-                        info.milliseconds_to_timestamp(dur.as_millis() as u64)
-                    }
-                };
-                match op {
-                    lexer::DSLOp::Add => {
-                        pts += item;
-                    }
-                    lexer::DSLOp::Sub => {
-                        pts -= item;
-                    }
-                }
-            }
-            pts
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn video_info_from_json(json: *const c_char) -> *mut VideoInfo {
+    LAST_VIDEO_INFO_ERROR.set(None);
+    if json.is_null() {
+        return std::ptr::null_mut();
+    }
+    let json = unsafe { CStr::from_ptr(json) }.to_string_lossy();
+    match VideoInfo::from_json(&json) {
+        Ok(info) => new_video_info(info),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// 还原分数帧率时分母的上限
+const MAX_FPS_DENOMINATOR: i64 = 1_000_000;
+
+/// 将帧率还原为分数 `(分子, 分母)`，例如 29.97fps 为 `(30000, 1001)`
+///
+/// 依次尝试整数帧率和NTSC帧率（分母为1001），都不符合时用连分数求分母不超过
+/// 1000000的最佳近似。帧率不是正的有限数时返回 `None`
+pub fn fps_to_rational(fps: f64) -> Option<(i64, i64)> {
+    if !fps.is_finite() || fps <= 0.0 || fps >= (i64::MAX / 1001) as f64 {
+        return None;
+    }
+    let close = |num: i64, den: i64| (num as f64 / den as f64 - fps).abs() <= fps * 1e-12;
+    for den in [1, 1001] {
+        let num = (fps * den as f64).round() as i64;
+        if num > 0 && close(num, den) {
+            return Some((num, den));
+        }
+    }
+    // 连分数的渐近分数 h/k，初始为 h(-1)/k(-1) = 1/0 和 h(-2)/k(-2) = 0/1
+    let (mut h, mut h_prev) = (1i64, 0i64);
+    let (mut k, mut k_prev) = (0i64, 1i64);
+    let mut x = fps;
+    loop {
+        let a = x.floor();
+        let next = (a as i64)
+            .checked_mul(h)
+            .and_then(|v| v.checked_add(h_prev))
+            .zip(
+                (a as i64)
+                    .checked_mul(k)
+                    .and_then(|v| v.checked_add(k_prev)),
+            );
+        let Some((h_next, k_next)) = next else {
+            break;
+        };
+        if k_next > MAX_FPS_DENOMINATOR {
+            break;
         }
+        (h_prev, h, k_prev, k) = (h, h_next, k, k_next);
+        if close(h, k) || x == a {
+            break;
+        }
+        x = 1.0 / (x - a);
     }
+    (h > 0 && k > 0).then_some((h, k))
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn get_to_timestamp(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
-    match res_ctx.end {
-        TimeType::Parser(ref per) => match per.kind {
-            TimeTypeKind::End => info.end_to_timestamp(),
-            TimeTypeKind::Frame => info.frame_to_timestamp(per.value),
-            TimeTypeKind::Millisecond => info.milliseconds_to_timestamp(per.value),
-        },
-        #[cfg(feature = "dsl")]
-        TimeType::DSL(ref expr) => {
-            let mut pts = 0i64;
-            for (op, item) in expr.ops.iter().zip(expr.items.iter()) {
-                let item = match item {
-                    lexer::DSLType::Keyword(keyword) => match keyword {
-                        lexer::DSLKeywords::From => get_from_timestamp(res_ctx, info),
-                        lexer::DSLKeywords::End => info.end_to_timestamp(),
-                        _ => unreachable!(),
-                    },
-                    lexer::DSLType::FrameIndex(index) => info.frame_to_timestamp(*index),
-                    lexer::DSLType::Timestamp(dur) => {
-                        info.milliseconds_to_timestamp(dur.as_millis() as u64)
-                    }
-                };
-                match op {
-                    lexer::DSLOp::Add => {
-                        pts += item;
-                    }
-                    lexer::DSLOp::Sub => {
-                        pts -= item;
-                    }
+/// 时间转换为时间戳时的舍入方式
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rounding {
+    /// 向下取整
+    Floor = 0,
+    /// 四舍五入，与 FFmpeg 的 `av_rescale_q` 相同
+    #[default]
+    Nearest = 1,
+    /// 向上取整
+    Ceil = 2,
+}
+
+impl Rounding {
+    /// 按舍入方式计算 `numerator / denominator`，`denominator` 必须为正数
+    pub fn div(self, numerator: i128, denominator: i128) -> i128 {
+        match self {
+            Self::Floor => numerator.div_euclid(denominator),
+            Self::Nearest => (numerator * 2 + denominator).div_euclid(denominator * 2),
+            Self::Ceil => (numerator + denominator - 1).div_euclid(denominator),
+        }
+    }
+
+    /// 按舍入方式取整浮点数
+    pub fn round(self, value: f64) -> f64 {
+        match self {
+            Self::Floor => value.floor(),
+            Self::Nearest => value.round(),
+            Self::Ceil => value.ceil(),
+        }
+    }
+}
+
+/// 视频信息，通过 `create_video_info*` 或 [`VideoInfo::from_duration_and_fps`] 创建
+///
+/// 之后可能增加新的字段，因此不能在crate之外直接用结构体字面量构造。
+/// 帧率按位比较和哈希，使 `VideoInfo` 可以作为 `HashMap` 的键，
+/// 因此 `0.0` 与 `-0.0` 不相等，而相同位模式的 `NaN` 相等
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct VideoInfo {
+    pub fps: f64,
+    /// 分数形式的帧率 `(分子, 分母)`，为 `None` 时由 `fps` 还原，见 [`VideoInfo::fps_rational`]
+    pub fps_rational: Option<(i64, i64)>,
+    /// 时间转换为时间戳时的舍入方式，默认为 [`Rounding::Nearest`]
+    pub rounding: Rounding,
+    pub time_base_den: i64,
+    pub time_base_num: i64,
+    pub start_time: i64,
+    pub duration: i64,
+    pub stream_count: u32,
+    /// 视频流的总帧数，例如 FFmpeg 的 `nb_frames`，为 `None` 表示未知，
+    /// 通过 [`VideoInfo::set_nb_frames`] 设置
+    nb_frames: Option<u64>,
+}
+
+impl PartialEq for VideoInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.fps.to_bits() == other.fps.to_bits()
+            && self.fps_rational == other.fps_rational
+            && self.rounding == other.rounding
+            && self.time_base_den == other.time_base_den
+            && self.time_base_num == other.time_base_num
+            && self.start_time == other.start_time
+            && self.duration == other.duration
+            && self.stream_count == other.stream_count
+            && self.nb_frames == other.nb_frames
+    }
+}
+
+impl Eq for VideoInfo {}
+
+impl std::hash::Hash for VideoInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.fps.to_bits().hash(state);
+        self.fps_rational.hash(state);
+        self.rounding.hash(state);
+        self.time_base_den.hash(state);
+        self.time_base_num.hash(state);
+        self.start_time.hash(state);
+        self.duration.hash(state);
+        self.stream_count.hash(state);
+        self.nb_frames.hash(state);
+    }
+}
+
+impl VideoInfo {
+    /// 只根据时长（秒）和帧率创建视频信息，使用 MPEG 的标准时间基 `1/90000`，开始时间未知
+    ///
+    /// 适用于只关心实际时间的场景，例如测试，不检查参数是否有效
+    ///
+    /// # 参数
+    /// - `duration_secs`: 视频时长，单位为秒
+    /// - `fps`: 帧率
+    pub fn from_duration_and_fps(duration_secs: f64, fps: f64) -> Self {
+        const TIME_BASE_DEN: i64 = 90_000;
+        Self {
+            fps,
+            fps_rational: None,
+            rounding: Rounding::default(),
+            time_base_den: TIME_BASE_DEN,
+            time_base_num: 1,
+            start_time: AV_NOPTS_VALUE,
+            duration: (duration_secs * TIME_BASE_DEN as f64).round() as i64,
+            stream_count: 1,
+            nb_frames: None,
+        }
+    }
+
+    /// 检查帧率、时间基和时长是否有效，时长可以为 `AV_NOPTS_VALUE`
+    pub fn validate(&self) -> Result<(), VideoInfoError> {
+        if !self.fps.is_finite() || self.fps <= 0.0 {
+            return Err(VideoInfoError::InvalidFps);
+        }
+        if let Some((fps_num, fps_den)) = self.fps_rational
+            && (fps_num <= 0 || fps_den <= 0)
+        {
+            return Err(VideoInfoError::InvalidFps);
+        }
+        if self.time_base_den == 0 {
+            return Err(VideoInfoError::ZeroTimeBaseDen);
+        }
+        if self.time_base_num <= 0 {
+            return Err(VideoInfoError::InvalidTimeBaseNum);
+        }
+        if self.duration < 0 && self.duration != AV_NOPTS_VALUE {
+            return Err(VideoInfoError::NegativeDuration);
+        }
+        Ok(())
+    }
+
+    /// 分数形式的帧率，优先使用创建时给出的分数，否则通过 [`fps_to_rational`] 还原
+    pub fn fps_rational(&self) -> Option<(i64, i64)> {
+        self.fps_rational.or_else(|| fps_to_rational(self.fps))
+    }
+
+    /// 将帧序号转换为时间戳，按 `rounding` 取整
+    ///
+    /// 按 [`VideoInfo::fps_rational`] 得到的分数帧率用 [`VideoInfo::frame_to_timestamp_exact`]
+    /// 整数计算，避免长视频和 29.97 这类帧率的浮点误差。帧率无效时退回浮点计算
+    pub fn frame_to_timestamp(&self, frame_index: u64) -> i64 {
+        if let Some((fps_num, fps_den)) = self.fps_rational() {
+            return self.frame_to_timestamp_exact(frame_index, fps_num, fps_den);
+        }
+        let seconds = frame_index as f64 / self.fps;
+        let tb_val = self.time_base_num as f64 / self.time_base_den as f64;
+        let mut target_ts = self.rounding.round(seconds / tb_val) as i64;
+        if self.start_time != AV_NOPTS_VALUE {
+            target_ts += self.start_time;
+        }
+        target_ts
+    }
+
+    /// 使用分数帧率精确地将帧序号转换为时间戳，按 `rounding` 取整，超出 `i64` 范围时取最大值
+    ///
+    /// # 参数
+    /// * `frame_index` - 帧序号
+    /// * `fps_num` - 帧率的分子，例如 29.97fps 为 30000
+    /// * `fps_den` - 帧率的分母，例如 29.97fps 为 1001
+    pub fn frame_to_timestamp_exact(&self, frame_index: u64, fps_num: i64, fps_den: i64) -> i64 {
+        // 超出i64范围时取最大值，与浮点计算的行为一致
+        let mut target_ts = i64::try_from(self.exact_frame_offset(frame_index, fps_num, fps_den))
+            .unwrap_or(i64::MAX);
+        if self.start_time != AV_NOPTS_VALUE {
+            target_ts = target_ts.saturating_add(self.start_time);
+        }
+        target_ts
+    }
+
+    /// 第 `frame_index` 帧相对于开始时间的偏移，即 `n * fps_den * tb_den / (fps_num * tb_num)`
+    fn exact_frame_offset(&self, frame_index: u64, fps_num: i64, fps_den: i64) -> i128 {
+        let numerator = frame_index as i128 * fps_den as i128 * self.time_base_den as i128;
+        let denominator = (fps_num as i128 * self.time_base_num as i128).max(1);
+        self.rounding.div(numerator, denominator)
+    }
+
+    /// 与 [`VideoInfo::frame_to_timestamp`] 相同，但结果超出 `i64` 范围时返回 `None` 而不是取最大值
+    pub fn checked_frame_to_timestamp(&self, frame_index: u64) -> Option<i64> {
+        let offset = match self.fps_rational() {
+            Some((fps_num, fps_den)) => {
+                i64::try_from(self.exact_frame_offset(frame_index, fps_num, fps_den)).ok()?
+            }
+            None => {
+                let seconds = frame_index as f64 / self.fps;
+                let tb_val = self.time_base_num as f64 / self.time_base_den as f64;
+                let ts = self.rounding.round(seconds / tb_val);
+                // `i64::MAX as f64` 向上取整为2^63，不在 `i64` 范围内
+                if !ts.is_finite() || ts >= i64::MAX as f64 {
+                    return None;
                 }
+                ts as i64
             }
-            pts
+        };
+        offset.checked_add(self.start_offset())
+    }
+
+    /// 视频的开始时间，未知（为 `AV_NOPTS_VALUE`）时为0
+    pub fn start_offset(&self) -> i64 {
+        if self.start_time == AV_NOPTS_VALUE {
+            0
+        } else {
+            self.start_time
+        }
+    }
+
+    /// 将时间戳转换为帧序号，即时间戳不晚于 `ts` 的最后一帧，早于第0帧时返回0
+    ///
+    /// 与 [`VideoInfo::frame_to_timestamp`] 互逆，
+    /// 对任意帧序号 `n` 有 `timestamp_to_frame(frame_to_timestamp(n)) == n`
+    pub fn timestamp_to_frame(&self, ts: i64) -> u64 {
+        let start = if self.start_time == AV_NOPTS_VALUE {
+            0
+        } else {
+            self.start_time
+        };
+        let seconds =
+            (ts as f64 - start as f64) * self.time_base_num as f64 / self.time_base_den as f64;
+        // 浮点估算后按 frame_to_timestamp 修正，保证互逆
+        let mut frame = (seconds * self.fps).max(0.0).floor() as u64;
+        while frame > 0 && self.frame_to_timestamp(frame) > ts {
+            frame -= 1;
+        }
+        while self.frame_to_timestamp(frame + 1) <= ts {
+            frame += 1;
+        }
+        frame
+    }
+
+    /// 获取时间戳在 `[from, to]` 范围内的帧序号，范围内没有帧时返回 `None`
+    pub(crate) fn frame_range(&self, from: i64, to: i64) -> Option<std::ops::RangeInclusive<u64>> {
+        let mut first = self.timestamp_to_frame(from);
+        if self.frame_to_timestamp(first) < from {
+            first += 1;
+        }
+        let last = self.timestamp_to_frame(to);
+        if self.frame_to_timestamp(last) > to || first > last {
+            return None;
+        }
+        Some(first..=last)
+    }
+
+    /// 获取时间戳在 `[from, to]` 范围内的帧数，`from` 晚于 `to` 时为0
+    pub fn frames_between(&self, from: i64, to: i64) -> u64 {
+        self.frame_range(from, to)
+            .map_or(0, |frames| frames.end() - frames.start() + 1)
+    }
+
+    /// 视频流的总帧数，未知时为 `None`
+    pub fn nb_frames(&self) -> Option<u64> {
+        self.nb_frames
+    }
+
+    /// 设置视频流的总帧数，例如 FFmpeg 的 `nb_frames`，`None` 表示未知
+    ///
+    /// 已知总帧数时，检查范围的求值函数会拒绝超出总帧数的帧序号
+    pub fn set_nb_frames(&mut self, nb_frames: Option<u64>) {
+        self.nb_frames = nb_frames;
+    }
+
+    /// 获取视频的总帧数，优先使用已知的 `nb_frames`，
+    /// 否则为时间戳与开始时间之差小于时长的帧数，时长为0时返回0
+    pub fn total_frames(&self) -> u64 {
+        if let Some(nb_frames) = self.nb_frames {
+            return nb_frames;
+        }
+        if self.duration <= 0 {
+            return 0;
+        }
+        let start = if self.start_time == AV_NOPTS_VALUE {
+            0
+        } else {
+            self.start_time
+        };
+        self.timestamp_to_frame(start.saturating_add(self.duration) - 1) + 1
+    }
+
+    /// 将丢帧时间码转换为时间戳，只支持 29.97fps 和 59.94fps
+    pub fn timecode_to_timestamp(&self, timecode: &Timecode) -> Result<i64, TimecodeError> {
+        let (nominal, dropped) = match self.fps_rational() {
+            Some((30000, 1001)) => (30, 2),
+            Some((60000, 1001)) => (60, 4),
+            _ => return Err(TimecodeError::UnsupportedFps),
+        };
+        let frame = timecode.to_frame_index(nominal, dropped)?;
+        self.checked_frame_to_timestamp(frame)
+            .ok_or(TimecodeError::Overflow)
+    }
+
+    /// 解析 `hh:mm:ss;ff` 格式的丢帧时间码并转换为时间戳，见 [`VideoInfo::timecode_to_timestamp`]
+    pub fn dropframe_timecode_to_timestamp(&self, timecode: &str) -> Result<i64, TimecodeError> {
+        self.timecode_to_timestamp(&timecode.parse()?)
+    }
+
+    /// 检查帧序号是否在已知的总帧数内，总帧数未知时不检查
+    ///
+    /// # 返回值
+    /// 帧序号超出范围时返回错误信息
+    pub fn check_frame_index(&self, frame_index: u64) -> Result<(), String> {
+        match self.nb_frames {
+            Some(nb_frames) if frame_index >= nb_frames => Err(format!(
+                "frame {frame_index} is out of range: the video has {nb_frames} frames"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 按固定的关键帧间隔，将 `pts` 向下对齐到不晚于它的关键帧
+    ///
+    /// 没有关键帧表时使用，假设第0帧是关键帧且之后每隔 `keyframe_interval` 个时间基单位有一个关键帧
+    ///
+    /// # 参数
+    /// * `pts` - 以时间基为单位的时间戳
+    /// * `keyframe_interval` - 关键帧间隔，以时间基为单位，为0时不对齐
+    pub fn keyframe_aligned_timestamp(&self, pts: i64, keyframe_interval: u32) -> i64 {
+        if keyframe_interval == 0 {
+            return pts;
+        }
+        let start = if self.start_time == AV_NOPTS_VALUE {
+            0
+        } else {
+            self.start_time
+        };
+        let interval = i128::from(keyframe_interval);
+        let aligned = start as i128 + (pts as i128 - start as i128).div_euclid(interval) * interval;
+        aligned.max(i64::MIN.into()) as i64
+    }
+
+    pub fn milliseconds_to_timestamp(&self, ms: u64) -> i64 {
+        self.duration_to_timestamp(Duration::from_millis(ms))
+    }
+
+    /// 将时长转换为时间戳，使用整数运算以保留纳秒精度，按 `rounding` 取整，超出 `i64` 范围时取最大值
+    pub fn duration_to_timestamp(&self, time: Duration) -> i64 {
+        self.checked_duration_to_timestamp(time).unwrap_or(i64::MAX)
+    }
+
+    /// 与 [`VideoInfo::duration_to_timestamp`] 相同，但结果超出 `i64` 范围时返回 `None`
+    pub fn checked_duration_to_timestamp(&self, time: Duration) -> Option<i64> {
+        let numerator = time.as_nanos() as i128 * self.time_base_den as i128;
+        let denominator = (self.time_base_num as i128 * 1_000_000_000).max(1);
+        i64::try_from(self.rounding.div(numerator, denominator))
+            .ok()?
+            .checked_add(self.start_offset())
+    }
+
+    /// 获取视频结束的时间戳，时长未知（为 `AV_NOPTS_VALUE`，例如直播流）时返回 `i64::MAX`，
+    /// 即没有结束时间，需要区分时使用 [`VideoInfo::checked_end_to_timestamp`]
+    pub fn end_to_timestamp(&self) -> i64 {
+        self.checked_end_to_timestamp().unwrap_or(i64::MAX)
+    }
+
+    /// 与 [`VideoInfo::end_to_timestamp`] 相同，但时长未知时返回 `None`
+    pub fn checked_end_to_timestamp(&self) -> Option<i64> {
+        self.has_duration().then_some(self.duration)
+    }
+
+    /// 视频的时长是否已知
+    pub fn has_duration(&self) -> bool {
+        self.duration != AV_NOPTS_VALUE
+    }
+
+    /// 将时间戳格式化为 `hh:mm:ss.mmm`，相对于视频的开始时间
+    ///
+    /// # 参数
+    /// * `pts` - 以时间基为单位的时间戳
+    ///
+    /// # 返回值
+    /// 格式化后的时间，早于开始时间时带有 `-` 前缀
+    pub fn timestamp_to_display(&self, pts: i64) -> String {
+        format_milliseconds(self.timestamp_to_milliseconds(pts))
+    }
+
+    /// 将时间戳转换为相对于视频开始时间的毫秒数，向零取整
+    pub fn timestamp_to_milliseconds(&self, pts: i64) -> i64 {
+        let start = if self.start_time == AV_NOPTS_VALUE {
+            0
+        } else {
+            self.start_time
+        };
+        let ms = (pts as i128 - start as i128) * self.time_base_num as i128 * 1000
+            / self.time_base_den.max(1) as i128;
+        ms.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+    }
+
+    /// 将时间戳转换为相对于视频开始时间的时长，精确到纳秒并向零取整
+    ///
+    /// 早于开始时间时为两者之差的绝对值，超出 `Duration` 的范围时取最大值
+    pub fn timestamp_to_duration(&self, ts: i64) -> Duration {
+        const NANOS_PER_SEC: u128 = 1_000_000_000;
+        let nanos = (ts as i128 - self.start_offset() as i128)
+            .unsigned_abs()
+            .checked_mul(self.time_base_num.max(0) as u128 * NANOS_PER_SEC)
+            .map(|numerator| numerator / self.time_base_den.max(1) as u128);
+        nanos
+            .and_then(|nanos| {
+                let secs = u64::try_from(nanos / NANOS_PER_SEC).ok()?;
+                Some(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32))
+            })
+            .unwrap_or(Duration::MAX)
+    }
+
+    /// 将时间戳格式化为 `H:MM:SS.mmm`，相对于视频的开始时间，小时为0时省略小时
+    ///
+    /// # 参数
+    /// * `ts` - 以时间基为单位的时间戳
+    ///
+    /// # 返回值
+    /// 格式化后的时间，早于开始时间时带有 `-` 前缀，`ts` 为 `AV_NOPTS_VALUE` 时为 `N/A`
+    pub fn format_timestamp(&self, ts: i64) -> String {
+        if ts == AV_NOPTS_VALUE {
+            return "N/A".to_string();
+        }
+        // 在 `timestamp_to_display` 的基础上省略为0的小时和小时的前导0，
+        // 不足一毫秒时不会出现 `-00:00.000`
+        let display = self.timestamp_to_display(ts);
+        let (sign, rest) = match display.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", display.as_str()),
+        };
+        let (hours, rest) = rest.split_once(':').unwrap_or(("", rest));
+        match hours.trim_start_matches('0') {
+            "" => format!("{sign}{rest}"),
+            hours => format!("{sign}{hours}:{rest}"),
+        }
+    }
+
+    /// 生成 FFmpeg `-ss` 参数的值，格式为 `HH:MM:SS.mmm`
+    ///
+    /// # 参数
+    /// * `pts` - 以时间基为单位的时间戳
+    ///
+    /// # 返回值
+    /// 相对于视频开始时间的位置，早于开始时间时为 `00:00:00.000`
+    pub fn to_ffmpeg_seek_arg(&self, pts: i64) -> String {
+        format_milliseconds(self.timestamp_to_milliseconds(pts).max(0))
+    }
+
+    /// 生成 FFmpeg `-t` 参数的值，格式为 `HH:MM:SS.mmm`
+    ///
+    /// # 参数
+    /// * `from_pts` - 开始的时间戳
+    /// * `to_pts` - 结束的时间戳
+    ///
+    /// # 返回值
+    /// 两个时间戳之间的时长，`to_pts` 早于 `from_pts` 时为 `00:00:00.000`
+    pub fn to_ffmpeg_to_arg(&self, from_pts: i64, to_pts: i64) -> String {
+        let ms = self.timestamp_to_milliseconds(to_pts) as i128
+            - self.timestamp_to_milliseconds(from_pts) as i128;
+        format_milliseconds(ms.clamp(0, i64::MAX.into()) as i64)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl VideoInfo {
+    /// 序列化为JSON，可以缓存探测结果供下次使用
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("VideoInfo only contains plain data")
+    }
+
+    /// 从 [`VideoInfo::to_json`] 生成的JSON中读取视频信息，不检查参数是否有效
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// 将毫秒数格式化为 `hh:mm:ss.mmm`，负数带有 `-` 前缀
+fn format_milliseconds(ms: i64) -> String {
+    let sign = if ms < 0 { "-" } else { "" };
+    let ms = ms.unsigned_abs();
+    format!(
+        "{sign}{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// 逐帧生成时间戳的状态
+///
+/// 创建时把每帧的时间戳增量预先换算为整数商和余数，
+/// 之后每次前进只需要整数加法，不会像 `frame_index as f64 / fps` 那样累积误差
+#[derive(Debug, Clone)]
+pub struct FrameTimestamps {
+    /// 开始时间
+    start: i64,
+    /// 每帧增量的整数部分
+    step: i128,
+    /// 每帧增量的余数部分，分母为 `divisor`
+    step_rem: i128,
+    /// 增量的分母
+    divisor: i128,
+    /// 当前帧时间戳（未取整）的整数部分
+    pts: i128,
+    /// 当前帧时间戳（未取整）的余数部分
+    rem: i128,
+    /// 舍入方式，来自视频信息
+    rounding: Rounding,
+}
+
+impl FrameTimestamps {
+    /// 创建逐帧时间戳的状态
+    ///
+    /// # 参数
+    /// * `info` - 视频信息，提供时间基和开始时间
+    /// * `fps_num` - 帧率的分子，例如 29.97fps 为 30000
+    /// * `fps_den` - 帧率的分母，例如 29.97fps 为 1001
+    /// * `first_frame` - 第一个返回的帧序号
+    pub fn new(info: &VideoInfo, fps_num: i64, fps_den: i64, first_frame: u64) -> Self {
+        // 第n帧的时间戳为 n * fps_den * tb_den / (fps_num * tb_num)
+        let numerator = fps_den as i128 * info.time_base_den as i128;
+        let divisor = (fps_num as i128 * info.time_base_num as i128).max(1);
+        let first = first_frame as i128 * numerator;
+        Self {
+            start: if info.start_time == AV_NOPTS_VALUE {
+                0
+            } else {
+                info.start_time
+            },
+            step: numerator / divisor,
+            step_rem: numerator % divisor,
+            divisor,
+            pts: first / divisor,
+            rem: first % divisor,
+            rounding: info.rounding,
+        }
+    }
+}
+
+impl Iterator for FrameTimestamps {
+    type Item = i64;
+
+    /// 返回当前帧的时间戳（按视频信息的舍入方式取整）并前进一帧
+    fn next(&mut self) -> Option<i64> {
+        let carry = match self.rounding {
+            Rounding::Floor => false,
+            Rounding::Nearest => self.rem * 2 >= self.divisor,
+            Rounding::Ceil => self.rem > 0,
+        };
+        let pts = self.start + (self.pts + i128::from(carry)) as i64;
+        self.pts += self.step;
+        self.rem += self.step_rem;
+        if self.rem >= self.divisor {
+            self.rem -= self.divisor;
+            self.pts += 1;
         }
+        Some(pts)
     }
 }
 
+/// 创建逐帧时间戳的状态，参见 [`FrameTimestamps::new`]
+///
+/// 返回的指针需要通过 `free_frame_timestamps` 释放
 #[unsafe(no_mangle)]
-pub extern "C" fn free_parse(res_ctx: *mut ArgParseResultContext) {
-    if res_ctx.is_null() {
+pub extern "C" fn create_frame_timestamps(
+    info: &VideoInfo,
+    fps_num: i64,
+    fps_den: i64,
+    first_frame: u64,
+) -> *mut FrameTimestamps {
+    Box::into_raw(Box::new(FrameTimestamps::new(
+        info,
+        fps_num,
+        fps_den,
+        first_frame,
+    )))
+}
+
+/// 返回当前帧的时间戳并前进一帧
+#[unsafe(no_mangle)]
+pub extern "C" fn next_frame_timestamp(state: &mut FrameTimestamps) -> i64 {
+    state.next().unwrap_or_default()
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn free_frame_timestamps(state: *mut FrameTimestamps) {
+    if state.is_null() {
         return;
     }
     unsafe {
-        _ = Box::from_raw(res_ctx);
+        _ = Box::from_raw(state);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeTypeKind {
+    Frame = 0,
+    #[default]
+    Millisecond = 1,
+    End = 2,
+    Nanosecond = 3,
+    /// 结束前的时长，以纳秒为单位
+    FromEnd = 4,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaserTimeType {
+    pub kind: TimeTypeKind,
+    pub value: u64,
+}
+
+/// 视频时长未知时引用结束时间的错误信息
+const DURATION_UNKNOWN: &str = "video duration unknown; specify an explicit --to";
+
+/// 时间戳超出 `i64` 范围时的错误信息
+const TIMESTAMP_OVERFLOW: &str = "timestamp overflow";
+
+impl PaserTimeType {
+    /// 根据视频信息转换为时间戳，需要结束时间但视频时长未知、或结果超出 `i64` 范围时返回错误
+    fn to_timestamp(self, info: &VideoInfo) -> Result<i64, &'static str> {
+        let duration = |dur: Duration| {
+            info.checked_duration_to_timestamp(dur)
+                .ok_or(TIMESTAMP_OVERFLOW)
+        };
+        match self.kind {
+            TimeTypeKind::End => info.checked_end_to_timestamp().ok_or(DURATION_UNKNOWN),
+            TimeTypeKind::Frame => info
+                .checked_frame_to_timestamp(self.value)
+                .ok_or(TIMESTAMP_OVERFLOW),
+            TimeTypeKind::Millisecond => duration(Duration::from_millis(self.value)),
+            TimeTypeKind::Nanosecond => duration(Duration::from_nanos(self.value)),
+            TimeTypeKind::FromEnd => {
+                let end = info.checked_end_to_timestamp().ok_or(DURATION_UNKNOWN)?;
+                // 时长本身不包含开始时间，减去 `checked_duration_to_timestamp` 加上的开始时间
+                let offset = duration(Duration::from_nanos(self.value))?
+                    .checked_sub(info.start_offset())
+                    .ok_or(TIMESTAMP_OVERFLOW)?;
+                end.checked_sub(offset).ok_or(TIMESTAMP_OVERFLOW)
+            }
+        }
+    }
+}
+
+pub struct ArgParseResultContext {
+    pub input: *const c_char,
+    pub output: *const c_char,
+    pub thread_count: u16,
+    /// 实际使用的线程数，`auto` 在解析时换算为可用的并行度，不会为0
+    pub resolved_thread_count: u16,
+    pub format: *const c_char,
+    pub stream_index: u32,
+    pub dry_run: bool,
+    /// 均匀选取的帧数，为0时提取范围内的所有帧
+    pub sample_count: u64,
+
+    start: TimeType,
+    end: TimeType,
+    step: TimeType,
+    /// `--interval` 指定的按时间采样的间隔，指定时代替 `step`
+    interval: Option<Duration>,
+    /// `--extra-args` 指定的参数，原样交给调用方
+    extra_args: Vec<CString>,
+    /// 按升序排列的关键帧时间戳，为空表示没有提供关键帧表，见 [`set_keyframes`]
+    keyframes: Vec<i64>,
+    #[cfg(feature = "dsl")]
+    vars: HashMap<String, lexer::CheckedExpr>,
+    /// `--at` 和 `--at-file` 指定的时间点，按出现的顺序排列
+    #[cfg(feature = "dsl")]
+    points: Vec<lexer::CheckedExpr>,
+    /// 表达式名称到源字符串及其来源的映射，用于在求值出错时输出诊断信息
+    ///
+    /// `--at-file` 中的每个时间点共用同一份文件内容
+    #[cfg(feature = "dsl")]
+    sources: HashMap<String, (Rc<str>, String)>,
+}
+
+impl ArgParseResultContext {
+    /// 创建一个不依赖命令行的构建器
+    pub fn builder() -> ArgParseResultContextBuilder {
+        ArgParseResultContextBuilder::default()
+    }
+
+    /// 检查起止时间是否构成有效的范围
+    ///
+    /// 起止时间相同且恰好落在某一帧上时只提取这一帧，视为有效
+    pub fn validate_range(&self, info: &VideoInfo) -> Result<(), RangeError> {
+        let (from_ts, to_ts) = evaluate_pair(self, info);
+        if from_ts > to_ts {
+            return Err(RangeError::FromAfterTo { from_ts, to_ts });
+        }
+        info.frame_range(from_ts, to_ts)
+            .map(|_| ())
+            .ok_or(RangeError::EmptyRange)
+    }
+
+    /// 设置关键帧时间戳表，会复制并排序 `keyframes`，传入空切片时清除关键帧表
+    pub fn set_keyframes(&mut self, keyframes: &[i64]) {
+        self.keyframes = keyframes.to_vec();
+        self.keyframes.sort_unstable();
+        self.keyframes.dedup();
+    }
+
+    /// 查找不晚于 `pts` 的最后一个关键帧
+    ///
+    /// 没有关键帧表或 `pts` 早于第一个关键帧时返回 `None`
+    pub fn keyframe_before(&self, pts: i64) -> Option<i64> {
+        let index = self.keyframes.partition_point(|&keyframe| keyframe <= pts);
+        index.checked_sub(1).map(|index| self.keyframes[index])
+    }
+
+    /// 查找不早于 `pts` 的第一个关键帧
+    ///
+    /// 没有关键帧表或 `pts` 晚于最后一个关键帧时返回 `None`
+    pub fn keyframe_after(&self, pts: i64) -> Option<i64> {
+        let index = self.keyframes.partition_point(|&keyframe| keyframe < pts);
+        self.keyframes.get(index).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 起止时间构成的范围无效
+pub enum RangeError {
+    /// 起始时间晚于结束时间
+    FromAfterTo { from_ts: i64, to_ts: i64 },
+    /// 范围内没有任何一帧
+    EmptyRange,
+}
+
+impl std::fmt::Display for RangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FromAfterTo { from_ts, to_ts } => {
+                write!(f, "`from` ({from_ts}) is after `to` ({to_ts})")
+            }
+            Self::EmptyRange => f.write_str("no frame between `from` and `to`"),
+        }
+    }
+}
+
+impl std::error::Error for RangeError {}
+
+/// 复制由上下文持有的C字符串，空指针仍为空指针
+fn dup_cstring(s: *const c_char) -> *const c_char {
+    if s.is_null() {
+        return s;
+    }
+    unsafe { CStr::from_ptr(s) }.to_owned().into_raw()
+}
+
+/// 按内容比较两个C字符串，两个都是空指针时视为相同
+fn cstr_eq(a: *const c_char, b: *const c_char) -> bool {
+    match (a.is_null(), b.is_null()) {
+        (false, false) => unsafe { CStr::from_ptr(a) == CStr::from_ptr(b) },
+        (a, b) => a && b,
+    }
+}
+
+impl Clone for ArgParseResultContext {
+    /// 复制上下文，`input`、`output`、`format` 会复制一份新的字符串
+    fn clone(&self) -> Self {
+        Self {
+            input: dup_cstring(self.input),
+            output: dup_cstring(self.output),
+            thread_count: self.thread_count,
+            resolved_thread_count: self.resolved_thread_count,
+            format: dup_cstring(self.format),
+            stream_index: self.stream_index,
+            dry_run: self.dry_run,
+            sample_count: self.sample_count,
+            start: self.start.clone(),
+            end: self.end.clone(),
+            step: self.step.clone(),
+            interval: self.interval,
+            extra_args: self.extra_args.clone(),
+            keyframes: self.keyframes.clone(),
+            #[cfg(feature = "dsl")]
+            vars: self.vars.clone(),
+            #[cfg(feature = "dsl")]
+            points: self.points.clone(),
+            #[cfg(feature = "dsl")]
+            sources: self.sources.clone(),
+        }
+    }
+}
+
+impl PartialEq for ArgParseResultContext {
+    /// 逐字段比较，`input`、`output`、`format` 按字符串内容比较
+    fn eq(&self, other: &Self) -> bool {
+        #[cfg(feature = "dsl")]
+        if self.vars != other.vars || self.points != other.points || self.sources != other.sources {
+            return false;
+        }
+        cstr_eq(self.input, other.input)
+            && cstr_eq(self.output, other.output)
+            && cstr_eq(self.format, other.format)
+            && self.thread_count == other.thread_count
+            && self.resolved_thread_count == other.resolved_thread_count
+            && self.stream_index == other.stream_index
+            && self.dry_run == other.dry_run
+            && self.sample_count == other.sample_count
+            && self.start == other.start
+            && self.end == other.end
+            && self.step == other.step
+            && self.interval == other.interval
+            && self.extra_args == other.extra_args
+            && self.keyframes == other.keyframes
+    }
+}
+
+impl Drop for ArgParseResultContext {
+    fn drop(&mut self) {
+        for s in [self.input, self.output, self.format] {
+            free_cstring(s as *mut c_char);
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+enum TimeType {
+    Parser(PaserTimeType),
+    #[cfg(feature = "dsl")]
+    DSL(lexer::CheckedExpr),
+}
+
+/// `时:分:秒` 格式的时间戳中小数部分最多的位数，即精确到毫秒
+pub(crate) const TIMESTAMP_FRACTION_DIGITS: usize = 3;
+
+/// 将秒的小数部分转换为纳秒，例如 `"5"` 为 500000000
+///
+/// 小数部分必须由1到9位数字组成，否则返回 `None`
+pub(crate) fn fraction_to_nanos(digits: &str) -> Option<u32> {
+    if digits.is_empty() || digits.len() > 9 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    format!("{digits:0<9}").parse().ok()
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
+pub enum Time {
+    Frame(u64),
+    Time(Duration),
+    End,
+    /// 相对于结束的时间，例如 `-5s` 表示结束前5秒
+    FromEnd(Duration),
+}
+
+impl std::str::FromStr for Time {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.to_lowercase() == "end" {
+            return Ok(Self::End);
+        }
+        if let Some(rest) = s.strip_prefix('-') {
+            return match rest.parse::<Self>()? {
+                Self::Time(t) => Ok(Self::FromEnd(t)),
+                _ => Err(format!(
+                    "only a duration can be relative to the end, found '{rest}'"
+                )),
+            };
+        }
+        if let Ok(frame) = s.parse::<u64>() {
+            return Ok(Self::Frame(frame));
+        }
+        if let Some(sub) = s.strip_suffix("ms") {
+            return sub
+                .parse::<f64>()
+                .ok()
+                .and_then(|v| Duration::try_from_secs_f64(v / 1000f64).ok())
+                .map(Self::Time)
+                .ok_or_else(|| format!("Wrong ms format: '{sub}'"));
+        }
+        if s.ends_with('s') {
+            let sub = s.chars().take(s.len() - 1).collect::<String>();
+            let Ok(v) = sub.parse::<f64>() else {
+                return Err(format!("Wrong second format: '{sub}'"));
+            };
+            return Duration::try_from_secs_f64(v)
+                .map(Self::Time)
+                .map_err(|_| format!("seconds out of range: '{sub}'"));
+        }
+        // `ms` 已经在上面处理，这里的 `m` 不会和它混淆
+        for (suffix, unit) in [
+            ("min", 60f64),
+            ("hr", 3600f64),
+            ("m", 60f64),
+            ("h", 3600f64),
+        ] {
+            let Some(sub) = s.strip_suffix(suffix) else {
+                continue;
+            };
+            return sub
+                .parse::<f64>()
+                .ok()
+                .and_then(|v| Duration::try_from_secs_f64(v * unit).ok())
+                .map(Self::Time)
+                .ok_or_else(|| format!("Wrong {suffix} format: '{sub}'"));
+        }
+        let segments = s.split(':').collect::<Vec<_>>();
+        if segments.len() > 3 || segments.len() < 2 {
+            return Err("Wrong time format".to_string());
+        }
+        let mut segs = segments.iter();
+        let hour = if segments.len() == 3 {
+            segs.next()
+                .unwrap()
+                .parse::<u64>()
+                .map_err(|err| err.to_string())?
+        } else {
+            0
+        };
+        let min = segs
+            .next()
+            .unwrap()
+            .parse::<u64>()
+            .map_err(|err| err.to_string())?;
+        // 字幕中的时间码用 `,` 分隔毫秒，例如 `00:01:23,500`
+        let mut secs = segs.next().unwrap().split(['.', ',']);
+        let sec = secs
+            .next()
+            .unwrap()
+            .parse::<u64>()
+            .map_err(|err| err.to_string())?;
+        let nanos = match secs.next() {
+            Some(fraction) => fraction_to_nanos(fraction)
+                .filter(|_| fraction.len() <= TIMESTAMP_FRACTION_DIGITS)
+                .ok_or_else(|| "fractional part must have 1 to 3 digits".to_string())?,
+            None => 0,
+        };
+        let sec = hour
+            .checked_mul(3600)
+            .and_then(|secs| secs.checked_add(min.checked_mul(60)?))
+            .and_then(|secs| secs.checked_add(sec))
+            .ok_or_else(|| format!("time out of range: '{s}'"))?;
+        Ok(Self::Time(Duration::new(sec, nanos)))
+    }
+}
+
+impl From<Time> for PaserTimeType {
+    fn from(value: Time) -> Self {
+        match value {
+            // 纳秒数超出 `u64` 的范围时改为以毫秒保存，而不是取最大值
+            Time::Time(t) => match u64::try_from(t.as_nanos()) {
+                Ok(value) => Self {
+                    kind: TimeTypeKind::Nanosecond,
+                    value,
+                },
+                Err(_) => Self {
+                    kind: TimeTypeKind::Millisecond,
+                    value: u64::try_from(t.as_millis()).unwrap_or(u64::MAX),
+                },
+            },
+            Time::Frame(f) => Self {
+                kind: TimeTypeKind::Frame,
+                value: f,
+            },
+            Time::End => Self {
+                kind: TimeTypeKind::End,
+                value: 0,
+            },
+            Time::FromEnd(t) => Self {
+                kind: TimeTypeKind::FromEnd,
+                value: u64::try_from(t.as_nanos()).unwrap_or(u64::MAX),
+            },
+        }
+    }
+}
+
+impl From<Time> for TimeType {
+    fn from(value: Time) -> Self {
+        Self::Parser(value.into())
+    }
+}
+
+/// 将时长格式化为秒，例如 `90s`、`1.5s`
+fn write_seconds(f: &mut std::fmt::Formatter<'_>, dur: Duration) -> std::fmt::Result {
+    let nanos = dur.subsec_nanos();
+    if nanos == 0 {
+        write!(f, "{}s", dur.as_secs())
+    } else {
+        let decimal = format!("{nanos:09}");
+        write!(f, "{}.{}s", dur.as_secs(), decimal.trim_end_matches('0'))
+    }
+}
+
+impl std::fmt::Display for PaserTimeType {
+    /// 格式化为可以重新解析的字符串，例如 `100`、`1.5s`、`end`、`-5s`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            TimeTypeKind::Frame => write!(f, "{}", self.value),
+            TimeTypeKind::Millisecond => write_seconds(f, Duration::from_millis(self.value)),
+            TimeTypeKind::End => f.write_str("end"),
+            TimeTypeKind::Nanosecond => write_seconds(f, Duration::from_nanos(self.value)),
+            TimeTypeKind::FromEnd => {
+                f.write_str("-")?;
+                write_seconds(f, Duration::from_nanos(self.value))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TimeType {
+    /// DSL表达式输出优化后的形式，例如 `end + 2s + 3s` 输出为 `end + 5s`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parser(per) => write!(f, "{per}"),
+            #[cfg(feature = "dsl")]
+            Self::DSL(expr) => write!(f, "{expr}"),
+        }
+    }
+}
+
+impl TimeType {
+    /// 使用 `--from-frame`/`--to-frame` 指定的帧序号，未指定时使用 `parse` 的结果
+    fn frame_or_else(frame: Option<u64>, parse: impl FnOnce() -> Self) -> Self {
+        match frame {
+            Some(frame) => Time::Frame(frame).into(),
+            None => parse(),
+        }
+    }
+
+    /// 表达式引用的其他表达式名称
+    #[cfg(feature = "dsl")]
+    fn references(&self) -> Vec<&str> {
+        match self {
+            Self::Parser(..) => vec![],
+            Self::DSL(expr) => expr.references().collect(),
+        }
+    }
+
+    /// 表达式中是否直接引用了 `to`
+    fn references_to(&self) -> bool {
+        match self {
+            Self::Parser(..) => false,
+            #[cfg(feature = "dsl")]
+            Self::DSL(expr) => expr.references_to(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadCount {
+    Auto,
+    Custom(u16),
+}
+
+impl ThreadCount {
+    /// 根据 `std::thread::available_parallelism` 得到线程数，获取失败时为1
+    pub fn from_available_parallelism() -> Self {
+        let count = std::thread::available_parallelism()
+            .map(|count| u16::try_from(count.get()).unwrap_or(u16::MAX))
+            .unwrap_or(1);
+        Self::Custom(count.max(1))
+    }
+
+    /// 获取指定的线程数，`Auto` 时返回 `None`
+    pub fn count(&self) -> Option<u16> {
+        match self {
+            Self::Auto => None,
+            Self::Custom(count) => Some(*count),
+        }
+    }
+
+    /// 线程数的上限，为可用并行度的 [`THREAD_COUNT_FACTOR`] 倍
+    pub fn max_count() -> u16 {
+        resolve_thread_count(Self::Auto).saturating_mul(THREAD_COUNT_FACTOR)
+    }
+
+    /// 将超过 `max` 的线程数限制为 `max`
+    ///
+    /// # 返回值
+    /// 限制后的线程数，以及是否发生了限制
+    pub fn clamp_to(self, max: u16) -> (Self, bool) {
+        match self {
+            Self::Custom(count) if count > max => (Self::Custom(max), true),
+            tc => (tc, false),
+        }
+    }
+}
+
+/// 线程数上限相对于可用并行度的倍数，见 [`ThreadCount::max_count`]
+pub const THREAD_COUNT_FACTOR: u16 = 8;
+
+/// 得到实际使用的线程数，`Auto` 时使用可用的并行度
+pub fn resolve_thread_count(tc: ThreadCount) -> u16 {
+    let tc = if tc == ThreadCount::Auto {
+        ThreadCount::from_available_parallelism()
+    } else {
+        tc
+    };
+    tc.count().unwrap_or(1)
+}
+
+impl From<ThreadCount> for u16 {
+    fn from(value: ThreadCount) -> Self {
+        match value {
+            ThreadCount::Auto => 0,
+            ThreadCount::Custom(v) => v,
+        }
+    }
+}
+
+impl std::str::FromStr for ThreadCount {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(Self::Auto)
+        } else {
+            s.parse::<u16>()
+                .map(Self::Custom)
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// `ArgParseResultContext` 的构建器，供不经过命令行的Rust代码使用
+///
+/// 未设置的字段使用与命令行相同的默认值，`format` 不会被校验
+#[derive(Debug, Clone)]
+pub struct ArgParseResultContextBuilder {
+    input: String,
+    output: String,
+    from: Time,
+    to: Time,
+    step: Time,
+    interval: Option<Duration>,
+    sample_count: u64,
+    thread_count: ThreadCount,
+    format: String,
+    extra_args: Vec<String>,
+}
+
+impl Default for ArgParseResultContextBuilder {
+    fn default() -> Self {
+        Self {
+            input: String::new(),
+            output: ".".to_string(),
+            from: Time::Frame(0),
+            to: Time::End,
+            step: Time::Frame(1),
+            interval: None,
+            sample_count: 0,
+            thread_count: ThreadCount::Auto,
+            format: "frame-%d.jpg".to_string(),
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+impl ArgParseResultContextBuilder {
+    pub fn input(mut self, path: &str) -> Self {
+        self.input = path.to_string();
+        self
+    }
+
+    pub fn output(mut self, path: &str) -> Self {
+        self.output = path.to_string();
+        self
+    }
+
+    pub fn from(mut self, time: Time) -> Self {
+        self.from = time;
+        self
+    }
+
+    pub fn to(mut self, time: Time) -> Self {
+        self.to = time;
+        self
+    }
+
+    pub fn step(mut self, time: Time) -> Self {
+        self.step = time;
+        self
+    }
+
+    /// 按时间间隔采样，代替 `step`
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn count(mut self, count: u64) -> Self {
+        self.sample_count = count;
+        self
+    }
+
+    pub fn thread_count(mut self, t: ThreadCount) -> Self {
+        self.thread_count = t;
+        self
+    }
+
+    pub fn format(mut self, fmt: &str) -> Self {
+        self.format = fmt.to_string();
+        self
+    }
+
+    /// 追加一个传给 FFmpeg 的参数
+    pub fn extra_arg(mut self, arg: &str) -> Self {
+        self.extra_args.push(arg.to_string());
+        self
+    }
+
+    /// 构建上下文，字符串在这里才转换为 `CString`
+    pub fn build(self) -> ArgParseResultContext {
+        ArgParseResultContext {
+            input: CString::new(self.input).unwrap_or_default().into_raw(),
+            output: CString::new(self.output).unwrap_or_default().into_raw(),
+            thread_count: self.thread_count.into(),
+            resolved_thread_count: resolve_thread_count(self.thread_count),
+            format: CString::new(self.format).unwrap_or_default().into_raw(),
+            stream_index: 0,
+            dry_run: false,
+            sample_count: self.sample_count,
+            start: self.from.into(),
+            end: self.to.into(),
+            step: self.step.into(),
+            interval: self.interval,
+            extra_args: to_cstrings(self.extra_args),
+            keyframes: Vec::new(),
+            #[cfg(feature = "dsl")]
+            vars: HashMap::new(),
+            #[cfg(feature = "dsl")]
+            points: Vec::new(),
+            #[cfg(feature = "dsl")]
+            sources: HashMap::new(),
+        }
+    }
+}
+
+/// 将字符串列表转换为 `CString`，包含空字符的字符串转换为空字符串
+fn to_cstrings(strings: Vec<String>) -> Vec<CString> {
+    strings
+        .into_iter()
+        .map(|s| CString::new(s).unwrap_or_default())
+        .collect()
+}
+
+/// 解析 `--interval`，只接受正的时长，例如 `2s`、`500ms`
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    #[cfg(feature = "dsl")]
+    let interval = match s.parse::<lexer::DSLType>()? {
+        lexer::DSLType::Timestamp(interval) => Some(interval),
+        _ => None,
+    };
+    #[cfg(not(feature = "dsl"))]
+    let interval = match s.parse::<Time>()? {
+        Time::Time(interval) => Some(interval),
+        _ => None,
+    };
+    match interval {
+        Some(interval) if interval.is_zero() => Err("interval must be positive".to_string()),
+        Some(interval) => Ok(interval),
+        None => Err(format!("interval must be a duration, found '{s}'")),
+    }
+}
+
+/// 解析 `--step`，只接受正的帧数或时长
+#[cfg(not(feature = "dsl"))]
+fn parse_step(s: &str) -> Result<Time, String> {
+    match s.parse::<Time>()? {
+        Time::Frame(0) => Err("step must be positive".to_string()),
+        Time::Time(t) if t.is_zero() => Err("step must be positive".to_string()),
+        time @ (Time::Frame(..) | Time::Time(..)) => Ok(time),
+        _ => Err(format!(
+            "step must be a frame count or a duration, found '{s}'"
+        )),
+    }
+}
+
+#[cfg(feature = "dsl")]
+#[derive(Debug, Clone)]
+struct Binding {
+    name: String,
+    expr: String,
+}
+
+#[cfg(feature = "dsl")]
+impl std::str::FromStr for Binding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, expr)) = s.split_once('=') else {
+            return Err("expected `NAME=EXPR`".to_string());
+        };
+        let name = name.trim();
+        if !lexer::is_var_name(name) {
+            return Err(format!(
+                "invalid variable name `{name}`, expected an identifier that is not a keyword"
+            ));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            expr: expr.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "A simple video frame picker\n\nTips:\n\t`xxx` is frame index\n\t`xx:xx.xx` is timestamp\n\t`end` is the end of video\n\t`xx.xxs` is seconds-base timestamp"
+)]
+struct Cli {
+    // 只有在使用 `--generate-completions` 时才会为空
+    #[arg(
+        short,
+        long,
+        help = "The video path",
+        value_hint = ValueHint::FilePath,
+        required = true
+    )]
+    input: Option<String>,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        short,
+        long,
+        value_name = "expr",
+        help = "time expression, `dur` and `duration` are aliases of `end`",
+        default_value = "0f"
+    )]
+    from: String,
+    #[cfg(not(feature = "dsl"))]
+    #[arg(
+        short,
+        long,
+        help = "possible format: [xxx, xx.xxs, xx.xxm, xx.xxh, xx:xx.xx, end], prefix a duration with `-` to count from the end",
+        allow_hyphen_values = true,
+        default_value = "0"
+    )]
+    from: Time,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        short,
+        long,
+        value_name = "expr",
+        help = "time expression, `dur` and `duration` are aliases of `end`",
+        default_value = "end"
+    )]
+    to: String,
+    #[cfg(not(feature = "dsl"))]
+    #[arg(
+        short,
+        long,
+        help = "possible format: [xxx, xx.xxs, xx.xxm, xx.xxh, xx:xx.xx, end], prefix a duration with `-` to count from the end",
+        allow_hyphen_values = true,
+        default_value = "end"
+    )]
+    to: Time,
+    #[arg(
+        long,
+        value_name = "n",
+        help = "start at frame n, a shortcut for `--from` without time expressions",
+        conflicts_with = "from"
+    )]
+    from_frame: Option<u64>,
+    #[arg(
+        long,
+        value_name = "n",
+        help = "stop at frame n, a shortcut for `--to` without time expressions",
+        conflicts_with = "to"
+    )]
+    to_frame: Option<u64>,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        long,
+        value_name = "expr",
+        help = "extract every Nth frame, e.g. `30f`, or a duration such as `1s`",
+        default_value = "1f"
+    )]
+    step: String,
+    #[cfg(not(feature = "dsl"))]
+    #[arg(
+        long,
+        help = "extract every Nth frame, possible format: [xxx, xx.xxs, xx.xxm, xx.xxh, xx:xx.xx]",
+        value_parser = parse_step,
+        default_value = "1"
+    )]
+    step: Time,
+    #[arg(
+        long,
+        value_name = "duration",
+        help = "extract a frame every given duration, e.g. `2s`",
+        value_parser = parse_interval,
+        conflicts_with_all = ["step", "count"]
+    )]
+    interval: Option<Duration>,
+    #[arg(
+        long,
+        value_name = "Auto|num",
+        help = "thread count for codec",
+        default_value = "auto"
+    )]
+    thread_count: ThreadCount,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        long = "let",
+        value_name = "NAME=EXPR",
+        help = "define a variable for time expressions, can be repeated"
+    )]
+    lets: Vec<Binding>,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        long,
+        value_name = "expr",
+        help = "pick the single frame at the time expression, can be repeated",
+        conflicts_with_all = ["count", "step", "interval"]
+    )]
+    at: Vec<String>,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        long,
+        value_name = "path",
+        help = "read `--at` time expressions from a file, one per line, `-` for stdin",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["count", "step", "interval"]
+    )]
+    at_file: Option<String>,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        long,
+        visible_alias = "strict",
+        help = "treat warnings in time expressions as errors"
+    )]
+    deny_warnings: bool,
+    #[cfg(feature = "dsl")]
+    #[arg(
+        long,
+        conflicts_with = "deny_warnings",
+        help = "do not print warnings for time expressions"
+    )]
+    no_warnings: bool,
+    #[arg(long, help = "filename format", default_value = "frame-%d.jpg")]
+    format: String,
+    #[arg(
+        long,
+        value_name = "index",
+        help = "index of the video stream to pick frames from",
+        default_value_t = 0
+    )]
+    stream: u32,
+    #[arg(
+        long,
+        help = "print the resolved timestamps and exit without extracting frames"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        value_name = "n",
+        help = "pick n evenly spaced frames between from and to, including both ends",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "step"
+    )]
+    count: Option<u64>,
+    #[arg(
+        long,
+        value_name = "arg",
+        help = "extra argument kept for the calling program as is, repeat for each argument; the caller is responsible for escaping",
+        num_args = 1,
+        action = ArgAction::Append,
+        allow_hyphen_values = true
+    )]
+    extra_args: Vec<String>,
+    #[arg(help = "Output path", default_value = ".", value_hint = ValueHint::DirPath)]
+    output: String,
+    #[arg(
+        long,
+        value_name = "path",
+        help = "read default options from a TOML file, `pick-frame.toml` in the current directory is used if present",
+        value_hint = ValueHint::FilePath
+    )]
+    config: Option<String>,
+    #[arg(
+        long,
+        value_name = "shell",
+        help = "print a shell completion script to stdout and exit",
+        hide = true,
+        exclusive = true
+    )]
+    generate_completions: Option<clap_complete::Shell>,
+}
+
+impl Cli {
+    /// 解析命令行参数并合并配置文件中的默认值，出错时输出错误并退出
+    fn parse_with_config() -> Self {
+        let matches = Cli::command().get_matches();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        if cli.generate_completions.is_some() {
+            return cli;
+        }
+        let config = config::Config::load(cli.config.as_deref())
+            .unwrap_or_else(|msg| Cli::command().error(clap::error::ErrorKind::Io, msg).exit());
+        if let Err(msg) = cli.apply_config(config, &matches) {
+            Cli::command()
+                .error(clap::error::ErrorKind::ValueValidation, msg)
+                .exit();
+        }
+        cli
+    }
+
+    /// 用配置文件中的值替换命令行中未指定的选项
+    ///
+    /// # 参数
+    /// * `config` - 配置文件中的选项
+    /// * `matches` - 命令行的解析结果，用于判断选项是否使用了默认值
+    fn apply_config(
+        &mut self,
+        config: config::Config,
+        matches: &clap::ArgMatches,
+    ) -> Result<(), String> {
+        let is_default = |id: &str| matches.value_source(id) == Some(ValueSource::DefaultValue);
+        if let Some(format) = config.format
+            && is_default("format")
+        {
+            self.format = format;
+        }
+        if let Some(count) = config.thread_count
+            && is_default("thread_count")
+        {
+            self.thread_count = match count {
+                config::ThreadCountValue::Count(count) => ThreadCount::Custom(count),
+                config::ThreadCountValue::Text(text) => text
+                    .parse()
+                    .map_err(|err| format!("invalid `thread-count` in config: {err}"))?,
+            };
+        }
+        if let Some(from) = config.from
+            && is_default("from")
+        {
+            self.from = from
+                .parse()
+                .map_err(|err| format!("invalid `from` in config: {err}"))?;
+        }
+        if let Some(to) = config.to
+            && is_default("to")
+        {
+            self.to = to
+                .parse()
+                .map_err(|err| format!("invalid `to` in config: {err}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// 生成指定shell的补全脚本
+///
+/// # 参数
+/// * `shell` - 目标shell
+/// * `out` - 输出位置
+fn generate_completions(shell: clap_complete::Shell, out: &mut dyn std::io::Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "pick-frame", out);
+}
+
+/// 向标准错误输出错误信息后以退出码 `$code`（默认为1）退出，与诊断信息一样遵循 `NO_COLOR`
+#[cfg(feature = "dsl")]
+macro_rules! err {
+    ($info:expr) => {
+        err!($info, 1)
+    };
+    ($info:expr, $code:literal) => {{
+        use colored::Colorize;
+        tui::write_diagnostic(
+            &mut std::io::stderr(),
+            &format!("{} {}", "error:".bright_red(), $info),
+            tui::use_color(),
+        );
+        std::process::exit($code);
+    }};
+}
+
+/// 读取表达式的源字符串
+///
+/// 以 `@` 开头时从对应的文件中读取表达式（去掉末尾换行），
+/// 并以文件路径作为错误信息中的来源
+///
+/// # 返回值
+/// 表达式的源字符串和来源
+#[cfg(feature = "dsl")]
+fn read_expr_source(content: &str, content_type: &str) -> (String, String) {
+    let Some(path) = content.strip_prefix('@') else {
+        return (content.to_string(), content_type.to_string());
+    };
+    match std::fs::read_to_string(path) {
+        Ok(text) => (
+            text.trim_end_matches(['\r', '\n']).to_string(),
+            path.to_string(),
+        ),
+        Err(e) => err!(
+            format!("cannot read expression of `{content_type}` from `{path}`: {e}").bright_white(),
+            2
+        ),
+    }
+}
+
+/// 读取 `--at-file` 的内容，`-` 表示从标准输入读取
+///
+/// # 返回值
+/// 文件内容和用于错误信息的来源
+#[cfg(feature = "dsl")]
+fn read_at_file(path: &str) -> std::io::Result<(String, String)> {
+    if path == "-" {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+        Ok((content, "<stdin>".to_string()))
+    } else {
+        Ok((std::fs::read_to_string(path)?, path.to_string()))
+    }
+}
+
+/// 将文本按行拆分为表达式，跳过空行和以 `#` 开头的注释行
+///
+/// 每一行保留在整个文本中的偏移量和行号，出错时可以指出具体的行
+#[cfg(feature = "dsl")]
+fn expression_lines(content: &str) -> Vec<lexer::Span<'_>> {
+    // 逐行前进，`take_from` 只需要统计当前行中的换行符
+    let mut rest = lexer::Span::new(content);
+    let mut lines = Vec::new();
+    for line in content.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        let trimmed = text.trim_start();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            lines.push(rest.take(text.len()));
+        }
+        rest = rest.take_from(line.len());
+    }
+    lines
+}
+
+/// 向标准错误输出一条警告
+fn warn(message: &str, help: &str) {
+    #[cfg(feature = "dsl")]
+    tui::write_diagnostic(
+        &mut std::io::stderr(),
+        &tui::render_warning(message, Some(help)),
+        tui::use_color(),
+    );
+    #[cfg(not(feature = "dsl"))]
+    eprintln!("warning: {message}\n  = help: {help}");
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn parse() -> *mut ArgParseResultContext {
+    let mut cli = Cli::parse_with_config();
+    if let Some(shell) = cli.generate_completions {
+        generate_completions(shell, &mut std::io::stdout());
+        std::process::exit(0);
+    }
+    let (thread_count, clamped) = cli.thread_count.clamp_to(ThreadCount::max_count());
+    if clamped {
+        let message = format!(
+            "thread count {} is too large, using {} instead",
+            u16::from(cli.thread_count),
+            u16::from(thread_count)
+        );
+        let help = format!("at most {THREAD_COUNT_FACTOR} times the available parallelism");
+        warn(&message, &help);
+        cli.thread_count = thread_count;
+    }
+    if let Err(err) = format::validate_format_string(&cli.format) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ValueValidation,
+                format!(
+                    "invalid value '{}' for '--format <FORMAT>': {err}",
+                    cli.format
+                ),
+            )
+            .exit();
+    }
+    #[cfg(feature = "dsl")]
+    {
+        let mut names = Vec::<&str>::new();
+        for binding in cli.lets.iter() {
+            if names.contains(&binding.name.as_str()) {
+                err!(
+                    format!("variable `{}` is defined more than once", binding.name).bright_white(),
+                    2
+                );
+            }
+            names.push(&binding.name);
+        }
+        // `input` 是 `content` 中的一段，错误位置相对于整个 `content`
+        let compile = |content: &str,
+                       content_type: &str,
+                       input: lexer::Span,
+                       options: &lexer::CheckOptions| {
+            let (_, mut expr) = tui::handle_error(
+                &mut std::io::stderr(),
+                content,
+                content_type,
+                lexer::parse_expr_complete(input),
+            );
+            tui::check_vars(content, content_type, &expr, &names);
+            let lints = lexer::lint_expr_with(&expr, options);
+            if !cli.no_warnings {
+                for lint in lints.iter() {
+                    tui::write_diagnostic(
+                        &mut std::io::stderr(),
+                        &tui::render_lint(content, content_type, lint, cli.deny_warnings),
+                        tui::use_color(),
+                    );
+                }
+            }
+            if cli.deny_warnings && !lints.is_empty() {
+                std::process::exit(2);
+            }
+            lexer::optimize_expr(&mut expr)
+                .map_err(|err| err!(format!("{content_type}: {err}"), 2))
+                .unwrap();
+            lexer::check_expr_with(&expr, options)
+                .map_err(|err| err!(format!("{content_type}: {err}"), 2))
+                .unwrap()
+        };
+        let parse_dsl = |content: &str, content_type: &str, options: &lexer::CheckOptions| {
+            let (content, content_type) = read_expr_source(content, content_type);
+            let expr = compile(&content, &content_type, content.as_str().into(), options);
+            (expr, (Rc::from(content), content_type))
+        };
+
+        let mut sources = HashMap::new();
+        // 变量不一定表示视频中的位置，与 `--step` 一样不要求基准
+        let let_options = lexer::CheckOptions {
+            positional: false,
+            ..Default::default()
+        };
+        let vars = cli
+            .lets
+            .iter()
+            .map(|binding| {
+                let content_type = format!("let {}", binding.name);
+                let (expr, source) = parse_dsl(&binding.expr, &content_type, &let_options);
+                sources.insert(binding.name.clone(), source);
+                (binding.name.clone(), expr)
+            })
+            .collect::<HashMap<_, _>>();
+        let options = lexer::CheckOptions {
+            anchored_vars: lexer::anchored_vars(&vars),
+            ..Default::default()
+        };
+        let start = TimeType::frame_or_else(cli.from_frame, || {
+            let (from_expr, source) = parse_dsl(&cli.from, "from", &options);
+            sources.insert("from".to_string(), source);
+            TimeType::DSL(from_expr)
+        });
+        let end = TimeType::frame_or_else(cli.to_frame, || {
+            let (to_expr, source) = parse_dsl(&cli.to, "to", &options);
+            sources.insert("to".to_string(), source);
+            TimeType::DSL(to_expr)
+        });
+        let (step_expr, source) = parse_dsl(
+            &cli.step,
+            "step",
+            &lexer::CheckOptions {
+                allowed_keywords: Default::default(),
+                require_positive: true,
+                positional: false,
+                ..Default::default()
+            },
+        );
+        sources.insert("step".to_string(), source);
+        let mut points = Vec::new();
+        for content in cli.at.iter() {
+            let (expr, source) = parse_dsl(content, "at", &options);
+            sources.insert(format!("at[{}]", points.len()), source);
+            points.push(expr);
+        }
+        if let Some(path) = cli.at_file.as_deref() {
+            let (content, content_type) = match read_at_file(path) {
+                Ok(content) => content,
+                Err(e) => err!(
+                    format!("cannot read `--at-file` from `{path}`: {e}").bright_white(),
+                    2
+                ),
+            };
+            let content = Rc::<str>::from(content);
+            for line in expression_lines(&content) {
+                points.push(compile(&content, &content_type, line, &options));
+                sources.insert(
+                    format!("at[{}]", points.len() - 1),
+                    (Rc::clone(&content), content_type.clone()),
+                );
+            }
+        }
+
+        {
+            let mut deps = HashMap::new();
+            deps.insert("from", start.references());
+            deps.insert("to", end.references());
+            for (name, expr) in vars.iter() {
+                deps.insert(name.as_str(), expr.references().collect());
+            }
+            if let Some(cycle) = lexer::find_cycle(&deps) {
+                err!(
+                    format!("circular references: {}", cycle.join(" -> ")).bright_white(),
+                    2
+                );
+            }
+        }
+
+        Box::into_raw(Box::new(ArgParseResultContext {
+            input: CString::new(cli.input.unwrap_or_default())
+                .unwrap_or_default()
+                .into_raw(),
+            output: CString::new(cli.output).unwrap_or_default().into_raw(),
+            format: CString::new(cli.format).unwrap_or_default().into_raw(),
+            thread_count: cli.thread_count.into(),
+            resolved_thread_count: resolve_thread_count(cli.thread_count),
+            stream_index: cli.stream,
+            dry_run: cli.dry_run,
+            sample_count: cli.count.unwrap_or_default(),
+            start,
+            end,
+            step: TimeType::DSL(step_expr),
+            interval: cli.interval,
+            extra_args: to_cstrings(cli.extra_args),
+            keyframes: Vec::new(),
+            vars,
+            points,
+            sources,
+        }))
+    }
+    #[cfg(not(feature = "dsl"))]
+    Box::into_raw(Box::new(ArgParseResultContext {
+        input: CString::new(cli.input.unwrap_or_default())
+            .unwrap_or_default()
+            .into_raw(),
+        output: CString::new(cli.output).unwrap_or_default().into_raw(),
+        start: TimeType::frame_or_else(cli.from_frame, || cli.from.into()),
+        end: TimeType::frame_or_else(cli.to_frame, || cli.to.into()),
+        step: cli.step.into(),
+        interval: cli.interval,
+        extra_args: to_cstrings(cli.extra_args),
+        keyframes: Vec::new(),
+        thread_count: cli.thread_count.into(),
+        resolved_thread_count: resolve_thread_count(cli.thread_count),
+        format: CString::new(cli.format).unwrap_or_default().into_raw(),
+        stream_index: cli.stream,
+        dry_run: cli.dry_run,
+        sample_count: cli.count.unwrap_or_default(),
+    }))
+}
+
+/// 返回的字符串由上下文持有，在调用 `free_parse` 之前有效，不要释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_input(res_ctx: &ArgParseResultContext) -> *const c_char {
+    res_ctx.input
+}
+
+/// 返回的字符串由上下文持有，在调用 `free_parse` 之前有效，不要释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_output(res_ctx: &ArgParseResultContext) -> *const c_char {
+    res_ctx.output
+}
+
+/// 获取 `--extra-args` 指定的参数个数
+#[unsafe(no_mangle)]
+pub extern "C" fn get_extra_args_len(res_ctx: &ArgParseResultContext) -> u32 {
+    u32::try_from(res_ctx.extra_args.len()).unwrap_or(u32::MAX)
+}
+
+/// 获取 `--extra-args` 指定的第 `idx` 个参数，越界时返回空指针
+///
+/// 参数原样交给调用方，不做任何转义。
+/// 返回的字符串由上下文持有，在调用 `free_parse` 之前有效，不要释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_extra_arg(res_ctx: &ArgParseResultContext, idx: u32) -> *const c_char {
+    res_ctx
+        .extra_args
+        .get(idx as usize)
+        .map_or(std::ptr::null(), |arg| arg.as_ptr())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn get_thread_count(res_ctx: &ArgParseResultContext) -> u16 {
+    res_ctx.thread_count
+}
+
+/// 获取实际使用的线程数，`auto` 已换算为可用的并行度，不会返回0
+#[unsafe(no_mangle)]
+pub extern "C" fn get_resolved_thread_count(res_ctx: &ArgParseResultContext) -> u16 {
+    res_ctx.resolved_thread_count
+}
+
+/// 返回的字符串由上下文持有，在调用 `free_parse` 之前有效，不要释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_format(res_ctx: &ArgParseResultContext) -> *const c_char {
+    res_ctx.format
+}
+
+/// 根据文件名格式生成文件名，参见 [`format::format_filename`]
+///
+/// 返回的字符串需要通过 `free_cstring` 释放，`fmt` 为空指针时返回空指针
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn format_filename_c(
+    fmt: *const c_char,
+    frame_index: u64,
+    timestamp_ms: u64,
+    fps: f64,
+) -> *mut c_char {
+    if fmt.is_null() {
+        return std::ptr::null_mut();
+    }
+    let fmt = unsafe { CStr::from_ptr(fmt) }.to_string_lossy();
+    let name = format::format_filename(&fmt, frame_index, timestamp_ms, fps);
+    CString::new(name).unwrap_or_default().into_raw()
+}
+
+/// 将时间戳格式化为 `H:MM:SS.mmm`，见 [`VideoInfo::format_timestamp`]
+///
+/// 返回的字符串需要通过 [`free_string`] 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn format_timestamp_c(info: &VideoInfo, ts: i64) -> *mut c_char {
+    CString::new(info.format_timestamp(ts))
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// 释放由本库返回的字符串
+///
+/// 所有以 `char *` 返回的字符串都由 Rust 分配，必须通过此函数或 [`free_string`] 释放，不能使用 `free()`：
+/// [`format_filename_c`]、[`format_timestamp_c`]、[`get_from_expr_string`]、[`get_to_expr_string`]，
+/// 以及开启 `serde` 功能时的 `video_info_to_json`。
+///
+/// 以 `const char *` 返回的字符串由上下文持有，不需要也不能释放：
+/// [`get_input`]、[`get_output`]、[`get_format`]、[`get_version`]。
+///
+/// 传入空指针时不做任何事
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn free_cstring(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    unsafe {
+        _ = CString::from_raw(s);
+    }
+}
+
+/// 与 [`free_cstring`] 相同，释放由本库返回的 `char *` 字符串
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn free_string(ptr: *mut c_char) {
+    free_cstring(ptr);
+}
+
+/// 获取 `--stream` 指定的视频流索引
+///
+/// 这里只保证索引非负，是否越界需要调用方结合视频信息自行检查，
+/// 例如对只有一个视频流的文件指定 `--stream 1` 时，调用方应当报错
+#[unsafe(no_mangle)]
+pub extern "C" fn get_stream_index(res_ctx: &ArgParseResultContext) -> u32 {
+    res_ctx.stream_index
+}
+
+/// 获取库的版本号，例如 `0.1.1`
+///
+/// 返回的字符串位于静态内存中，不要释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// 获取库的主版本号
+#[unsafe(no_mangle)]
+pub extern "C" fn get_version_major() -> u32 {
+    env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or_default()
+}
+
+/// 获取库的次版本号
+#[unsafe(no_mangle)]
+pub extern "C" fn get_version_minor() -> u32 {
+    env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or_default()
+}
+
+/// 获取库的修订号
+#[unsafe(no_mangle)]
+pub extern "C" fn get_version_patch() -> u32 {
+    env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or_default()
+}
+
+/// 是否指定了 `--dry-run`，为true时调用方应调用 [`print_dry_run`] 而不提取帧
+#[unsafe(no_mangle)]
+pub extern "C" fn get_dry_run(res_ctx: &ArgParseResultContext) -> bool {
+    res_ctx.dry_run
+}
+
+/// `--dry-run` 最多列出的文件名数量
+const DRY_RUN_PREVIEW: u64 = 5;
+
+/// 计算将要提取的帧数以及其中前 `limit` 帧的帧序号和时间戳，顺序与调用方提取的顺序一致
+///
+/// 依次对应 `--at`、`--count` 和按 `--step`（或 `--interval`）提取范围内的帧，
+/// 表达式无法求值时返回错误
+fn planned_frames(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    limit: u64,
+) -> Result<(u64, Vec<(u64, i64)>), EvalError> {
+    let with_index = |pts: i64| (info.timestamp_to_frame(pts), pts);
+    let at_count = get_at_count(res_ctx);
+    if at_count > 0 {
+        let frames = (0..at_count.min(limit))
+            .map(|index| at_timestamp(res_ctx, index, info).map(with_index))
+            .collect::<Result<_, _>>()?;
+        return Ok((at_count, frames));
+    }
+    let (from, to) = try_evaluate_pair(res_ctx, info, false)?;
+    if res_ctx.sample_count > 0 {
+        let frames = (0..res_ctx.sample_count.min(limit))
+            .map(|index| with_index(get_sample_timestamp(res_ctx, index, info)))
+            .collect();
+        return Ok((res_ctx.sample_count, frames));
+    }
+    if from > to {
+        return Ok((0, Vec::new()));
+    }
+    if let Some((ticks, _)) = interval_ticks(res_ctx, info) {
+        // 与调用方一致，从起始时间开始累加采样点，每个采样点取不早于它的第一帧
+        let frame_at = |target: i64| {
+            let frame = info.timestamp_to_frame(target);
+            if info.frame_to_timestamp(frame) < target {
+                frame + 1
+            } else {
+                frame
+            }
+        };
+        let target = |index: u64| from.saturating_add((index as i64).saturating_mul(ticks));
+        let mut count = (to.saturating_sub(from) / ticks.max(1)) as u64 + 1;
+        if info.frame_to_timestamp(frame_at(target(count - 1))) > to {
+            count -= 1;
+        }
+        let frames = (0..count.min(limit))
+            .map(|index| {
+                let frame = frame_at(target(index));
+                (frame, info.frame_to_timestamp(frame))
+            })
+            .collect();
+        return Ok((count, frames));
+    }
+    let first = info.timestamp_to_frame(from);
+    let step = get_step_frames(res_ctx, info);
+    let count = info.timestamp_to_frame(to).saturating_sub(first) / step + 1;
+    let frames = (0..count.min(limit))
+        .map(|index| {
+            let frame = first + index * step;
+            (frame, info.frame_to_timestamp(frame))
+        })
+        .collect();
+    Ok((count, frames))
+}
+
+/// 与调用方相同，按 `av_rescale_q(pts, time_base, 1/1000)` 将时间戳换算为毫秒，不减去开始时间
+fn rescale_to_milliseconds(info: &VideoInfo, pts: i64) -> i64 {
+    let ms = Rounding::Nearest.div(
+        pts as i128 * info.time_base_num as i128 * 1000,
+        info.time_base_den.max(1) as i128,
+    );
+    ms.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+/// 生成 `--dry-run` 的输出，包含起止时间戳、`--at` 指定的时间点及其对应的时间和帧序号，
+/// 以及将要提取的帧数和前几个文件名，表达式无法求值时返回错误
+fn dry_run_report(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> Result<String, EvalError> {
+    let line = |name: &str, pts: i64| {
+        format!(
+            "{name}: {pts} ({}, frame {})",
+            info.timestamp_to_display(pts),
+            info.timestamp_to_frame(pts)
+        )
+    };
+    let (from, to) = try_evaluate_pair(res_ctx, info, false)?;
+    let mut report = format!("{}\n{}", line("from", from), line("to", to));
+    for index in 0..get_at_count(res_ctx) {
+        let pts = at_timestamp(res_ctx, index, info)?;
+        report += "\n";
+        report += &line(&format!("at[{index}]"), pts);
+    }
+    let (count, frames) = planned_frames(res_ctx, info, DRY_RUN_PREVIEW)?;
+    report += &format!("\noutputs: {count}");
+    if !res_ctx.format.is_null() {
+        let fmt = unsafe { CStr::from_ptr(res_ctx.format) }.to_string_lossy();
+        for (frame, pts) in &frames {
+            let ms = rescale_to_milliseconds(info, *pts).max(0) as u64;
+            report += "\n  ";
+            report += &format::format_filename(&fmt, *frame, ms, info.fps);
+        }
+        if count > frames.len() as u64 {
+            report += &format!("\n  ... and {} more", count - frames.len() as u64);
+        }
+    }
+    Ok(report)
+}
+
+/// 打印解析出的起止时间戳后以退出码0退出
+///
+/// 调用方应在 [`get_dry_run`] 返回true且得到视频信息后调用
+///
+/// # 返回值
+/// 表达式无法求值时记录错误并返回1，可通过 [`print_last_error`] 输出，成功时不会返回
+#[unsafe(no_mangle)]
+pub extern "C" fn print_dry_run(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> c_int {
+    store_checked(dry_run_report(res_ctx, info), |report| {
+        println!("{report}");
+        std::process::exit(0);
+    })
+}
+
+/// 检查解析出的起止时间是否构成有效的范围，见 [`ArgParseResultContext::validate_range`]
+///
+/// 起止时间依赖视频信息，无法在解析参数时检查，调用方应在得到视频信息后调用
+///
+/// # 返回值
+/// 范围有效时返回0，否则输出错误信息并返回1
+#[unsafe(no_mangle)]
+pub extern "C" fn validate_range(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> c_int {
+    match res_ctx.validate_range(info) {
+        Ok(()) => 0,
+        // 错误信息中的时间戳换算为时间，便于阅读
+        Err(RangeError::FromAfterTo { from_ts, to_ts }) => {
+            eprintln!(
+                "error: invalid range: `from` ({}) is after `to` ({})",
+                info.timestamp_to_display(from_ts),
+                info.timestamp_to_display(to_ts)
+            );
+            1
+        }
+        Err(err) => {
+            eprintln!("error: invalid range: {err}");
+            1
+        }
+    }
+}
+
+/// 表达式求值错误，记录出错的项以便输出诊断信息
+#[derive(Debug, Clone, PartialEq)]
+struct EvalError {
+    /// 出错的表达式名称，`from`、`to`、`step` 或变量名
+    name: String,
+    /// 出错的项在表达式中的位置
+    #[cfg(feature = "dsl")]
+    span: lexer::SourceSpan,
+    /// 错误信息
+    message: String,
+    /// 显示在出错的项下方的标注
+    #[cfg(feature = "dsl")]
+    label: &'static str,
+}
+
+impl EvalError {
+    /// 不指向具体的项的错误
+    fn new(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            #[cfg(feature = "dsl")]
+            span: lexer::SourceSpan::default(),
+            message: message.into(),
+            #[cfg(feature = "dsl")]
+            label: "",
+        }
+    }
+
+    #[cfg(feature = "dsl")]
+    fn overflow(name: &str, span: lexer::SourceSpan) -> Self {
+        Self {
+            name: name.to_string(),
+            span,
+            message: TIMESTAMP_OVERFLOW.to_string(),
+            label: "overflows here",
+        }
+    }
+}
+
+/// 计算单个项相对于视频开始时间的值
+///
+/// 表达式在不含开始时间的域中求值：每个项都减去开始时间，
+/// 求和后由 [`eval_time`]、[`eval_position`] 加上一次开始时间，
+/// 避免 `5s + 5s` 这样的多个项重复计入开始时间。
+/// `from`、`to`、`end` 本身是绝对时间戳，减去开始时间后参与求和
+///
+/// # 参数
+/// * `name` - 项所在的表达式名称，用于错误信息
+/// * `item` - 需要求值的项
+/// * `span` - 项在表达式中的位置
+/// * `positional` - 项是否表示视频中的位置，为true时帧数视为帧序号并检查是否超出总帧数，
+///   变量和函数参数中的项同样检查
+#[cfg(feature = "dsl")]
+fn eval_term(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    known: KnownTimes,
+    name: &str,
+    item: &lexer::DSLType,
+    span: lexer::SourceSpan,
+    positional: bool,
+) -> Result<i64, EvalError> {
+    let offset = info.start_offset();
+    let relative = |pts: i64| {
+        pts.checked_sub(offset)
+            .ok_or_else(|| EvalError::overflow(name, span))
+    };
+    Ok(match item {
+        lexer::DSLType::Keyword(keyword) => match keyword {
+            lexer::DSLKeywords::From => relative(match known.from {
+                Some(pts) => pts,
+                None => eval_time(res_ctx, info, known, "from", &res_ctx.start)?,
+            })?,
+            lexer::DSLKeywords::To => relative(match known.to {
+                Some(pts) => pts,
+                None => eval_time(res_ctx, info, known, "to", &res_ctx.end)?,
+            })?,
+            lexer::DSLKeywords::End => {
+                relative(info.checked_end_to_timestamp().ok_or_else(|| EvalError {
+                    name: name.to_string(),
+                    span,
+                    message: DURATION_UNKNOWN.to_string(),
+                    label: "duration unknown",
+                })?)?
+            }
+            lexer::DSLKeywords::Start => 0,
+        },
+        lexer::DSLType::FrameIndex(index) => {
+            if positional && let Err(message) = info.check_frame_index(*index) {
+                return Err(EvalError {
+                    name: name.to_string(),
+                    span,
+                    message,
+                    label: "no such frame",
+                });
+            }
+            relative(
+                info.checked_frame_to_timestamp(*index)
+                    .ok_or_else(|| EvalError::overflow(name, span))?,
+            )?
+        }
+        lexer::DSLType::Timestamp(dur) => relative(
+            info.checked_duration_to_timestamp(*dur)
+                .ok_or_else(|| EvalError::overflow(name, span))?,
+        )?,
+        lexer::DSLType::Timecode(timecode) => relative(
+            info.timecode_to_timestamp(timecode)
+                .map_err(|err| EvalError {
+                    name: name.to_string(),
+                    span,
+                    message: err.to_string(),
+                    label: "invalid timecode",
+                })?,
+        )?,
+        lexer::DSLType::Var(var) => eval_terms(
+            res_ctx,
+            info,
+            known,
+            var,
+            res_ctx.vars[var].spanned_terms(),
+            positional,
+        )?,
+        lexer::DSLType::Call { name: func, args } => {
+            let values = args
+                .iter()
+                .map(|arg| eval_terms(res_ctx, info, known, name, arg.spanned_terms(), positional))
+                .collect::<Result<Vec<_>, _>>()?;
+            let keyframe =
+                |find: fn(&ArgParseResultContext, i64) -> Option<i64>, side: &str, pts: i64| {
+                    if res_ctx.keyframes.is_empty() {
+                        return Err(EvalError {
+                            name: name.to_string(),
+                            span,
+                            message: format!(
+                                "`{func}` needs a keyframe table, supply it with `set_keyframes`"
+                            ),
+                            label: "no keyframe table",
+                        });
+                    }
+                    let pts = pts.saturating_add(offset);
+                    find(res_ctx, pts)
+                        .map(|pts| pts.saturating_sub(offset))
+                        .ok_or_else(|| EvalError {
+                            name: name.to_string(),
+                            span,
+                            message: format!("`{func}` found no keyframe {side} {pts}"),
+                            label: "no keyframe here",
+                        })
+                };
+            match func.as_str() {
+                "min" => values.iter().copied().min().unwrap_or_default(),
+                "max" => values.iter().copied().max().unwrap_or_default(),
+                // 单参数的函数在检查时已经保证恰好有一个参数
+                "clamp" => {
+                    let end = info.end_to_timestamp().saturating_sub(offset).max(0);
+                    values[0].clamp(0, end)
+                }
+                "keyframe_before" => keyframe(
+                    ArgParseResultContext::keyframe_before,
+                    "at or before",
+                    values[0],
+                )?,
+                "keyframe_after" => keyframe(
+                    ArgParseResultContext::keyframe_after,
+                    "at or after",
+                    values[0],
+                )?,
+                _ => unreachable!(),
+            }
+        }
+    })
+}
+
+/// 计算表达式中所有项的和，结果相对于视频的开始时间，见 [`eval_term`]
+///
+/// `positional` 为true时只有加上的项表示位置，减去的帧数只是偏移，例如 `end - 10f`
+#[cfg(feature = "dsl")]
+fn eval_terms<'a>(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    known: KnownTimes,
+    name: &str,
+    terms: impl Iterator<Item = (lexer::DSLOp, &'a lexer::DSLType, lexer::SourceSpan)>,
+    positional: bool,
+) -> Result<i64, EvalError> {
+    let mut pts = 0i64;
+    for (op, item, span) in terms {
+        let positional = positional && op == lexer::DSLOp::Add;
+        let value = eval_term(res_ctx, info, known, name, item, span, positional)?;
+        pts = op
+            .apply_checked(pts, value)
+            .ok_or_else(|| EvalError::overflow(name, span))?;
+    }
+    Ok(pts)
+}
+
+/// 计算起止时间的时间戳，表达式无法求值时返回错误
+#[cfg(feature = "dsl")]
+fn eval_time(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    known: KnownTimes,
+    name: &str,
+    time: &TimeType,
+) -> Result<i64, EvalError> {
+    match time {
+        TimeType::Parser(per) => per
+            .to_timestamp(info)
+            .map_err(|message| EvalError::new(name, message)),
+        TimeType::DSL(expr) => eval_terms(res_ctx, info, known, name, expr.spanned_terms(), false)?
+            .checked_add(info.start_offset())
+            .ok_or_else(|| EvalError::overflow(name, lexer::SourceSpan::default())),
+    }
+}
+
+/// 计算起止时间的时间戳，并检查结果是否在视频范围内
+///
+/// 结果超出范围时，错误指向最后一次使结果越界的项，
+/// 例如30秒的视频中 `end + 2:00:00` 的 `2:00:00`
+#[cfg(feature = "dsl")]
+fn eval_position(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    known: KnownTimes,
+    name: &str,
+    expr: &lexer::CheckedExpr,
+) -> Result<i64, EvalError> {
+    let start = info.start_offset();
+    // 时长未知时不检查是否超过结束时间
+    let end = info.end_to_timestamp().max(start);
+    // 超过结束时间为Less，早于开始时间为Greater
+    let side = |pts: i64| pts.clamp(start, end).cmp(&pts);
+    let mut relative = 0i64;
+    let mut pts = start;
+    let mut last_side = std::cmp::Ordering::Equal;
+    let mut culprit = None;
+    for (op, item, span) in expr.spanned_terms() {
+        // 加上的帧数视为帧序号，减去的帧数只是偏移，例如 `end - 10f`
+        let positional = op == lexer::DSLOp::Add;
+        let value = eval_term(res_ctx, info, known, name, item, span, positional)?;
+        relative = op
+            .apply_checked(relative, value)
+            .ok_or_else(|| EvalError::overflow(name, span))?;
+        pts = relative
+            .checked_add(start)
+            .ok_or_else(|| EvalError::overflow(name, span))?;
+        let current = side(pts);
+        if current != last_side && current.is_ne() {
+            culprit = Some(span);
+        }
+        last_side = current;
+    }
+    match position_error(info, name, pts) {
+        None => Ok(pts),
+        Some((message, label)) => Err(EvalError {
+            name: name.to_string(),
+            span: culprit.unwrap_or_default(),
+            message,
+            label,
+        }),
+    }
+}
+
+/// 检查时间戳是否在视频范围内，时长未知时不检查是否超过结束时间
+///
+/// # 返回值
+/// 超出范围时返回错误信息和显示在出错的项下方的标注
+fn position_error(info: &VideoInfo, name: &str, pts: i64) -> Option<(String, &'static str)> {
+    let start = info.start_offset();
+    let end = info.end_to_timestamp().max(start);
+    if pts > end {
+        Some((
+            format!(
+                "`{name}` is past the end of the video: {} > {}",
+                info.timestamp_to_display(pts),
+                info.timestamp_to_display(end)
+            ),
+            "exceeds the end here",
+        ))
+    } else if pts < start {
+        Some((
+            format!(
+                "`{name}` is before the start of the video: {} < {}",
+                info.timestamp_to_display(pts),
+                info.timestamp_to_display(start)
+            ),
+            "goes before the start here",
+        ))
+    } else {
+        None
+    }
+}
+
+/// 向标准错误输出求值错误
+///
+/// 保留了表达式的源字符串时指出出错的项，否则只输出错误信息
+#[cfg_attr(not(feature = "dsl"), allow(unused_variables))]
+fn write_eval_error(res_ctx: &ArgParseResultContext, err: &EvalError) {
+    #[cfg(feature = "dsl")]
+    if let Some((content, content_type)) = res_ctx.sources.get(&err.name)
+        && !err.label.is_empty()
+    {
+        tui::show_error(
+            &mut std::io::stderr(),
+            tui::use_color(),
+            &err.message,
+            content_type,
+            content,
+            err.span.offset,
+            err.span.length,
+            Some(err.label),
+            None::<&str>,
+        );
+        return;
+    }
+    eprintln!("error: {}: {}", err.name, err.message);
+}
+
+/// 输出求值错误后以退出码2退出，见 [`write_eval_error`]
+fn report_eval_error(res_ctx: &ArgParseResultContext, err: EvalError) -> ! {
+    write_eval_error(res_ctx, &err);
+    std::process::exit(2);
+}
+
+thread_local! {
+    /// 当前线程最近一次调用 `*_checked` 求值函数的错误
+    static LAST_EVAL_ERROR: std::cell::RefCell<Option<EvalError>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// 记录 `*_checked` 求值函数的结果，成功时调用 `write` 写出结果并返回0，失败时记录错误并返回1
+fn store_checked<T>(result: Result<T, EvalError>, write: impl FnOnce(T)) -> c_int {
+    match result {
+        Ok(value) => {
+            LAST_EVAL_ERROR.set(None);
+            write(value);
+            0
+        }
+        Err(err) => {
+            LAST_EVAL_ERROR.set(Some(err));
+            1
+        }
+    }
+}
+
+/// 获取当前线程最近一次调用 `*_checked` 求值函数的错误信息，没有错误时返回空指针
+///
+/// 返回的字符串需要通过 [`free_cstring`] 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_last_error() -> *mut c_char {
+    LAST_EVAL_ERROR.with_borrow(|err| match err {
+        Some(err) => CString::new(format!("{}: {}", err.name, err.message))
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    })
+}
+
+/// 向标准错误输出当前线程最近一次调用 `*_checked` 求值函数的错误，
+/// 保留了表达式的源字符串时指出出错的项，没有错误时不输出
+#[unsafe(no_mangle)]
+pub extern "C" fn print_last_error(res_ctx: &ArgParseResultContext) {
+    LAST_EVAL_ERROR.with_borrow(|err| {
+        if let Some(err) = err {
+            write_eval_error(res_ctx, err);
+        }
+    });
+}
+
+/// 逐帧遍历 `[from, to]` 范围内的帧，每隔 `step` 帧调用一次 `callback`
+///
+/// 起止帧由解析出的时间戳换算得到，回调的参数依次为帧序号、该帧的时间戳和 `user`，
+/// `step` 为0时按1处理
+#[unsafe(no_mangle)]
+pub extern "C" fn for_each_frame(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    step: u64,
+    callback: extern "C" fn(u64, i64, *mut c_void),
+    user: *mut c_void,
+) {
+    let (from, to) = evaluate_pair(res_ctx, info);
+    let Some(frames) = info.frame_range(from, to) else {
+        return;
+    };
+    for frame in frames.step_by(step.max(1) as usize) {
+        callback(frame, info.frame_to_timestamp(frame), user);
+    }
+}
+
+/// 将时间戳转换为帧序号，见 [`VideoInfo::timestamp_to_frame`]
+#[unsafe(no_mangle)]
+pub extern "C" fn timestamp_to_frame(info: &VideoInfo, ts: i64) -> u64 {
+    info.timestamp_to_frame(ts)
+}
+
+/// 获取视频的总帧数，见 [`VideoInfo::total_frames`]
+#[unsafe(no_mangle)]
+pub extern "C" fn total_frames(info: &VideoInfo) -> u64 {
+    info.total_frames()
+}
+
+/// 获取时间戳在 `[from_ts, to_ts]` 范围内的帧数，见 [`VideoInfo::frames_between`]
+#[unsafe(no_mangle)]
+pub extern "C" fn frames_between(info: &VideoInfo, from_ts: i64, to_ts: i64) -> u64 {
+    info.frames_between(from_ts, to_ts)
+}
+
+/// 按固定的关键帧间隔对齐时间戳，见 [`VideoInfo::keyframe_aligned_timestamp`]
+#[unsafe(no_mangle)]
+pub extern "C" fn keyframe_align_timestamp(
+    info: &VideoInfo,
+    pts: i64,
+    keyframe_interval: u32,
+) -> i64 {
+    info.keyframe_aligned_timestamp(pts, keyframe_interval)
+}
+
+/// 将时间戳对齐到关键帧的方式
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SnapMode {
+    /// 不晚于时间戳的最后一个关键帧，早于所有关键帧时取第一个关键帧
+    #[default]
+    Before = 0,
+    /// 不早于时间戳的第一个关键帧，晚于所有关键帧时取最后一个关键帧
+    After = 1,
+    /// 距离最近的关键帧，距离相同时取较早的关键帧
+    Nearest = 2,
+}
+
+impl SnapMode {
+    /// 将 `ts` 对齐到 `keyframes` 中的关键帧，`keyframes` 不需要排序
+    ///
+    /// # 返回值
+    /// 对齐后的时间戳，`keyframes` 为空时返回 `None`
+    pub fn snap(self, ts: i64, keyframes: &[i64]) -> Option<i64> {
+        let before = || keyframes.iter().copied().filter(|&key| key <= ts).max();
+        let after = || keyframes.iter().copied().filter(|&key| key >= ts).min();
+        match self {
+            Self::Before => before().or_else(|| keyframes.iter().copied().min()),
+            Self::After => after().or_else(|| keyframes.iter().copied().max()),
+            Self::Nearest => match (before(), after()) {
+                (Some(before), Some(after)) => Some(
+                    if (after as i128 - ts as i128) < (ts as i128 - before as i128) {
+                        after
+                    } else {
+                        before
+                    },
+                ),
+                (before, after) => before.or(after),
+            },
+        }
+    }
+}
+
+/// 将时间戳对齐到调用方提供的关键帧，只做时间戳的计算，不需要解码
+///
+/// # 参数
+/// * `ts` - 需要对齐的时间戳
+/// * `keyframes` - 关键帧时间戳数组，不需要排序
+/// * `len` - 数组的长度
+/// * `mode` - 对齐方式，见 [`SnapMode`]
+///
+/// # 返回值
+/// 对齐后的时间戳，`keyframes` 为空指针或 `len` 为0时原样返回 `ts`
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn snap_to_keyframe(
+    ts: i64,
+    keyframes: *const i64,
+    len: usize,
+    mode: SnapMode,
+) -> i64 {
+    if keyframes.is_null() {
+        return ts;
+    }
+    let keyframes = unsafe { std::slice::from_raw_parts(keyframes, len) };
+    mode.snap(ts, keyframes).unwrap_or(ts)
+}
+
+/// 视频的时长是否已知，未知时 `end` 无法求值，需要明确指定 `--to`
+#[unsafe(no_mangle)]
+pub extern "C" fn video_info_has_duration(info: &VideoInfo) -> bool {
+    info.has_duration()
+}
+
+/// 使用分数帧率精确地将帧序号转换为时间戳，见 [`VideoInfo::frame_to_timestamp_exact`]
+#[unsafe(no_mangle)]
+pub extern "C" fn frame_to_timestamp_exact(
+    info: &VideoInfo,
+    frame_index: u64,
+    fps_num: i64,
+    fps_den: i64,
+) -> i64 {
+    info.frame_to_timestamp_exact(frame_index, fps_num, fps_den)
+}
+
+/// 计算起止时间的时间戳，表达式无法求值时返回错误
+///
+/// `checked` 为true时还检查帧序号是否超出总帧数、结果是否在视频范围内
+#[cfg_attr(not(feature = "dsl"), allow(unused_variables))]
+fn resolve_time(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    known: KnownTimes,
+    name: &str,
+    time: &TimeType,
+    checked: bool,
+) -> Result<i64, EvalError> {
+    match time {
+        TimeType::Parser(per) => {
+            let pts = per
+                .to_timestamp(info)
+                .map_err(|message| EvalError::new(name, message))?;
+            if checked {
+                if per.kind == TimeTypeKind::Frame {
+                    info.check_frame_index(per.value)
+                        .map_err(|message| EvalError::new(name, message))?;
+                }
+                if let Some((message, _)) = position_error(info, name, pts) {
+                    return Err(EvalError::new(name, message));
+                }
+            }
+            Ok(pts)
+        }
+        #[cfg(feature = "dsl")]
+        TimeType::DSL(expr) if checked => eval_position(res_ctx, info, known, name, expr),
+        #[cfg(feature = "dsl")]
+        TimeType::DSL(..) => eval_time(res_ctx, info, known, name, time),
+    }
+}
+
+/// 获取起始时间的时间戳，表达式无法求值时输出错误并退出
+///
+/// 不检查结果是否在视频范围内，需要检查或不希望退出时使用 [`get_from_timestamp_checked`]
+#[unsafe(no_mangle)]
+pub extern "C" fn get_from_timestamp(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
+    resolve_time(
+        res_ctx,
+        info,
+        KnownTimes::default(),
+        "from",
+        &res_ctx.start,
+        false,
+    )
+    .unwrap_or_else(|err| report_eval_error(res_ctx, err))
+}
+
+/// 获取结束时间的时间戳，表达式无法求值时输出错误并退出
+///
+/// 不检查结果是否在视频范围内，需要检查或不希望退出时使用 [`get_to_timestamp_checked`]
+#[unsafe(no_mangle)]
+pub extern "C" fn get_to_timestamp(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
+    resolve_time(
+        res_ctx,
+        info,
+        KnownTimes::default(),
+        "to",
+        &res_ctx.end,
+        false,
+    )
+    .unwrap_or_else(|err| report_eval_error(res_ctx, err))
+}
+
+/// 获取起始时间的时间戳，并检查帧序号是否超出总帧数、结果是否在视频范围内
+///
+/// 不会退出，出错时可以通过 [`get_last_error`] 或 [`print_last_error`] 获取错误
+///
+/// # 返回值
+/// 成功时把时间戳写入 `out` 并返回0，否则返回1
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn get_from_timestamp_checked(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    out: *mut i64,
+) -> c_int {
+    let result = resolve_time(
+        res_ctx,
+        info,
+        KnownTimes::default(),
+        "from",
+        &res_ctx.start,
+        true,
+    );
+    store_checked(result, |pts| write_out(out, pts))
+}
+
+/// 与 [`get_from_timestamp_checked`] 相同，但获取结束时间
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn get_to_timestamp_checked(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    out: *mut i64,
+) -> c_int {
+    let result = resolve_time(
+        res_ctx,
+        info,
+        KnownTimes::default(),
+        "to",
+        &res_ctx.end,
+        true,
+    );
+    store_checked(result, |pts| write_out(out, pts))
+}
+
+/// 将结果写入调用方提供的指针，空指针时忽略
+fn write_out<T>(out: *mut T, value: T) {
+    if !out.is_null() {
+        unsafe { *out = value };
+    }
+}
+
+/// 将时间转换为C字符串，返回的字符串需要通过 [`free_cstring`] 释放
+fn time_to_cstring(time: &TimeType) -> *mut c_char {
+    CString::new(time.to_string())
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// 获取规范化后的起始时间字符串，DSL表达式为优化后的形式
+///
+/// 返回的字符串需要通过 [`free_cstring`] 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_from_expr_string(res_ctx: &ArgParseResultContext) -> *mut c_char {
+    time_to_cstring(&res_ctx.start)
+}
+
+/// 获取规范化后的结束时间字符串，DSL表达式为优化后的形式
+///
+/// 返回的字符串需要通过 [`free_cstring`] 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn get_to_expr_string(res_ctx: &ArgParseResultContext) -> *mut c_char {
+    time_to_cstring(&res_ctx.end)
+}
+
+/// 已经求出的起止时间戳，求值时直接使用而不重复计算
+#[derive(Debug, Default, Clone, Copy)]
+struct KnownTimes {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+/// 同时计算起止时间戳，每个表达式只求值一次，`checked` 见 [`resolve_time`]
+///
+/// 先计算被另一方引用的一方，并在计算另一方时直接使用其结果
+fn try_evaluate_pair(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    checked: bool,
+) -> Result<(i64, i64), EvalError> {
+    let mut known = KnownTimes::default();
+    if res_ctx.start.references_to() {
+        known.to = Some(resolve_time(
+            res_ctx,
+            info,
+            known,
+            "to",
+            &res_ctx.end,
+            checked,
+        )?);
+    }
+    let from = resolve_time(res_ctx, info, known, "from", &res_ctx.start, checked)?;
+    known.from = Some(from);
+    let to = match known.to {
+        Some(to) => to,
+        None => resolve_time(res_ctx, info, known, "to", &res_ctx.end, checked)?,
+    };
+    Ok((from, to))
+}
+
+/// 同时计算起止时间戳，表达式无法求值时输出错误并退出，见 [`try_evaluate_pair`]
+fn evaluate_pair(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> (i64, i64) {
+    try_evaluate_pair(res_ctx, info, false).unwrap_or_else(|err| report_eval_error(res_ctx, err))
+}
+
+/// 同时获取起止时间戳，见 [`get_from_timestamp`] 和 [`get_to_timestamp`]
+///
+/// `to` 引用了 `from` 时，`from` 只会求值一次
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn get_from_to_timestamps(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    from_out: *mut i64,
+    to_out: *mut i64,
+) {
+    let (from, to) = evaluate_pair(res_ctx, info);
+    write_out(from_out, from);
+    write_out(to_out, to);
+}
+
+/// 与 [`get_from_to_timestamps`] 相同，但像 [`get_from_timestamp_checked`] 一样检查结果且不会退出
+///
+/// # 返回值
+/// 成功时写入起止时间戳并返回0，否则返回1
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn get_from_to_timestamps_checked(
+    res_ctx: &ArgParseResultContext,
+    info: &VideoInfo,
+    from_out: *mut i64,
+    to_out: *mut i64,
+) -> c_int {
+    store_checked(try_evaluate_pair(res_ctx, info, true), |(from, to)| {
+        write_out(from_out, from);
+        write_out(to_out, to);
+    })
+}
+
+/// 获取均匀选取的帧数，为0时表示提取范围内的所有帧
+#[unsafe(no_mangle)]
+pub extern "C" fn get_sample_count(res_ctx: &ArgParseResultContext) -> u64 {
+    res_ctx.sample_count
+}
+
+/// 获取第 `index` 个均匀选取的时间戳，包括起止时间在内
+///
+/// 只选取一帧时返回起始时间，`index` 超出范围时返回结束时间
+#[unsafe(no_mangle)]
+pub extern "C" fn get_sample_timestamp(
+    res_ctx: &ArgParseResultContext,
+    index: u64,
+    info: &VideoInfo,
+) -> i64 {
+    let (from, to) = evaluate_pair(res_ctx, info);
+    if res_ctx.sample_count <= 1 {
+        return from;
+    }
+    let last = res_ctx.sample_count - 1;
+    if index >= last {
+        return to;
+    }
+    let offset = (to as i128 - from as i128) * index as i128 / last as i128;
+    (from as i128 + offset) as i64
+}
+
+/// 获取 `--at` 和 `--at-file` 指定的时间点数量，为0时按范围提取
+#[unsafe(no_mangle)]
+#[cfg_attr(not(feature = "dsl"), allow(unused_variables))]
+pub extern "C" fn get_at_count(res_ctx: &ArgParseResultContext) -> u64 {
+    #[cfg(feature = "dsl")]
+    return res_ctx.points.len() as u64;
+    #[cfg(not(feature = "dsl"))]
+    0
+}
+
+/// 获取第 `index` 个时间点的时间戳，`index` 超出范围时返回结束时间
+///
+/// 表达式无法求值或超出视频范围时输出错误并退出
+#[unsafe(no_mangle)]
+#[cfg_attr(not(feature = "dsl"), allow(unused_variables))]
+pub extern "C" fn get_at_timestamp(
+    res_ctx: &ArgParseResultContext,
+    index: u64,
+    info: &VideoInfo,
+) -> i64 {
+    at_timestamp(res_ctx, index, info).unwrap_or_else(|err| report_eval_error(res_ctx, err))
+}
+
+/// 与 [`get_at_timestamp`] 相同，但出错时不会退出，见 [`get_from_timestamp_checked`]
+///
+/// # 返回值
+/// 成功时把时间戳写入 `out` 并返回0，否则返回1
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn get_at_timestamp_checked(
+    res_ctx: &ArgParseResultContext,
+    index: u64,
+    info: &VideoInfo,
+    out: *mut i64,
+) -> c_int {
+    store_checked(at_timestamp(res_ctx, index, info), |pts| {
+        write_out(out, pts)
+    })
+}
+
+/// 计算第 `index` 个时间点的时间戳并检查是否在视频范围内，`index` 超出范围时为结束时间
+#[cfg_attr(not(feature = "dsl"), allow(unused_variables))]
+fn at_timestamp(
+    res_ctx: &ArgParseResultContext,
+    index: u64,
+    info: &VideoInfo,
+) -> Result<i64, EvalError> {
+    #[cfg(feature = "dsl")]
+    if let Some(expr) = res_ctx.points.get(index as usize) {
+        let name = format!("at[{index}]");
+        return eval_position(res_ctx, info, KnownTimes::default(), &name, expr);
+    }
+    resolve_time(
+        res_ctx,
+        info,
+        KnownTimes::default(),
+        "to",
+        &res_ctx.end,
+        true,
+    )
+}
+
+/// `--interval` 以时间基为单位的长度，短于一帧时按一帧计算，第二项表示是否被调整
+///
+/// 时间基比一帧还粗时一帧按一个时间基单位计算，因此结果至少为1。
+/// 未指定 `--interval` 时返回 `None`
+fn interval_ticks(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> Option<(i64, bool)> {
+    let interval = res_ctx.interval?;
+    let offset = info.start_offset();
+    let ticks = info.duration_to_timestamp(interval).saturating_sub(offset);
+    let one_frame = info.frame_to_timestamp(1).saturating_sub(offset).max(1);
+    Some(if ticks < one_frame {
+        (one_frame, true)
+    } else {
+        (ticks, false)
+    })
+}
+
+/// 获取 `--interval` 以时间基为单位的长度，不包含视频的开始时间，未指定时返回0
+///
+/// 调用方应从起始时间开始按这个长度累加采样点，每个采样点提取不早于它的第一帧，
+/// 间隔短于一帧时按一帧计算，并且只在第一次遇到时输出警告
+#[unsafe(no_mangle)]
+pub extern "C" fn get_interval_timestamp(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    let Some((ticks, clamped)) = interval_ticks(res_ctx, info) else {
+        return 0;
+    };
+    if clamped && !WARNED.swap(true, Ordering::Relaxed) {
+        let interval = res_ctx.interval.unwrap_or_default();
+        warn(
+            &format!("interval {interval:?} is shorter than one frame, using one frame instead"),
+            &format!("the video has {} frames per second", info.fps),
+        );
+    }
+    ticks
+}
+
+/// 计算 `--step` 对应的时间戳（包含开始时间），无法求值时输出错误并退出
+fn step_timestamp(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
+    match &res_ctx.step {
+        TimeType::Parser(per) => per
+            .to_timestamp(info)
+            .unwrap_or_else(|message| report_eval_error(res_ctx, EvalError::new("step", message))),
+        #[cfg(feature = "dsl")]
+        TimeType::DSL(..) => eval_time(res_ctx, info, KnownTimes::default(), "step", &res_ctx.step)
+            .unwrap_or_else(|err| report_eval_error(res_ctx, err)),
+    }
+}
+
+/// 获取 `--step` 以时间基为单位的长度，不包含视频的开始时间
+///
+/// 帧数按帧率换算，例如时间基为 1/90000 时 `2s` 为180000，供按时间戳跳帧的调用方使用
+#[unsafe(no_mangle)]
+pub extern "C" fn get_step_ticks(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> i64 {
+    step_timestamp(res_ctx, info).saturating_sub(info.start_offset())
+}
+
+/// 获取每隔多少帧提取一帧，不考虑 `--interval`，见 [`get_interval_timestamp`]
+///
+/// 时长按帧率换算为最接近的帧数，例如 29.97fps 下 `1s` 为30帧，结果至少为1
+#[unsafe(no_mangle)]
+pub extern "C" fn get_step_frames(res_ctx: &ArgParseResultContext, info: &VideoInfo) -> u64 {
+    let pts = step_timestamp(res_ctx, info);
+    let frame = info.timestamp_to_frame(pts);
+    let next = frame + 1;
+    let nearest = if info.frame_to_timestamp(next) - pts < pts - info.frame_to_timestamp(frame) {
+        next
+    } else {
+        frame
+    };
+    nearest.max(1)
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn free_parse(res_ctx: *mut ArgParseResultContext) {
+    if res_ctx.is_null() {
+        return;
+    }
+    unsafe {
+        _ = Box::from_raw(res_ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        let version = unsafe { CStr::from_ptr(get_version()) }.to_str().unwrap();
+        assert_eq!(version, env!("CARGO_PKG_VERSION"));
+        let parts = version
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            [
+                get_version_major(),
+                get_version_minor(),
+                get_version_patch()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_video_info_hash() {
+        let info = |fps| VideoInfo {
+            fps,
+            ..video_info()
+        };
+        assert_eq!(info(25f64), info(25f64));
+        assert_ne!(info(25f64), info(30f64));
+        let set = [info(25f64), info(25f64), info(29.97)]
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&info(29.97)));
+        assert_eq!(info(f64::NAN), info(f64::NAN));
+        assert_ne!(info(0f64), info(-0f64));
+    }
+
+    #[test]
+    fn test_timestamp_to_display() {
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: 9_000,
+            duration: 0,
+            ..video_info()
+        };
+        assert_eq!(info.timestamp_to_display(9_000), "00:00:00.000");
+        assert_eq!(info.timestamp_to_display(9_000 + 90_045), "00:00:01.000");
+        assert_eq!(
+            info.timestamp_to_display(9_000 + 90_000 * 3_723 + 45_000),
+            "01:02:03.500"
+        );
+        assert_eq!(info.timestamp_to_display(0), "-00:00:00.100");
+    }
+
+    #[test]
+    fn test_video_info_getters() {
+        let info = create_video_info_extended(29.97, 30_000, 1, 1_000, 900_000, 2);
+        assert!(!info.is_null());
+        assert_eq!(video_info_get_fps(info), 29.97);
+        assert_eq!(video_info_get_duration(info), 900_000);
+        assert_eq!(video_info_get_start_time(info), 1_000);
+        assert_eq!(video_info_get_time_base_num(info), 1);
+        assert_eq!(video_info_get_time_base_den(info), 30_000);
+
+        let copy = video_info_clone(info);
+        assert!(!copy.is_null());
+        assert_ne!(copy, info);
+        assert_eq!(unsafe { &*copy }, unsafe { &*info });
+        free_video_info(info);
+        assert_eq!(video_info_get_duration(copy), 900_000);
+        free_video_info(copy);
+
+        let null = std::ptr::null();
+        assert!(video_info_clone(null).is_null());
+        assert_eq!(video_info_get_fps(null), 0.0);
+        assert_eq!(video_info_get_duration(null), 0);
+        assert_eq!(video_info_get_time_base_den(null), 0);
+    }
+
+    #[test]
+    fn test_create_video_info_copy() {
+        let info = create_video_info(25.0, 1000, 1, 0, 60_000);
+        let copy = create_video_info_copy(info);
+        assert!(!copy.is_null());
+        assert!(video_info_equal(info, copy));
+
+        set_video_info_rounding(unsafe { &mut *copy }, Rounding::Ceil);
+        assert!(!video_info_equal(info, copy));
+        assert_eq!(unsafe { &*info }.rounding, Rounding::Nearest);
+        free_video_info(copy);
+
+        assert!(create_video_info_copy(std::ptr::null()).is_null());
+        assert!(video_info_clone(std::ptr::null()).is_null());
+        assert!(video_info_equal(std::ptr::null(), std::ptr::null()));
+        assert!(!video_info_equal(info, std::ptr::null()));
+        free_video_info(info);
+    }
+
+    #[test]
+    fn test_free_string() {
+        let fmt = CString::new("frame_{index}.png").unwrap();
+        for _ in 0..100 {
+            let name = format_filename_c(fmt.as_ptr(), 1, 0, 25.0);
+            assert!(!name.is_null());
+            free_string(name);
+            let name = format_filename_c(fmt.as_ptr(), 2, 0, 25.0);
+            free_cstring(name);
+        }
+        free_string(std::ptr::null_mut());
+        assert!(format_filename_c(std::ptr::null(), 0, 0, 25.0).is_null());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_video_info_json() {
+        let info = VideoInfo {
+            fps_rational: Some((30_000, 1001)),
+            rounding: Rounding::Floor,
+            nb_frames: None,
+            ..video_info()
+        };
+        assert_eq!(VideoInfo::from_json(&info.to_json()).unwrap(), info);
+
+        let live = VideoInfo {
+            start_time: AV_NOPTS_VALUE,
+            duration: AV_NOPTS_VALUE,
+            ..video_info()
+        };
+        let json = live.to_json();
+        assert!(json.contains(&AV_NOPTS_VALUE.to_string()));
+        assert_eq!(VideoInfo::from_json(&json).unwrap(), live);
+        assert!(VideoInfo::from_json("{}").is_err());
+
+        let ptr = video_info_to_json(&info);
+        let copy = video_info_from_json(ptr);
+        free_string(ptr);
+        assert_eq!(unsafe { &*copy }, &info);
+        free_video_info(copy);
+
+        let invalid = CString::new(
+            VideoInfo {
+                time_base_den: 0,
+                ..video_info()
+            }
+            .to_json(),
+        )
+        .unwrap();
+        assert!(video_info_from_json(invalid.as_ptr()).is_null());
+        assert_eq!(
+            video_info_last_error(),
+            VideoInfoError::ZeroTimeBaseDen.code()
+        );
+        let garbage = CString::new("not json").unwrap();
+        assert!(video_info_from_json(garbage.as_ptr()).is_null());
+        assert_eq!(video_info_last_error(), 0);
+        assert!(video_info_from_json(std::ptr::null()).is_null());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_time_type_json() {
+        for time in ["100", "1.5s", "end", "-5s"] {
+            let per = PaserTimeType::from(time.parse::<Time>().unwrap());
+            let json = serde_json::to_string(&per).unwrap();
+            assert_eq!(serde_json::from_str::<PaserTimeType>(&json).unwrap(), per);
+        }
+        let per = PaserTimeType {
+            kind: TimeTypeKind::Millisecond,
+            value: 1_500,
+        };
+        assert_eq!(
+            serde_json::to_string(&per).unwrap(),
+            r#"{"kind":"Millisecond","value":1500}"#
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let info = video_info();
+        assert_eq!(info.to_ffmpeg_seek_arg(0), "00:00:00.000");
+        assert_eq!(info.to_ffmpeg_seek_arg(3_723_500), "01:02:03.500");
+        assert_eq!(info.to_ffmpeg_to_arg(1_000, 2_500), "00:00:01.500");
+        assert_eq!(info.to_ffmpeg_to_arg(2_500, 1_000), "00:00:00.000");
+
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: 9_000,
+            ..video_info()
+        };
+        assert_eq!(info.timestamp_to_milliseconds(9_000 + 90_045), 1_000);
+        assert_eq!(info.to_ffmpeg_seek_arg(9_000 + 90_000 * 5), "00:00:05.000");
+        assert_eq!(info.to_ffmpeg_seek_arg(0), "00:00:00.000");
+        assert_eq!(
+            info.to_ffmpeg_to_arg(9_000 + 90_000, 9_000 + 90_000 * 61 + 45_000),
+            "00:01:00.500"
+        );
+    }
+
+    #[test]
+    fn test_dropframe_timecode() {
+        let ntsc = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            fps_rational: Some((30000, 1001)),
+            time_base_den: 90_000,
+            duration: 3_600 * 90_000,
+            ..video_info()
+        };
+        let frame = |info: &VideoInfo, timecode: &str| {
+            info.dropframe_timecode_to_timestamp(timecode)
+                .map(|pts| info.timestamp_to_frame(pts))
+        };
+        for (timecode, expected) in [
+            ("00:00:00;00", 0),
+            ("00:00:59;29", 1_799),
+            ("00:01:00;02", 1_800),
+            ("00:02:00;02", 3_598),
+            ("00:09:59;29", 17_981),
+            ("00:10:00;00", 17_982),
+            ("00:10:00;02", 17_984),
+            ("01:00:00;00", 107_892),
+            ("10:00:00;00", 1_078_920),
+        ] {
+            assert_eq!(frame(&ntsc, timecode), Ok(expected), "{timecode}");
+        }
+        assert_eq!(
+            ntsc.dropframe_timecode_to_timestamp("00:10:00;00"),
+            Ok(17_982 * 3003)
+        );
+
+        let ntsc60 = VideoInfo {
+            fps: 60000f64 / 1001f64,
+            fps_rational: Some((60000, 1001)),
+            ..ntsc
+        };
+        for (timecode, expected) in [
+            ("00:01:00;04", 3_600),
+            ("00:10:00;00", 35_964),
+            ("01:00:00;00", 215_784),
+        ] {
+            assert_eq!(frame(&ntsc60, timecode), Ok(expected), "{timecode}");
+        }
+        assert_eq!(
+            frame(&ntsc60, "00:01:00;03"),
+            Err(TimecodeError::DroppedFrame)
+        );
+
+        for (timecode, err) in [
+            ("00:01:00;00", TimecodeError::DroppedFrame),
+            ("00:01:00;01", TimecodeError::DroppedFrame),
+            ("00:00:00;30", TimecodeError::FieldOutOfRange),
+            ("00:00:60;00", TimecodeError::FieldOutOfRange),
+            ("00:60:00;00", TimecodeError::FieldOutOfRange),
+            ("00:10:00:02", TimecodeError::InvalidFormat),
+            ("00:10;02", TimecodeError::InvalidFormat),
+            ("00:10:-1;02", TimecodeError::InvalidFormat),
+            ("99999999999999999:00:00;00", TimecodeError::Overflow),
+        ] {
+            assert_eq!(
+                ntsc.dropframe_timecode_to_timestamp(timecode),
+                Err(err),
+                "{timecode}"
+            );
+        }
+        assert_eq!(
+            video_info().dropframe_timecode_to_timestamp("00:10:00;00"),
+            Err(TimecodeError::UnsupportedFps)
+        );
+        let timecode = "01:02:03;04".parse::<Timecode>().unwrap();
+        assert_eq!(timecode.to_string(), "01:02:03;04");
+    }
+
+    #[cfg(feature = "dsl")]
+    #[test]
+    fn test_dropframe_timecode_eval() {
+        let ntsc = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            fps_rational: Some((30000, 1001)),
+            time_base_den: 90_000,
+            duration: 3_600 * 90_000,
+            ..video_info()
+        };
+        let ctx = context("00:10:00;02", "00:10:00;02 + 1s");
+        assert_eq!(get_from_timestamp(&ctx, &ntsc), 17_984 * 3003);
+        assert_eq!(get_to_timestamp(&ctx, &ntsc), 17_984 * 3003 + 90_000);
+
+        let ctx = context("end - 00:01:00;00", "end");
+        let err = eval_position(
+            &ctx,
+            &ntsc,
+            KnownTimes::default(),
+            "from",
+            ctx_expr(&ctx.start),
+        )
+        .unwrap_err();
+        assert_eq!(err.message, TimecodeError::DroppedFrame.to_string());
+        assert_eq!((err.span.offset, err.span.length), (6, 11));
+
+        let ctx = context("00:10:00;02", "end");
+        let err = eval_position(
+            &ctx,
+            &video_info(),
+            KnownTimes::default(),
+            "from",
+            ctx_expr(&ctx.start),
+        )
+        .unwrap_err();
+        assert_eq!(err.message, TimecodeError::UnsupportedFps.to_string());
+        assert_eq!(err.label, "invalid timecode");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: 9_000,
+            ..video_info()
+        };
+        for (ms, expected) in [
+            (0, "00:00.000"),
+            (1, "00:00.001"),
+            (61_999, "01:01.999"),
+            (3_723_500, "1:02:03.500"),
+            (36_000_000 * 10, "100:00:00.000"),
+        ] {
+            let ts = info.milliseconds_to_timestamp(ms);
+            assert_eq!(info.timestamp_to_duration(ts), Duration::from_millis(ms));
+            assert_eq!(info.format_timestamp(ts), expected);
+        }
+        assert_eq!(info.format_timestamp(9_000 - 135_000), "-00:01.500");
+        assert_eq!(
+            info.timestamp_to_duration(9_000 - 135_000),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(info.format_timestamp(AV_NOPTS_VALUE), "N/A");
+        // 不足一毫秒的部分向零取整
+        assert_eq!(info.format_timestamp(9_000 + 89), "00:00.000");
+        assert_eq!(info.format_timestamp(9_000 - 89), "00:00.000");
+        assert_eq!(info.format_timestamp(9_000 - 90), "-00:00.001");
+        assert_eq!(
+            info.timestamp_to_duration(9_000 + 9),
+            Duration::from_micros(100)
+        );
+
+        let formatted = format_timestamp_c(&info, info.milliseconds_to_timestamp(2_500));
+        assert_eq!(
+            unsafe { CStr::from_ptr(formatted) }.to_str(),
+            Ok("00:02.500")
+        );
+        free_string(formatted);
+    }
+
+    #[test]
+    fn test_builder() {
+        let info = video_info();
+        let ctx = ArgParseResultContext::builder()
+            .input("video.mp4")
+            .output("frames")
+            .from(Time::Time(Duration::from_secs(10)))
+            .to(Time::Frame(500))
+            .thread_count(ThreadCount::Custom(4))
+            .format("{:05}.png")
+            .build();
+        let text = |s| unsafe { CStr::from_ptr(s) }.to_str().unwrap();
+        assert_eq!(text(get_input(&ctx)), "video.mp4");
+        assert_eq!(text(get_output(&ctx)), "frames");
+        assert_eq!(text(get_format(&ctx)), "{:05}.png");
+        assert_eq!(get_thread_count(&ctx), 4);
+        assert_eq!(get_resolved_thread_count(&ctx), 4);
+        assert_eq!(get_from_timestamp(&ctx, &info), 10_000);
+        assert_eq!(get_to_timestamp(&ctx, &info), 20_000);
+
+        let ctx = ArgParseResultContext::builder().build();
+        assert_eq!(text(get_output(&ctx)), ".");
+        assert_eq!(get_thread_count(&ctx), 0);
+        assert!(get_resolved_thread_count(&ctx) >= 1);
+        assert_eq!(get_from_timestamp(&ctx, &info), 0);
+        assert_eq!(get_to_timestamp(&ctx, &info), 60_000);
+    }
+
+    #[test]
+    fn test_extra_args() {
+        let text = |s| unsafe { CStr::from_ptr(s) }.to_str().unwrap();
+        let ctx = ArgParseResultContext::builder()
+            .extra_arg("-vf")
+            .extra_arg("scale=1280:720")
+            .build();
+        assert_eq!(get_extra_args_len(&ctx), 2);
+        assert_eq!(text(get_extra_arg(&ctx, 0)), "-vf");
+        assert_eq!(text(get_extra_arg(&ctx, 1)), "scale=1280:720");
+        assert!(get_extra_arg(&ctx, 2).is_null());
+
+        let ctx = ArgParseResultContext::builder().build();
+        assert_eq!(get_extra_args_len(&ctx), 0);
+        assert!(get_extra_arg(&ctx, 0).is_null());
+
+        let cli = Cli::try_parse_from([
+            "pick-frame",
+            "-i",
+            "a.mp4",
+            "--extra-args",
+            "-vf",
+            "out",
+            "--extra-args=scale=1280:720",
+            "--dry-run",
+        ])
+        .unwrap();
+        assert_eq!(cli.extra_args, ["-vf", "scale=1280:720"]);
+        assert_eq!(cli.output, "out");
+        assert!(cli.dry_run);
+    }
+
+    #[test]
+    fn test_context_eq() {
+        let build = || {
+            ArgParseResultContext::builder()
+                .input("a.mp4")
+                .output("frames")
+                .from(Time::Frame(100))
+                .step(Time::Time(Duration::from_secs(2)))
+                .extra_arg("-an")
+                .build()
+        };
+        let ctx = build();
+        assert!(ctx == build());
+        let copy = ctx.clone();
+        assert_ne!(copy.input, ctx.input);
+        assert!(copy == ctx);
+        drop(ctx);
+        let input = unsafe { CStr::from_ptr(get_input(&copy)) };
+        assert_eq!(input.to_str(), Ok("a.mp4"));
+
+        let other = ArgParseResultContext::builder()
+            .input("b.mp4")
+            .output("frames")
+            .from(Time::Frame(100))
+            .step(Time::Time(Duration::from_secs(2)))
+            .extra_arg("-an")
+            .build();
+        assert!(copy != other);
+
+        #[cfg(feature = "dsl")]
+        {
+            let parse = |expr: &str| TimeType::DSL(expr.parse().unwrap());
+            assert_eq!(parse("end - 5s + 2s"), parse("end - 3s"));
+            assert_ne!(parse("end - 5s"), parse("end - 3s"));
+            assert!(context("10s", "from + 5s") == context("10s", "from + 5s"));
+            assert!(context("10s", "from + 5s") != context("10s", "from + 6s"));
+        }
+    }
+
+    #[test]
+    fn test_frame_shortcuts() {
+        let info = video_info();
+        let cli = Cli::try_parse_from([
+            "pick-frame",
+            "-i",
+            "a.mp4",
+            "--from-frame",
+            "100",
+            "--to-frame",
+            "200",
+        ])
+        .unwrap();
+        assert_eq!((cli.from_frame, cli.to_frame), (Some(100), Some(200)));
+        let mut ctx = ArgParseResultContext::builder().build();
+        ctx.start = TimeType::frame_or_else(cli.from_frame, || unreachable!());
+        ctx.end = TimeType::frame_or_else(cli.to_frame, || unreachable!());
+        assert_eq!(get_from_timestamp(&ctx, &info), 4_000);
+        assert_eq!(get_to_timestamp(&ctx, &info), 8_000);
+        #[cfg(feature = "dsl")]
+        assert_eq!(
+            get_from_timestamp(&ctx, &info),
+            get_from_timestamp(&context("100f", "end"), &info)
+        );
+
+        for conflict in [
+            ["--from-frame", "1", "--from", "2"],
+            ["--to-frame", "1", "-t", "2"],
+        ] {
+            let err =
+                Cli::try_parse_from(["pick-frame", "-i", "a.mp4"].into_iter().chain(conflict))
+                    .unwrap_err();
+            assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn test_time_suffix() {
+        let secs = |s: &str| match s.parse::<Time>() {
+            Ok(Time::Time(t)) => t.as_secs_f64(),
+            res => panic!("unexpected result for {s:?}: {res:?}"),
+        };
+        assert_eq!(secs("90m"), 5400f64);
+        assert_eq!(secs("90min"), 5400f64);
+        assert_eq!(secs("2h"), 7200f64);
+        assert_eq!(secs("1.5hr"), 5400f64);
+        assert_eq!(secs("30s"), 30f64);
+        assert_eq!(secs("1:30"), 90f64);
+        assert_eq!(secs("500ms"), 0.5);
+        assert!("1e300s".parse::<Time>().is_err());
+        assert!("99999999999999999:00:00".parse::<Time>().is_err());
+        assert!("1:18446744073709551615:00".parse::<Time>().is_err());
+        assert!("xm".parse::<Time>().is_err());
+        assert!(matches!("-1h".parse::<Time>(), Ok(Time::FromEnd(..))));
+        assert!(matches!("end".parse::<Time>(), Ok(Time::End)));
+        assert!(matches!("12".parse::<Time>(), Ok(Time::Frame(12))));
+    }
+
+    #[test]
+    fn test_time_fraction() {
+        for (input, nanos) in [
+            ("1:02.1", 100_000_000),
+            ("1:02.123", 123_000_000),
+            ("1:02,5", 500_000_000),
+            ("00:01:02,500", 500_000_000),
+        ] {
+            let Ok(Time::Time(t)) = input.parse::<Time>() else {
+                panic!("expected a time for {input:?}");
+            };
+            assert_eq!(t, Duration::new(62, nanos));
+        }
+        assert!("1:02.1234".parse::<Time>().is_err());
+
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            duration: 90_000 * 60,
+            ..video_info()
+        };
+        let ctx = ArgParseResultContext::builder()
+            .from("1.0005s".parse().unwrap())
+            .build();
+        assert_eq!(get_from_timestamp(&ctx, &info), 90_045);
+    }
+
+    #[test]
+    fn test_time_from_end() {
+        let info = video_info();
+        let ctx = ArgParseResultContext::builder()
+            .from("-5s".parse().unwrap())
+            .to("-0:01.5".parse().unwrap())
+            .build();
+        assert_eq!(get_from_timestamp(&ctx, &info), 55_000);
+        assert_eq!(get_to_timestamp(&ctx, &info), 58_500);
+
+        let info = VideoInfo {
+            start_time: 500,
+            duration: 60_500,
+            ..info
+        };
+        assert_eq!(get_from_timestamp(&ctx, &info), 55_500);
+
+        assert!(matches!("-2m".parse::<Time>(), Ok(Time::FromEnd(..))));
+        assert!("-100".parse::<Time>().is_err());
+        assert!("-end".parse::<Time>().is_err());
+        #[cfg(not(feature = "dsl"))]
+        {
+            let cli = Cli::try_parse_from(["pick-frame", "-i", "a.mp4", "--from", "-5s"]).unwrap();
+            assert!(matches!(cli.from, Time::FromEnd(..)));
+        }
+    }
+
+    #[test]
+    fn test_for_each_frame() {
+        extern "C" fn collect(frame: u64, ts: i64, user: *mut c_void) {
+            let frames = unsafe { &mut *(user as *mut Vec<(u64, i64)>) };
+            frames.push((frame, ts));
+        }
+        let run = |ctx: &ArgParseResultContext, info: &VideoInfo, step| {
+            let mut frames = Vec::<(u64, i64)>::new();
+            for_each_frame(
+                ctx,
+                info,
+                step,
+                collect,
+                &mut frames as *mut _ as *mut c_void,
+            );
+            frames
+        };
+        let info = video_info();
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::Frame(10))
+            .to(Time::Frame(20))
+            .build();
+        assert_eq!(run(&ctx, &info, 1).len(), 11);
+        assert_eq!(
+            run(&ctx, &info, 3),
+            [(10, 400), (13, 520), (16, 640), (19, 760)]
+        );
+        assert_eq!(run(&ctx, &info, 0).len(), 11);
+
+        // 时间落在两帧之间时只包含范围内的帧
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::Time(Duration::from_millis(410)))
+            .to(Time::Time(Duration::from_millis(790)))
+            .build();
+        let frames = run(&ctx, &info, 1);
+        assert_eq!(frames.first(), Some(&(11, 440)));
+        assert_eq!(frames.last(), Some(&(19, 760)));
+
+        let info = VideoInfo {
+            start_time: 1000,
+            ..info
+        };
+        let ctx = ArgParseResultContext::builder().build();
+        assert_eq!(run(&ctx, &info, 1).len(), 1476);
+    }
+
+    #[test]
+    fn test_frame_to_timestamp_exact() {
+        let info = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            time_base_den: 90_000,
+            duration: 0,
+            ..video_info()
+        };
+        // 100000 * 1001 * 90000 / 30000
+        assert_eq!(
+            info.frame_to_timestamp_exact(100_000, 30_000, 1001),
+            300_300_000
+        );
+        assert_eq!(frame_to_timestamp_exact(&info, 1, 30_000, 1001), 3003);
+        assert_eq!(info.frame_to_timestamp(100_000), 300_300_000);
+
+        let info = VideoInfo {
+            time_base_den: 1000,
+            start_time: 40,
+            ..info
+        };
+        assert_eq!(
+            info.frame_to_timestamp_exact(100_000, 30_000, 1001),
+            (100_000u128 * 1001 * 1000).div_ceil(30_000) as i64 + 40
+        );
+    }
+
+    #[test]
+    fn test_frame_timestamps() {
+        let info = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            time_base_den: 90_000,
+            duration: 0,
+            ..video_info()
+        };
+        let mut state = FrameTimestamps::new(&info, 30_000, 1001, 0);
+        for n in 0..100_000i128 {
+            let exact = (n * 1001 * 90_000 + 29_999) / 30_000;
+            assert_eq!(next_frame_timestamp(&mut state) as i128, exact);
+        }
+        assert_eq!(state.next(), Some(300_300_000));
+
+        // 时间基无法整除帧间隔时同样没有漂移
+        let info = VideoInfo {
+            time_base_den: 1000,
+            start_time: 40,
+            ..info
+        };
+        let mut state = FrameTimestamps::new(&info, 30_000, 1001, 99_999);
+        for n in 99_999u128..100_001 {
+            let exact = (n * 1001 * 1000 * 2 + 30_000) / 60_000;
+            assert_eq!(state.next(), Some(exact as i64 + 40));
+        }
+        let info = VideoInfo {
+            rounding: Rounding::Ceil,
+            ..info
+        };
+        let mut state = FrameTimestamps::new(&info, 30_000, 1001, 99_999);
+        for n in 99_999u128..100_001 {
+            let exact = (n * 1001 * 1000).div_ceil(30_000) as i64 + 40;
+            assert_eq!(state.next(), Some(exact));
+        }
+    }
+
+    #[test]
+    fn test_rounding() {
+        // 1/1000时间基的MKV中，29.97fps的第n帧时间戳为 n * 1001 / 30 四舍五入
+        let mut info = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            time_base_den: 1000,
+            ..video_info()
+        };
+        let muxed = |n: i64| (n * 1001 * 2 + 30) / 60;
+        for n in 0..10_000u64 {
+            assert_eq!(info.frame_to_timestamp(n), muxed(n as i64), "frame {n}");
+            assert_eq!(info.timestamp_to_frame(muxed(n as i64)), n, "frame {n}");
+        }
+        // 向上取整时第1帧为34，跳转后会取到时间戳为67的第2帧
+        set_video_info_rounding(&mut info, Rounding::Ceil);
+        assert_eq!(info.frame_to_timestamp(1), 34);
+        assert_eq!(muxed(1), 33);
+        set_video_info_rounding(&mut info, Rounding::Floor);
+        assert_eq!(info.frame_to_timestamp(2), 66);
+
+        let mut info = video_info();
+        let time = Duration::from_micros(1_500);
+        assert_eq!(info.duration_to_timestamp(time), 2);
+        info.rounding = Rounding::Floor;
+        assert_eq!(info.duration_to_timestamp(time), 1);
+        info.rounding = Rounding::Ceil;
+        assert_eq!(info.duration_to_timestamp(Duration::from_micros(1_001)), 2);
+
+        assert_eq!(Rounding::Nearest.div(-3, 2), -1);
+        assert_eq!(Rounding::Floor.div(-3, 2), -2);
+        assert_eq!(Rounding::Ceil.div(-3, 2), -1);
+    }
+
+    #[test]
+    fn test_thread_count() {
+        let ThreadCount::Custom(count) = ThreadCount::from_available_parallelism() else {
+            panic!("expected a custom thread count");
+        };
+        assert_ne!(count, 0);
+        assert_eq!(ThreadCount::Auto.count(), None);
+        assert_eq!(ThreadCount::Custom(3).count(), Some(3));
+        assert_eq!(resolve_thread_count(ThreadCount::Custom(3)), 3);
+        assert_eq!(resolve_thread_count(ThreadCount::Auto), count);
+        assert_eq!(ThreadCount::max_count(), count.saturating_mul(8));
+    }
+
+    #[test]
+    fn test_thread_count_clamp() {
+        assert_eq!(
+            ThreadCount::Custom(60000).clamp_to(64),
+            (ThreadCount::Custom(64), true)
+        );
+        assert_eq!(
+            ThreadCount::Custom(4).clamp_to(64),
+            (ThreadCount::Custom(4), false)
+        );
+        assert_eq!(
+            ThreadCount::Custom(64).clamp_to(64),
+            (ThreadCount::Custom(64), false)
+        );
+        assert_eq!(ThreadCount::Auto.clamp_to(64), (ThreadCount::Auto, false));
+        let (clamped, _) = ThreadCount::Custom(u16::MAX).clamp_to(ThreadCount::max_count());
+        assert!(clamped.count().unwrap() <= ThreadCount::max_count());
+    }
+
+    #[test]
+    fn test_generate_completions() {
+        let cli = Cli::try_parse_from(["pick-frame", "--generate-completions", "bash"]).unwrap();
+        assert_eq!(cli.generate_completions, Some(clap_complete::Shell::Bash));
+        assert!(Cli::try_parse_from(["pick-frame"]).is_err());
+        let mut out = vec![];
+        generate_completions(clap_complete::Shell::Bash, &mut out);
+        let script = String::from_utf8(out).unwrap();
+        assert!(!script.is_empty());
+        assert!(script.contains("pick-frame"));
+        assert!(script.contains("--input"));
+    }
+
+    fn video_info() -> VideoInfo {
+        VideoInfo {
+            fps: 25f64,
+            fps_rational: None,
+            rounding: Rounding::default(),
+            time_base_den: 1000,
+            time_base_num: 1,
+            start_time: 0,
+            duration: 60_000,
+            stream_count: 1,
+            nb_frames: None,
+        }
+    }
+
+    #[cfg(feature = "dsl")]
+    fn context(from: &str, to: &str) -> ArgParseResultContext {
+        ArgParseResultContext {
+            input: std::ptr::null(),
+            output: std::ptr::null(),
+            thread_count: 0,
+            resolved_thread_count: 1,
+            format: std::ptr::null(),
+            stream_index: 0,
+            dry_run: false,
+            sample_count: 0,
+            start: TimeType::DSL(from.parse().unwrap()),
+            end: TimeType::DSL(to.parse().unwrap()),
+            step: Time::Frame(1).into(),
+            interval: None,
+            extra_args: Vec::new(),
+            keyframes: Vec::new(),
+            vars: HashMap::new(),
+            points: Vec::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_start_time_counted_once() {
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: 900_000_000,
+            duration: 900_000_000 + 90_000 * 60,
+            ..video_info()
+        };
+        let at = |expr: &str| get_from_timestamp(&context(expr, "end"), &info);
+        let start = info.start_time;
+        assert_eq!(at("10s"), start + 900_000);
+        assert_eq!(at("5s + 5s"), at("10s"));
+        assert_eq!(at("2s + 3s + 5s"), at("10s"));
+        assert_eq!(at("15s - 5s"), at("10s"));
+        assert_eq!(at("125f + 5s"), at("10s"));
+        assert_eq!(at("start + 10s"), at("10s"));
+        assert_eq!(at("end - 50s"), at("10s"));
+        assert_eq!(at("end - 25s - 25s"), at("10s"));
+        assert_eq!(at("max(5s, 1s) + 5s"), at("10s"));
+        assert_eq!(at("clamp(0s - 1s + 1s) + 10s"), at("10s"));
+
+        let ctx = context("10s", "from + 5s + 5s");
+        assert_eq!(get_to_timestamp(&ctx, &info), start + 90_000 * 20);
+        let ctx = context("end - 10s", "end");
+        assert_eq!(get_from_timestamp(&ctx, &info), start + 90_000 * 50);
+
+        let mut ctx = context("0s", "end");
+        ctx.step = TimeType::DSL("1s + 1s".parse().unwrap());
+        assert_eq!(get_step_frames(&ctx, &info), 50);
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_start_keyword() {
+        let info = VideoInfo {
+            start_time: 2_000,
+            ..video_info()
+        };
+        let ctx = context("10s", "max(start, 0f)");
+        assert_eq!(get_from_timestamp(&ctx, &info), 12_000);
+        assert_eq!(get_to_timestamp(&ctx, &info), 2_000);
+        let ctx = context("10s", "max(from, 0f)");
+        assert_eq!(get_to_timestamp(&ctx, &info), 12_000);
+        let ctx = context("begin", "end");
+        assert_eq!(get_from_timestamp(&ctx, &info), 2_000);
+
+        let info = VideoInfo {
+            start_time: AV_NOPTS_VALUE,
+            ..video_info()
+        };
+        let ctx = context("start + 1s", "end");
+        assert_eq!(get_from_timestamp(&ctx, &info), 1_000);
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_min_max() {
+        let info = video_info();
+        let ctx = context("50s", "min(end, from + 30s)");
+        assert_eq!(get_to_timestamp(&ctx, &info), 60_000);
+        let ctx = context("10s", "min(end, from + 30s)");
+        assert_eq!(get_to_timestamp(&ctx, &info), 40_000);
+        let ctx = context("max(0s, end - 70s)", "end");
+        assert_eq!(get_from_timestamp(&ctx, &info), 0);
+        let ctx = context("max(1s, 2s, 3s) + 1s", "end");
+        assert_eq!(get_from_timestamp(&ctx, &info), 4_000);
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_duration_alias() {
+        let info = video_info();
+        let alias = context("duration - 5s", "dur - 1s");
+        let plain = context("end - 5s", "end - 1s");
+        assert_eq!(
+            get_from_timestamp(&alias, &info),
+            get_from_timestamp(&plain, &info)
+        );
+        assert_eq!(
+            get_to_timestamp(&alias, &info),
+            get_to_timestamp(&plain, &info)
+        );
+        assert_eq!(get_from_timestamp(&alias, &info), 55_000);
+    }
+
+    #[test]
+    #[cfg(all(feature = "dsl", feature = "serde"))]
+    fn test_checked_expr_serde_eval() {
+        let info = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            fps_rational: Some((30000, 1001)),
+            time_base_den: 90_000,
+            start_time: 900,
+            duration: 5_400_000,
+            ..video_info()
+        };
+        for (from, to) in [
+            ("1.0000005s + 10f", "end - 2.25s"),
+            ("00:00:10;02", "min(end, from + 30s)"),
+            ("clamp(to - 100s)", "0:40"),
+        ] {
+            let ctx = context(from, to);
+            let mut copy = context("0f", "end");
+            for (src, dst) in [(&ctx.start, &mut copy.start), (&ctx.end, &mut copy.end)] {
+                let json = serde_json::to_string(ctx_expr(src)).unwrap();
+                *dst = TimeType::DSL(serde_json::from_str(&json).unwrap());
+            }
+            assert_eq!(
+                evaluate_pair(&copy, &info),
+                evaluate_pair(&ctx, &info),
+                "{from} .. {to}"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_dry_run_report() {
+        let info = video_info();
+        let ctx = context("1:2.5", "end - 1s");
+        assert_eq!(
+            dry_run_report(&ctx, &info).unwrap(),
+            "from: 62500 (00:01:02.500, frame 1562)\nto: 59000 (00:00:59.000, frame 1475)\noutputs: 0"
+        );
+
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::FromEnd(Duration::from_secs(10)))
+            .to(Time::End)
+            .step(Time::Frame(5))
+            .build();
+        assert_eq!(
+            dry_run_report(&ctx, &info).unwrap(),
+            "from: 50000 (00:00:50.000, frame 1250)
+to: 60000 (00:01:00.000, frame 1500)
+outputs: 51
+  frame-1250.jpg
+  frame-1255.jpg
+  frame-1260.jpg
+  frame-1265.jpg
+  frame-1270.jpg
+  ... and 46 more"
+        );
+
+        // 文件名中的毫秒时间戳与调用方一样是绝对时间，包含开始时间
+        let info = VideoInfo {
+            start_time: 500,
+            ..info
+        };
+        let format = "{frame_index}-{timestamp_ms}.png";
+        assert!(format::validate_format_string(format).is_ok());
+        let ctx = ArgParseResultContext::builder()
+            .count(3)
+            .format(format)
+            .build();
+        let report = dry_run_report(&ctx, &info).unwrap();
+        assert!(
+            report.ends_with("\noutputs: 3\n  0-500.png\n  743-30250.png\n  1487-60000.png"),
+            "{report}"
+        );
+
+        // 无法求值时记录错误而不是退出
+        let live = VideoInfo {
+            duration: AV_NOPTS_VALUE,
+            ..info
+        };
+        let ctx = context("end - 1s", "end");
+        assert!(!get_dry_run(&ctx));
+        let err = dry_run_report(&ctx, &live).unwrap_err();
+        assert_eq!(err.message, DURATION_UNKNOWN);
+        assert_eq!(print_dry_run(&ctx, &live), 1);
+        assert_eq!(
+            LAST_EVAL_ERROR.with_borrow(|err| err.clone().map(|err| err.name)),
+            Some("from".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_clamp() {
+        let info = VideoInfo {
+            start_time: 500,
+            ..video_info()
+        };
+        let ctx = context("10s", "clamp(end + 10s)");
+        assert_eq!(get_to_timestamp(&ctx, &info), info.end_to_timestamp());
+        let ctx = context("5s", "clamp(from - 100s)");
+        assert_eq!(get_to_timestamp(&ctx, &info), 500);
+        let ctx = context("clamp(20s)", "end");
+        assert_eq!(get_from_timestamp(&ctx, &info), 20_500);
+    }
+
+    #[test]
+    fn test_step_frames() {
+        let info = video_info();
+        let step = |time| {
+            let ctx = ArgParseResultContext::builder().step(time).build();
+            get_step_frames(&ctx, &info)
+        };
+        assert_eq!(step(Time::Frame(1)), 1);
+        assert_eq!(step(Time::Frame(30)), 30);
+        assert_eq!(step(Time::Time(Duration::from_secs(1))), 25);
+        assert_eq!(step(Time::Time(Duration::from_millis(10))), 1);
+
+        let info = VideoInfo {
+            fps: 30000f64 / 1001f64,
+            time_base_den: 90_000,
+            start_time: 3003,
+            ..video_info()
+        };
+        let ctx = ArgParseResultContext::builder()
+            .step(Time::Time(Duration::from_secs(1)))
+            .build();
+        assert_eq!(get_step_frames(&ctx, &info), 30);
+        let ctx = ArgParseResultContext::builder()
+            .step(Time::Frame(7))
+            .build();
+        assert_eq!(get_step_frames(&ctx, &info), 7);
+    }
+
+    #[test]
+    fn test_step_ticks() {
+        let two_seconds = ArgParseResultContext::builder()
+            .step(Time::Time(Duration::from_secs(2)))
+            .build();
+        for (time_base_num, time_base_den, start_time, expected) in [
+            (1, 1000, 0, 2_000),
+            (1, 90_000, 0, 180_000),
+            (1, 90_000, 900_000, 180_000),
+            (1, 44_100, 441, 88_200),
+            (1001, 30_000, 7, 60),
+        ] {
+            let info = VideoInfo {
+                time_base_num,
+                time_base_den,
+                start_time,
+                ..video_info()
+            };
+            assert_eq!(
+                get_step_ticks(&two_seconds, &info),
+                expected,
+                "{time_base_num}/{time_base_den}"
+            );
+        }
+
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: 900_000,
+            ..video_info()
+        };
+        let ctx = ArgParseResultContext::builder()
+            .step(Time::Frame(30))
+            .build();
+        assert_eq!(get_step_ticks(&ctx, &info), 108_000);
+        assert_eq!(get_step_frames(&ctx, &info), 30);
+    }
+
+    #[test]
+    fn test_parser_time_type_eq() {
+        let expected = PaserTimeType {
+            kind: TimeTypeKind::Frame,
+            value: 30,
+        };
+        let built = PaserTimeType::from(Time::Frame(30));
+        assert_eq!(built, expected);
+        let copied = built;
+        assert_eq!(copied, built);
+        assert_ne!(built.kind, TimeTypeKind::Millisecond);
+        assert_eq!(
+            PaserTimeType::from(Time::Time(Duration::from_millis(1500))),
+            PaserTimeType {
+                kind: TimeTypeKind::Nanosecond,
+                value: 1_500_000_000,
+            }
+        );
+        assert_eq!(PaserTimeType::from(Time::End).kind, TimeTypeKind::End);
+    }
+
+    #[test]
+    fn test_from_duration_and_fps() {
+        let info = VideoInfo::from_duration_and_fps(60.0, 25.0);
+        assert_eq!(info.validate(), Ok(()));
+        assert_eq!((info.time_base_num, info.time_base_den), (1, 90_000));
+        assert_eq!(info.start_time, AV_NOPTS_VALUE);
+        assert_eq!(info.duration, 5_400_000);
+        assert_eq!(info.frame_to_timestamp(0), 0);
+        assert_eq!(info.frame_to_timestamp(1), 3_600);
+        assert_eq!(info.frame_to_timestamp(1500), 5_400_000);
+        assert_eq!(info.end_to_timestamp(), 5_400_000);
+        assert_eq!(total_frames(&info), 1500);
+
+        let info = VideoInfo::from_duration_and_fps(1.5, 30000.0 / 1001.0);
+        assert_eq!(info.duration, 135_000);
+        assert_eq!(info.frame_to_timestamp(1), 3_003);
+        assert_eq!(info.frame_to_timestamp(30), 90_090);
+    }
+
+    #[test]
+    fn test_interval() {
+        let two_seconds = ArgParseResultContext::builder()
+            .interval(Duration::from_secs(2))
+            .build();
+        assert_eq!(get_interval_timestamp(&two_seconds, &video_info()), 2_000);
+        // `--interval` 不影响 `--step`
+        assert_eq!(get_step_frames(&two_seconds, &video_info()), 1);
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: 900_000,
+            ..video_info()
+        };
+        assert_eq!(get_interval_timestamp(&two_seconds, &info), 180_000);
+
+        // 短于一帧时按一帧计算
+        let ctx = ArgParseResultContext::builder()
+            .interval(Duration::from_millis(10))
+            .build();
+        assert_eq!(interval_ticks(&ctx, &video_info()), Some((40, true)));
+        assert_eq!(get_interval_timestamp(&ctx, &video_info()), 40);
+        assert_eq!(planned_frames(&ctx, &video_info(), 3).unwrap().0, 1501);
+
+        // 时间基比一帧还粗时一帧的长度为0个时间基单位，按一个单位计算
+        let ptr = create_video_info(60.0, 25, 1, 0, 250);
+        let coarse = unsafe { &*ptr };
+        let ctx = ArgParseResultContext::builder()
+            .interval(Duration::from_millis(1))
+            .build();
+        assert_eq!(interval_ticks(&ctx, coarse), Some((1, true)));
+        assert_eq!(planned_frames(&ctx, coarse, 3).unwrap().0, 251);
+        assert!(
+            dry_run_report(&ctx, coarse)
+                .unwrap()
+                .contains("\noutputs: 251\n")
+        );
+        free_video_info(ptr);
+
+        // 按累计的时间戳采样，29.97fps 下第50秒是第1499帧而不是第1500帧
+        let ntsc = VideoInfo::from_duration_and_fps(60.0, 30000f64 / 1001f64);
+        let one_second = ArgParseResultContext::builder()
+            .interval(Duration::from_secs(1))
+            .build();
+        let (count, frames) = planned_frames(&one_second, &ntsc, 60).unwrap();
+        assert_eq!(count, 60);
+        assert_eq!(frames[50], (1499, ntsc.frame_to_timestamp(1499)));
+
+        let ctx = ArgParseResultContext::builder().build();
+        assert_eq!(interval_ticks(&ctx, &video_info()), None);
+        assert_eq!(get_interval_timestamp(&ctx, &video_info()), 0);
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_interval("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_interval("500ms"), Ok(Duration::from_millis(500)));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("30").is_err());
+
+        let cli = Cli::try_parse_from(["pick-frame", "-i", "a.mp4", "--interval", "2s"]).unwrap();
+        assert_eq!(cli.interval, Some(Duration::from_secs(2)));
+        for conflict in [["--step", "5"], ["--count", "3"], ["--at", "1s"]] {
+            assert!(
+                Cli::try_parse_from(
+                    ["pick-frame", "-i", "a.mp4", "--interval", "2s"]
+                        .into_iter()
+                        .chain(conflict)
+                )
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let cli = Cli::try_parse_from(["pick-frame", "-i", "a.mp4"]).unwrap();
+        assert_eq!(cli.stream, 0);
+        let cli = Cli::try_parse_from(["pick-frame", "-i", "a.mp4", "--stream", "2"]).unwrap();
+        assert_eq!(cli.stream, 2);
+        for bad in ["-1", "x", ""] {
+            assert!(Cli::try_parse_from(["pick-frame", "-i", "a.mp4", "--stream", bad]).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "dsl"))]
+    fn test_parse_step() {
+        assert!(matches!(parse_step("30"), Ok(Time::Frame(30))));
+        assert!(matches!(parse_step("1.5s"), Ok(Time::Time(t)) if t.as_millis() == 1500));
+        assert!(parse_step("0").is_err());
+        assert!(parse_step("0s").is_err());
+        assert!(parse_step("end").is_err());
+        assert!(parse_step("-1s").is_err());
+    }
+
+    #[test]
+    fn test_sample_timestamps() {
+        let info = video_info();
+        let ctx = ArgParseResultContext::builder().count(5).build();
+        assert_eq!(get_sample_count(&ctx), 5);
+        let samples = (0..5)
+            .map(|index| get_sample_timestamp(&ctx, index, &info))
+            .collect::<Vec<_>>();
+        assert_eq!(samples, [0, 15_000, 30_000, 45_000, 60_000]);
+        assert_eq!(get_sample_timestamp(&ctx, 9, &info), 60_000);
+
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::Time(Duration::from_secs(10)))
+            .to(Time::Time(Duration::from_secs(20)))
+            .count(1)
+            .build();
+        assert_eq!(get_sample_timestamp(&ctx, 0, &info), 10_000);
+        let ctx = ArgParseResultContext::builder().count(3).build();
+        let info = VideoInfo {
+            duration: 1001,
+            ..info
+        };
+        assert_eq!(get_sample_timestamp(&ctx, 1, &info), 500);
+        assert_eq!(
+            get_sample_count(&ArgParseResultContext::builder().build()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_keyframe_aligned_timestamp() {
+        let info = video_info();
+        for interval in [1u32, 7, 250, 2002] {
+            for pts in (-5_000..60_000).step_by(37) {
+                let aligned = keyframe_align_timestamp(&info, pts, interval);
+                assert!(aligned <= pts, "{pts} {interval}");
+                assert!(pts - aligned < interval as i64, "{pts} {interval}");
+                assert_eq!(aligned.rem_euclid(interval as i64), 0, "{pts} {interval}");
+            }
+        }
+        assert_eq!(info.keyframe_aligned_timestamp(1_234, 0), 1_234);
+        assert_eq!(info.keyframe_aligned_timestamp(1_234, 500), 1_000);
+        assert_eq!(info.keyframe_aligned_timestamp(-1, 500), -500);
+        // 对齐以开始时间为起点
+        let info = VideoInfo {
+            start_time: 3003,
+            ..video_info()
+        };
+        assert_eq!(
+            info.keyframe_aligned_timestamp(3003 + 1_234, 500),
+            3003 + 1_000
+        );
+        assert_eq!(info.keyframe_aligned_timestamp(3003, 500), 3003);
+        assert_eq!(info.keyframe_aligned_timestamp(i64::MIN, 500), i64::MIN);
+        assert_eq!(
+            info.keyframe_aligned_timestamp(i64::MAX, 500),
+            i64::MAX - (i64::MAX - 3003) % 500
+        );
+    }
+
+    #[test]
+    fn test_snap_to_keyframe() {
+        let keyframes = [4_000, 0, 2_000, 8_000];
+        let snap = |ts, mode| snap_to_keyframe(ts, keyframes.as_ptr(), keyframes.len(), mode);
+        for (ts, before, after, nearest) in [
+            (-500, 0, 0, 0),
+            (0, 0, 0, 0),
+            (1_000, 0, 2_000, 0),
+            (1_001, 0, 2_000, 2_000),
+            (2_000, 2_000, 2_000, 2_000),
+            (5_000, 4_000, 8_000, 4_000),
+            (6_500, 4_000, 8_000, 8_000),
+            (9_000, 8_000, 8_000, 8_000),
+        ] {
+            assert_eq!(snap(ts, SnapMode::Before), before, "before {ts}");
+            assert_eq!(snap(ts, SnapMode::After), after, "after {ts}");
+            assert_eq!(snap(ts, SnapMode::Nearest), nearest, "nearest {ts}");
+        }
+        assert_eq!(
+            snap_to_keyframe(1_234, std::ptr::null(), 4, SnapMode::Nearest),
+            1_234
+        );
+        assert_eq!(
+            snap_to_keyframe(1_234, keyframes.as_ptr(), 0, SnapMode::After),
+            1_234
+        );
+        assert_eq!(
+            SnapMode::Nearest.snap(0, &[-i64::MAX, i64::MAX]),
+            Some(-i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let mut ctx = ArgParseResultContext::builder().build();
+        assert_eq!(ctx.keyframe_before(1000), None);
+        let keyframes = [20_000i64, 0, 10_000, 10_000];
+        set_keyframes(&mut ctx, keyframes.as_ptr(), keyframes.len());
+        assert_eq!(ctx.keyframes, [0, 10_000, 20_000]);
+        assert_eq!(ctx.keyframe_before(15_000), Some(10_000));
+        assert_eq!(ctx.keyframe_before(10_000), Some(10_000));
+        assert_eq!(ctx.keyframe_after(15_000), Some(20_000));
+        assert_eq!(ctx.keyframe_after(10_000), Some(10_000));
+        // 超出关键帧表的范围时没有对应的关键帧
+        assert_eq!(ctx.keyframe_before(-500), None);
+        assert_eq!(ctx.keyframe_after(25_000), None);
+        set_keyframes(&mut ctx, std::ptr::null(), 0);
+        assert!(ctx.keyframes.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_keyframe_functions() {
+        let info = VideoInfo {
+            start_time: 500,
+            ..video_info()
+        };
+        let mut ctx = context("keyframe_before(1:30)", "keyframe_after(from + 1s)");
+        let err = eval_time(&ctx, &info, KnownTimes::default(), "from", &ctx.start).unwrap_err();
+        assert!(err.message.contains("set_keyframes"), "{}", err.message);
+        assert_eq!(
+            err.span,
+            lexer::SourceSpan {
+                offset: 0,
+                length: 21
+            }
+        );
+
+        let keyframes = [2_500, 40_500, 100_500];
+        ctx.set_keyframes(&keyframes);
+        assert_eq!(
+            eval_time(&ctx, &info, KnownTimes::default(), "from", &ctx.start),
+            Ok(40_500)
+        );
+        assert_eq!(get_from_timestamp(&ctx, &info), 40_500);
+        assert_eq!(get_to_timestamp(&ctx, &info), 100_500);
+
+        // 早于第一个关键帧或晚于最后一个关键帧时没有可用的关键帧
+        let mut ctx = context("keyframe_before(1s)", "keyframe_after(end + 50s)");
+        ctx.set_keyframes(&keyframes);
+        let err = eval_time(&ctx, &info, KnownTimes::default(), "from", &ctx.start).unwrap_err();
+        assert_eq!(
+            err.message,
+            "`keyframe_before` found no keyframe at or before 1500"
+        );
+        assert_eq!(err.label, "no keyframe here");
+        let err = eval_time(&ctx, &info, KnownTimes::default(), "to", &ctx.end).unwrap_err();
+        assert_eq!(
+            err.message,
+            "`keyframe_after` found no keyframe at or after 110000"
+        );
+        let mut from = 0;
+        assert_eq!(get_from_timestamp_checked(&ctx, &info, &mut from), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_eval_error_span() {
+        let info = VideoInfo {
+            duration: 30_000,
+            ..video_info()
+        };
+        let ctx = context("0f", "end + 2:00:00 - 1s");
+        let to = ctx_expr(&ctx.end);
+        let err = eval_position(&ctx, &info, KnownTimes::default(), "to", to).unwrap_err();
+        assert_eq!(
+            err.span,
+            lexer::SourceSpan {
+                offset: 6,
+                length: 7
+            }
+        );
+        assert_eq!(
+            err.message,
+            "`to` is past the end of the video: 02:00:29.000 > 00:00:30.000"
+        );
+
+        let ctx = context("end - 1:00 + 10s", "end");
+        let err = eval_position(
+            &ctx,
+            &info,
+            KnownTimes::default(),
+            "from",
+            ctx_expr(&ctx.start),
+        )
+        .unwrap_err();
+        assert_eq!(err.span.offset, 6);
+        assert!(err.message.contains("before the start"), "{}", err.message);
+
+        // 中途越界但最终回到范围内时不报错
+        let ctx = context("end + 10s - 20s", "end");
+        assert_eq!(
+            eval_position(
+                &ctx,
+                &info,
+                KnownTimes::default(),
+                "from",
+                ctx_expr(&ctx.start)
+            ),
+            Ok(20_000)
+        );
+
+        let ctx = context("9223372036854775807f + 1s", "end");
+        let err = eval_position(
+            &ctx,
+            &info,
+            KnownTimes::default(),
+            "from",
+            ctx_expr(&ctx.start),
+        )
+        .unwrap_err();
+        assert_eq!(err.message, "timestamp overflow");
+    }
+
+    #[test]
+    fn test_nb_frames() {
+        let mut info = video_info();
+        assert_eq!(info.total_frames(), 1500);
+        assert_eq!(info.check_frame_index(u64::MAX), Ok(()));
+
+        set_video_info_nb_frames(&mut info, 300);
+        assert_eq!(info.nb_frames(), Some(300));
+        assert_eq!(info.total_frames(), 300);
+        assert_eq!(info.check_frame_index(299), Ok(()));
+        assert_eq!(
+            info.check_frame_index(300),
+            Err("frame 300 is out of range: the video has 300 frames".to_string())
+        );
+
+        set_video_info_nb_frames(&mut info, 0);
+        assert_eq!(info.nb_frames(), None);
+        assert_eq!(info.total_frames(), 1500);
+
+        // 只有检查范围的求值函数拒绝超出总帧数的帧序号
+        info.set_nb_frames(Some(300));
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::Frame(1000))
+            .build();
+        let mut pts = 0;
+        assert_eq!(get_from_timestamp(&ctx, &info), 40_000);
+        assert_eq!(get_from_timestamp_checked(&ctx, &info, &mut pts), 1);
+        assert_eq!(
+            LAST_EVAL_ERROR.with_borrow(|err| err.clone().map(|err| err.message)),
+            Some("frame 1000 is out of range: the video has 300 frames".to_string())
+        );
+        assert_eq!(get_from_timestamp_checked(&ctx, &video_info(), &mut pts), 0);
+        assert_eq!(pts, 40_000);
+    }
+
+    #[cfg(feature = "dsl")]
+    #[test]
+    fn test_nb_frames_eval() {
+        let eval = |info: &VideoInfo, expr: &str| {
+            let ctx = context(expr, "end");
+            eval_position(
+                &ctx,
+                info,
+                KnownTimes::default(),
+                "from",
+                ctx_expr(&ctx.start),
+            )
+        };
+        // 帧数未知时只检查是否超过结束时间
+        let unknown = video_info();
+        assert_eq!(eval(&unknown, "1000f"), Ok(40_000));
+
+        let info = VideoInfo {
+            nb_frames: Some(300),
+            ..video_info()
+        };
+        assert_eq!(eval(&info, "299f"), Ok(11_960));
+        let err = eval(&info, "1000f").unwrap_err();
+        assert_eq!(
+            err.message,
+            "frame 1000 is out of range: the video has 300 frames"
+        );
+        assert_eq!((err.span.offset, err.span.length), (0, 5));
+        assert_eq!(err.label, "no such frame");
+        // 减去的帧数是偏移而不是帧序号
+        assert_eq!(eval(&info, "end - 1000f"), Ok(20_000));
+
+        // 时长未知时只能靠总帧数拦截超出视频的帧序号，变量和函数参数中的帧数同样检查
+        let info = VideoInfo {
+            duration: AV_NOPTS_VALUE,
+            ..info
+        };
+        let err = eval(&info, "max(5000f, 1f)").unwrap_err();
+        assert_eq!(
+            err.message,
+            "frame 5000 is out of range: the video has 300 frames"
+        );
+        assert_eq!((err.span.offset, err.span.length), (4, 5));
+        assert_eq!(eval(&info, "max(299f, 1f)"), Ok(11_960));
+
+        let mut ctx = context("x", "end");
+        ctx.vars.insert("x".to_string(), "5000f".parse().unwrap());
+        let err = eval_position(
+            &ctx,
+            &info,
+            KnownTimes::default(),
+            "from",
+            ctx_expr(&ctx.start),
+        )
+        .unwrap_err();
+        assert_eq!(err.name, "x");
+        assert_eq!(err.label, "no such frame");
+        assert_eq!((err.span.offset, err.span.length), (0, 5));
+        let mut pts = 0;
+        assert_eq!(get_from_timestamp_checked(&ctx, &info, &mut pts), 1);
+        // 变量中减去的帧数同样只是偏移
+        ctx.vars
+            .insert("x".to_string(), "end - 1000f".parse().unwrap());
+        let info = VideoInfo {
+            nb_frames: Some(300),
+            ..video_info()
+        };
+        assert_eq!(
+            eval_position(
+                &ctx,
+                &info,
+                KnownTimes::default(),
+                "from",
+                ctx_expr(&ctx.start)
+            ),
+            Ok(20_000)
+        );
+    }
+
+    #[cfg(feature = "dsl")]
+    #[test]
+    fn test_eval_term_overflow() {
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            start_time: i64::MAX - 90_000,
+            duration: 90_000,
+            ..video_info()
+        };
+        let eval = |expr: &str| {
+            let ctx = context(expr, "end");
+            eval_position(
+                &ctx,
+                &info,
+                KnownTimes::default(),
+                "from",
+                ctx_expr(&ctx.start),
+            )
+        };
+        assert_eq!(eval("0s"), Ok(i64::MAX - 90_000));
+        // 单独的项在加上开始时间后就已溢出，错误指向该项
+        for expr in ["2s", "50f"] {
+            let err = eval(expr).unwrap_err();
+            assert_eq!(err.message, "timestamp overflow", "{expr}");
+            assert_eq!(
+                (err.span.offset, err.span.length),
+                (0, expr.len()),
+                "{expr}"
+            );
+        }
+        let err = eval("999999999999999s").unwrap_err();
+        assert_eq!(err.message, "timestamp overflow");
+        assert_eq!(err.span.offset, 0);
+
+        let info = VideoInfo {
+            start_time: i64::MAX - 1000,
+            ..info
+        };
+        assert_eq!(
+            info.checked_duration_to_timestamp(Duration::ZERO),
+            Some(info.start_time)
+        );
+        assert_eq!(
+            info.checked_duration_to_timestamp(Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(info.duration_to_timestamp(Duration::from_secs(1)), i64::MAX);
+        assert_eq!(info.checked_frame_to_timestamp(25), None);
+        assert_eq!(info.frame_to_timestamp(25), i64::MAX);
+    }
+
+    #[cfg(feature = "dsl")]
+    fn ctx_expr(time: &TimeType) -> &lexer::CheckedExpr {
+        match time {
+            TimeType::DSL(expr) => expr,
+            TimeType::Parser(..) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_validate_range() {
+        let info = video_info();
+        let range =
+            |from: Time, to: Time| ArgParseResultContext::builder().from(from).to(to).build();
+        let ctx = range(Time::End, Time::Frame(0));
+        assert_eq!(validate_range(&ctx, &info), 1);
+
+        let ctx = range(Time::Time(Duration::from_secs(10)), Time::Frame(250));
+        assert_eq!(ctx.validate_range(&info), Ok(()));
+        assert_eq!(validate_range(&ctx, &info), 0);
+
+        let ctx = range(Time::Frame(0), Time::End);
+        assert_eq!(validate_range(&ctx, &info), 0);
+
+        let ctx = range(Time::End, Time::Frame(0));
+        assert_eq!(
+            ctx.validate_range(&info),
+            Err(RangeError::FromAfterTo {
+                from_ts: 60_000,
+                to_ts: 0
+            })
+        );
+        // 25fps下相邻两帧为10000和10040
+        let ctx = range(
+            Time::Time(Duration::from_millis(10_001)),
+            Time::Time(Duration::from_millis(10_039)),
+        );
+        assert_eq!(ctx.validate_range(&info), Err(RangeError::EmptyRange));
+        assert_eq!(validate_range(&ctx, &info), 1);
+        assert_eq!(info.frame_range(10_001, 10_039), None);
+        assert_eq!(info.frame_range(10_000, 10_080), Some(250..=252));
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_evaluate_pair() {
+        let info = video_info();
+        let ctx = context("end - 30s", "from + 10s");
+        assert_eq!(evaluate_pair(&ctx, &info), (30_000, 40_000));
+        let ctx = context("to - 10s", "end - 5s");
+        assert_eq!(evaluate_pair(&ctx, &info), (45_000, 55_000));
+
+        let known = KnownTimes {
+            from: Some(1_000),
+            to: None,
+        };
+        let to = ctx_expr(&ctx.end);
+        assert_eq!(eval_position(&ctx, &info, known, "to", to), Ok(55_000));
+        let ctx = context("end", "from + 2s");
+        assert_eq!(
+            eval_position(&ctx, &info, known, "to", ctx_expr(&ctx.end)),
+            Ok(3_000)
+        );
+
+        let ctx = context("10s", "from + 2s");
+        let (mut from, mut to) = (0, 0);
+        get_from_to_timestamps(&ctx, &info, &mut from, &mut to);
+        assert_eq!((from, to), (10_000, 12_000));
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let config = || {
+            config::Config::from_toml(
+                "format = \"shot-%d.png\"\nthread-count = \"4\"\nto = \"end\"\nfrom = \"250\"\n",
+            )
+            .unwrap()
+        };
+        let cli_with_config = |args: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(["pick-frame", "-i", "a.mp4"].iter().chain(args))
+                .unwrap();
+            let mut cli = Cli::from_arg_matches(&matches).unwrap();
+            cli.apply_config(config(), &matches).map(|_| cli)
+        };
+
+        let cli = cli_with_config(&[]).unwrap();
+        assert_eq!(cli.format, "shot-%d.png");
+        assert_eq!(cli.thread_count, ThreadCount::Custom(4));
+        #[cfg(feature = "dsl")]
+        assert_eq!(cli.from, "250");
+        #[cfg(not(feature = "dsl"))]
+        assert!(matches!(cli.from, Time::Frame(250)));
+
+        let cli =
+            cli_with_config(&["--format", "%d.jpg", "--thread-count", "auto", "-f", "1"]).unwrap();
+        assert_eq!(cli.format, "%d.jpg");
+        assert_eq!(cli.thread_count, ThreadCount::Auto);
+        #[cfg(feature = "dsl")]
+        assert_eq!(cli.from, "1");
+        #[cfg(not(feature = "dsl"))]
+        assert!(matches!(cli.from, Time::Frame(1)));
+        // 与默认值相同但显式指定时也以命令行为准
+        let cli = cli_with_config(&["--format", "frame-%d.jpg"]).unwrap();
+        assert_eq!(cli.format, "frame-%d.jpg");
+
+        let matches = Cli::command()
+            .try_get_matches_from(["pick-frame", "-i", "a.mp4"])
+            .unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        let config = config::Config::from_toml("thread-count = \"many\"").unwrap();
+        assert!(cli.apply_config(config, &matches).is_err());
+    }
+
+    #[test]
+    fn test_fps_to_rational() {
+        assert_eq!(fps_to_rational(25f64), Some((25, 1)));
+        assert_eq!(fps_to_rational(30000f64 / 1001f64), Some((30_000, 1001)));
+        assert_eq!(fps_to_rational(24000f64 / 1001f64), Some((24_000, 1001)));
+        assert_eq!(fps_to_rational(12.5), Some((25, 2)));
+        assert_eq!(fps_to_rational(1f64 / 3f64), Some((1, 3)));
+        assert_eq!(fps_to_rational(0f64), None);
+        assert_eq!(fps_to_rational(-25f64), None);
+        assert_eq!(fps_to_rational(f64::NAN), None);
+        assert_eq!(fps_to_rational(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn test_frame_to_timestamp_rational() {
+        let frames = [
+            0u64,
+            1,
+            1001,
+            1_000_000,
+            10_000_000,
+            10_000_001,
+            123_456_789,
+            999_999_999,
+            1_000_000_000,
+        ];
+        for (fps_num, fps_den) in [(30_000i128, 1001i128), (60_000, 1001)] {
+            let info = VideoInfo {
+                fps: fps_num as f64 / fps_den as f64,
+                time_base_den: 90_000,
+                duration: 0,
+                ..video_info()
+            };
+            for n in frames {
+                let exact = (n as i128 * fps_den * 90_000 + fps_num - 1) / fps_num;
+                assert_eq!(info.frame_to_timestamp(n) as i128, exact, "frame {n}");
+                assert_eq!(info.timestamp_to_frame(exact as i64), n, "frame {n}");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_at_file() {
+        let content = "# points of interest\n0:30\n\n  # credits\nend - 10s\r\n10f + 2s # intro\n";
+        let lines = expression_lines(content);
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| (line.location_line(), *line.fragment()))
+                .collect::<Vec<_>>(),
+            [(2, "0:30"), (5, "end - 10s"), (6, "10f + 2s # intro")]
+        );
+        let mut ctx = context("0f", "end");
+        for line in lines {
+            let (_, mut expr) = lexer::parse_expr_complete(line).unwrap();
+            lexer::optimize_expr(&mut expr).unwrap();
+            ctx.points.push(lexer::check_expr(&expr).unwrap());
+        }
+        let info = video_info();
+        assert_eq!(get_at_count(&ctx), 3);
+        assert_eq!(
+            (0..4)
+                .map(|index| get_at_timestamp(&ctx, index, &info))
+                .collect::<Vec<_>>(),
+            [30_000, 50_000, 2_400, 60_000]
+        );
+        assert!(
+            dry_run_report(&ctx, &info)
+                .unwrap()
+                .ends_with("\nat[2]: 2400 (00:00:02.400, frame 60)\noutputs: 3")
+        );
+
+        // 错误位置按整个文件计算行号
+        let content = "1:30\n\nend - 10d\n";
+        let line = expression_lines(content)[1];
+        let err = match lexer::parse_expr_complete(line) {
+            Err(nom::Err::Error(err) | nom::Err::Failure(err)) => err.with_line(content.into()),
+            res => panic!("unexpected result: {res:?}"),
+        };
+        assert_eq!(err.line_column().0, 3);
+    }
+
+    #[test]
+    fn test_timestamp_to_frame_inverse() {
+        let cases = [
+            (25f64, 1, 1000, 0),
+            (30000f64 / 1001f64, 1, 90_000, 0),
+            (60000f64 / 1001f64, 1, 90_000, 3003),
+            (24f64, 1, 12_288, -512),
+            (30000f64 / 1001f64, 1001, 30_000, 7),
+            (50f64, 1, 1000, AV_NOPTS_VALUE),
+        ];
+        for (fps, time_base_num, time_base_den, start_time) in cases {
+            let info = VideoInfo {
+                fps,
+                time_base_num,
+                time_base_den,
+                start_time,
+                ..video_info()
+            };
+            for n in (0..5_000).chain((1_000_000..1_000_100).chain([123_456_789])) {
+                let ts = info.frame_to_timestamp(n);
+                assert_eq!(timestamp_to_frame(&info, ts), n, "{info:?} frame {n}");
+                // 两帧之间的时间戳属于前一帧
+                let next = info.frame_to_timestamp(n + 1);
+                if next - ts > 1 {
+                    assert_eq!(info.timestamp_to_frame(next - 1), n, "{info:?} frame {n}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_total_frames() {
+        let info = |fps, time_base_den, start_time, duration| VideoInfo {
+            fps,
+            time_base_num: 1,
+            time_base_den,
+            start_time,
+            duration,
+            ..video_info()
+        };
+        let film = info(24f64, 1000, 0, 10_000);
+        assert_eq!(total_frames(&film), 240);
+        assert_eq!(frames_between(&film, 1000, 2000), 25);
+        assert_eq!(frames_between(&film, 1001, 2000), 24);
+        assert_eq!(frames_between(&film, 2000, 1000), 0);
+
+        // 29.97fps、1/90000时间基下每帧3003
+        let ntsc = info(30000f64 / 1001f64, 90_000, 0, 900_900);
+        assert_eq!(total_frames(&ntsc), 300);
+        assert_eq!(frames_between(&ntsc, 0, 90_090), 31);
+        assert_eq!(frames_between(&ntsc, 1, 3002), 0);
+
+        let offset = info(60f64, 1000, 500, 1000);
+        assert_eq!(total_frames(&offset), 60);
+        assert_eq!(frames_between(&offset, 500, 1500), 61);
+        assert_eq!(frames_between(&offset, 0, 499), 0);
+
+        assert_eq!(total_frames(&info(24f64, 1000, 0, 0)), 0);
+        assert_eq!(total_frames(&info(60f64, 1000, AV_NOPTS_VALUE, 1000)), 60);
+    }
+
+    #[test]
+    fn test_create_video_info_validation() {
+        let create = |fps, time_base_den, time_base_num, duration| {
+            let info = create_video_info(fps, time_base_den, time_base_num, 0, duration);
+            let code = video_info_last_error();
+            free_video_info(info);
+            (info.is_null(), code)
+        };
+        assert_eq!(create(25f64, 1000, 1, 60_000), (false, 0));
+        assert_eq!(create(25f64, 1000, 1, 0), (false, 0));
+        assert_eq!(create(25f64, 1000, 1, AV_NOPTS_VALUE), (false, 0));
+        for fps in [0f64, -25f64, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(create(fps, 1000, 1, 60_000), (true, 1), "fps {fps}");
+        }
+        assert_eq!(create(25f64, 0, 1, 60_000), (true, 2));
+        assert_eq!(create(25f64, 1000, 0, 60_000), (true, 3));
+        assert_eq!(create(25f64, 1000, -1, 60_000), (true, 3));
+        assert_eq!(create(25f64, 1000, 1, -1), (true, 4));
+        assert_eq!(
+            VideoInfo {
+                duration: -1,
+                ..video_info()
+            }
+            .validate(),
+            Err(VideoInfoError::NegativeDuration)
+        );
+        assert_eq!(
+            VideoInfoError::InvalidFps.to_string(),
+            "fps must be a finite positive number"
+        );
+    }
+
+    #[test]
+    fn test_create_video_info_rational() {
+        let info = create_video_info_rational(30000, 1001, 90_000, 1, 0, 0);
+        let ntsc = unsafe { &*info };
+        assert_eq!(ntsc.fps_rational(), Some((30000, 1001)));
+        // av_rescale_q(100000, {1001, 30000}, {1, 90000})
+        assert_eq!(ntsc.frame_to_timestamp(100_000), 300_300_000);
+        assert_eq!(timestamp_to_frame(ntsc, 300_300_000), 100_000);
+        // 每一帧恰好间隔 1001 * 90000 / 30000 = 3003 个时间基单位，不会累积误差
+        for frame in (0..1_000_000u64).step_by(997) {
+            let pts = frame as i64 * 3003;
+            assert_eq!(ntsc.frame_to_timestamp(frame), pts);
+            assert_eq!(ntsc.timestamp_to_frame(pts), frame);
+        }
+        free_video_info(info);
+
+        // 分母超过 MAX_FPS_DENOMINATOR 时浮点帧率无法还原出同一个分数
+        let (fps_num, fps_den) = (30_000_001, 1_000_001);
+        let info = create_video_info_rational(fps_num, fps_den, 90_000, 1, 0, 3_600 * 90_000);
+        let exact = unsafe { &*info };
+        let frame = 100_000u64;
+        let numerator = frame as i128 * fps_den as i128 * 90_000;
+        let expected = (numerator * 2 + fps_num as i128) / (fps_num as i128 * 2);
+        assert_eq!(exact.frame_to_timestamp(frame) as i128, expected);
+        assert_ne!(fps_to_rational(exact.fps), Some((fps_num, fps_den)));
+        assert_eq!(exact.timestamp_to_frame(expected as i64), frame);
+        assert_eq!(
+            total_frames(exact),
+            exact.timestamp_to_frame(3_600 * 90_000 - 1) + 1
+        );
+        free_video_info(info);
+
+        for (fps_num, fps_den) in [(0, 1), (30000, 0), (-30000, 1001)] {
+            let info = create_video_info_rational(fps_num, fps_den, 90_000, 1, 0, 0);
+            assert!(info.is_null());
+            assert_eq!(video_info_last_error(), 1);
+        }
+    }
+
+    #[test]
+    fn test_unknown_duration() {
+        let info = VideoInfo {
+            duration: AV_NOPTS_VALUE,
+            ..video_info()
+        };
+        assert!(!video_info_has_duration(&info));
+        assert!(video_info_has_duration(&video_info()));
+        assert_eq!(info.checked_end_to_timestamp(), None);
+        assert_eq!(info.end_to_timestamp(), i64::MAX);
+        let time = |s: &str| PaserTimeType::from(s.parse::<Time>().unwrap()).to_timestamp(&info);
+        assert_eq!(time("end"), Err(DURATION_UNKNOWN));
+        assert_eq!(time("-10s"), Err(DURATION_UNKNOWN));
+        assert_eq!(time("10s"), Ok(10_000));
+        assert_eq!(total_frames(&info), 0);
+    }
+
+    #[test]
+    fn test_parser_time_overflow() {
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            ..video_info()
+        };
+        let time = |s: &str| PaserTimeType::from(s.parse::<Time>().unwrap()).to_timestamp(&info);
+        // 纳秒数超出 `u64` 的时长不会被截断为较小的值
+        assert_eq!(
+            PaserTimeType::from("9999999999999s".parse::<Time>().unwrap()),
+            PaserTimeType {
+                kind: TimeTypeKind::Millisecond,
+                value: 9_999_999_999_999_000,
+            }
+        );
+        assert_eq!(time("999999999999999s"), Err(TIMESTAMP_OVERFLOW));
+        assert_eq!(time("9223372036854775807"), Err(TIMESTAMP_OVERFLOW));
+        let info = VideoInfo {
+            start_time: i64::MAX - 10,
+            ..video_info()
+        };
+        assert_eq!(
+            PaserTimeType::from(Time::FromEnd(Duration::from_secs(1))).to_timestamp(&info),
+            Err(TIMESTAMP_OVERFLOW)
+        );
+
+        let ctx = ArgParseResultContext::builder()
+            .from("999999999999999s".parse().unwrap())
+            .build();
+        let mut pts = 0;
+        let info = VideoInfo {
+            time_base_den: 90_000,
+            ..video_info()
+        };
+        assert_eq!(get_from_timestamp_checked(&ctx, &info, &mut pts), 1);
+        assert_eq!(
+            LAST_EVAL_ERROR.with_borrow(|err| err.clone().map(|err| err.message)),
+            Some(TIMESTAMP_OVERFLOW.to_string())
+        );
+    }
+
+    #[test]
+    fn test_checked_timestamps() {
+        let last_error = || {
+            let ptr = get_last_error();
+            if ptr.is_null() {
+                return None;
+            }
+            let message = unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned();
+            free_cstring(ptr);
+            Some(message)
+        };
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::Time(Duration::from_secs(10)))
+            .to(Time::End)
+            .build();
+        let unknown = VideoInfo {
+            duration: AV_NOPTS_VALUE,
+            ..video_info()
+        };
+        let mut pts = -1;
+        assert_eq!(get_to_timestamp_checked(&ctx, &unknown, &mut pts), 1);
+        assert_eq!(pts, -1);
+        assert_eq!(last_error(), Some(format!("to: {DURATION_UNKNOWN}")));
+
+        let (mut from, mut to) = (0, 0);
+        assert_eq!(
+            get_from_to_timestamps_checked(&ctx, &video_info(), &mut from, &mut to),
+            0
+        );
+        assert_eq!((from, to), (10_000, 60_000));
+        assert_eq!(last_error(), None);
+
+        // 不检查范围的函数保持原来的行为，检查范围的函数返回错误
+        let ctx = ArgParseResultContext::builder()
+            .from(Time::Time(Duration::from_secs(90)))
+            .build();
+        assert_eq!(get_from_timestamp(&ctx, &video_info()), 90_000);
+        assert_eq!(get_from_timestamp_checked(&ctx, &video_info(), &mut pts), 1);
+        assert_eq!(
+            last_error(),
+            Some(
+                "from: `from` is past the end of the video: 00:01:30.000 > 00:01:00.000"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_time_type_display() {
+        let time = |s: &str| TimeType::from(s.parse::<Time>().unwrap()).to_string();
+        assert_eq!(time("100"), "100");
+        assert_eq!(time("1.5s"), "1.5s");
+        assert_eq!(time("1:30"), "90s");
+        assert_eq!(time("END"), "end");
+        assert_eq!(time("-5s"), "-5s");
+        let per = PaserTimeType {
+            kind: TimeTypeKind::Millisecond,
+            value: 2_250,
+        };
+        assert_eq!(per.to_string(), "2.25s");
+        for s in ["100", "1.5s", "end", "-5s"] {
+            assert_eq!(time(&time(s)), time(s));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_expr_string() {
+        let ctx = context("1s + 500ms", "end + 2s + 3s");
+        let to_string = |ptr: *mut c_char| {
+            let s = unsafe { std::ffi::CStr::from_ptr(ptr) }
+                .to_str()
+                .unwrap()
+                .to_string();
+            free_cstring(ptr);
+            s
+        };
+        assert_eq!(to_string(get_from_expr_string(&ctx)), "1.5s");
+        let to = to_string(get_to_expr_string(&ctx));
+        assert_eq!(to, "end + 5s");
+        let reparsed = to.parse::<lexer::CheckedExpr>().unwrap();
+        assert_eq!(reparsed.to_string(), to);
+    }
+
+    #[test]
+    #[cfg(feature = "dsl")]
+    fn test_unknown_duration_dsl() {
+        let info = VideoInfo {
+            duration: AV_NOPTS_VALUE,
+            ..video_info()
+        };
+        let ctx = context("10s", "end - 5s");
+        let err = eval_position(&ctx, &info, KnownTimes::default(), "to", ctx_expr(&ctx.end))
+            .unwrap_err();
+        assert_eq!(err.message, DURATION_UNKNOWN);
+        assert_eq!(
+            err.span,
+            lexer::SourceSpan {
+                offset: 0,
+                length: 3
+            }
+        );
+        // 时长未知时不检查是否超过结束时间
+        let ctx = context("10s", "from + 10:00:00");
+        let known = KnownTimes {
+            from: Some(10_000),
+            to: None,
+        };
+        assert_eq!(
+            eval_position(&ctx, &info, known, "to", ctx_expr(&ctx.end)),
+            Ok(36_010_000)
+        );
     }
 }
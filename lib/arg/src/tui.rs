@@ -1,10 +1,40 @@
-use crate::lexer::{Expr, Span, error::ParseExprResult};
+use crate::lexer::{
+    DSLItem, DSLType, Expr, Lint, Span, did_you_mean,
+    error::{Diagnostic, ParseError, ParseExprResult},
+    keyword_spellings,
+};
 use colored::Colorize;
+use nom::Input;
 use std::fmt::Display;
+use std::io::Write;
+use unicode_width::UnicodeWidthStr;
 
-const KEYWORDS: [&str; 3] = ["from", "to", "end"];
+/// 计算偏移量在源字符串中的行号和列号（均从1开始，列号按字符计算）
+///
+/// # 参数
+/// * `content` - 源字符串
+/// * `offset` - 字节偏移量
+pub fn line_column(content: &str, offset: usize) -> (u32, usize) {
+    let span = Span::new(content).take_from(offset.min(content.len()));
+    (span.location_line(), span.get_utf8_column())
+}
+
+/// 生成 `来源:行:列` 形式的位置描述
+///
+/// # 参数
+/// * `content_type` - 表达式的来源，例如 `from` 或文件路径
+/// * `content` - 源字符串
+/// * `offset` - 字节偏移量
+pub fn location(content_type: &str, content: &str, offset: usize) -> String {
+    let (line, column) = line_column(content, offset);
+    format!("{content_type}:{line}:{column}")
+}
 
+/// 将错误信息写入 `writer`，`color` 为false时不输出颜色，通常传入 [`use_color`] 的结果
+#[allow(clippy::too_many_arguments)]
 pub fn show_error<T>(
+    writer: &mut dyn Write,
+    color: bool,
     message: &str,
     from: &str,
     content: &str,
@@ -15,163 +45,584 @@ pub fn show_error<T>(
 ) where
     T: AsRef<str> + Display,
 {
-    println!("{}: {}", "error".bright_red(), message.bright_white());
-    println!("{}", format!("  --> {from}").bright_cyan().bold());
-    println!("   {}", "|".bright_cyan().bold());
-    println!(" {} {content}", "1 |".bright_cyan().bold());
-    println!(
-        "   {} {}{} {}",
+    write_diagnostic(
+        writer,
+        &render_error(message, from, content, offset, length, tips, help),
+        color,
+    );
+}
+
+/// 是否输出颜色，设置了非空的 `NO_COLOR` 环境变量时不输出颜色
+pub fn use_color() -> bool {
+    color_enabled(std::env::var_os("NO_COLOR"))
+}
+
+fn color_enabled(no_color: Option<std::ffi::OsString>) -> bool {
+    no_color.is_none_or(|value| value.is_empty())
+}
+
+/// 将渲染好的诊断信息写入 `writer`
+///
+/// # 参数
+/// * `writer` - 输出位置
+/// * `text` - 渲染好的诊断信息
+/// * `color` - 为false时去掉文本中的ANSI转义序列
+pub fn write_diagnostic(writer: &mut dyn Write, text: &str, color: bool) {
+    if color {
+        _ = writeln!(writer, "{text}");
+    } else {
+        _ = writeln!(writer, "{}", strip_ansi(text));
+    }
+}
+
+/// 去掉文本中形如 `ESC [ ... m` 的ANSI转义序列
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// 生成 `show_error` 输出的内容，参数与 `show_error` 相同
+pub fn render_error<T>(
+    message: &str,
+    from: &str,
+    content: &str,
+    offset: usize,
+    length: usize,
+    tips: Option<&str>,
+    help: Option<T>,
+) -> String
+where
+    T: AsRef<str> + Display,
+{
+    render_diagnostic(
+        Level::Error,
+        message,
+        from,
+        content,
+        offset,
+        length,
+        tips,
+        help,
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 诊断信息的级别
+enum Level {
+    Error,
+    Warning,
+}
+
+impl Level {
+    /// 按级别的颜色渲染文本
+    fn paint(self, text: &str) -> colored::ColoredString {
+        match self {
+            Self::Error => text.bright_red(),
+            Self::Warning => text.bright_yellow(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_diagnostic<T>(
+    level: Level,
+    message: &str,
+    from: &str,
+    content: &str,
+    offset: usize,
+    length: usize,
+    tips: Option<&str>,
+    help: Option<T>,
+) -> String
+where
+    T: AsRef<str> + Display,
+{
+    use std::fmt::Write;
+    // 偏移量是字节偏移量，对齐时按显示宽度计算，使全角字符不会让 `^` 错位
+    let offset = offset.min(content.len());
+    let span = Span::new(content).take_from(offset);
+    let line_no = span.location_line();
+    let line_start = offset + 1 - span.get_column();
+    let line = content[line_start..].lines().next().unwrap_or_default();
+    let mut end = (offset + length).min(content.len());
+    while !content.is_char_boundary(end) {
+        end += 1;
+    }
+    let column = content[line_start..offset].width();
+    let length = content[offset..end].width().max(length.min(1));
+    let line_no = line_no.to_string();
+    let pad = " ".repeat(line_no.len() + 1);
+    let mut out = String::new();
+    _ = writeln!(
+        out,
+        "{}: {}",
+        level.paint(level.name()),
+        message.bright_white()
+    );
+    _ = writeln!(out, "{}", format!("{pad}--> {from}").bright_cyan().bold());
+    _ = writeln!(out, "{pad} {}", "|".bright_cyan().bold());
+    _ = writeln!(
+        out,
+        " {} {line}",
+        format!("{line_no} |").bright_cyan().bold()
+    );
+    _ = writeln!(
+        out,
+        "{pad} {} {}{} {}",
         "|".bright_cyan().bold(),
-        " ".repeat(offset),
-        "^".repeat(length).bright_red(),
-        tips.unwrap_or_default().bright_red()
+        " ".repeat(column),
+        level.paint(&"^".repeat(length)),
+        level.paint(tips.unwrap_or_default())
     );
     if let Some(help) = help {
-        println!("   {}", "|".bright_cyan().bold());
-        println!("   {}", format!("= help: {}", help).bright_cyan().bold());
+        _ = writeln!(out, "{pad} {}", "|".bright_cyan().bold());
+        _ = writeln!(
+            out,
+            "{pad} {}",
+            format!("= help: {}", help).bright_cyan().bold()
+        );
+    }
+    out
+}
+
+/// 生成不指向源码的警告，例如选项的值被调整时
+///
+/// # 参数
+/// * `message` - 警告信息
+/// * `help` - 帮助信息
+pub fn render_warning(message: &str, help: Option<&str>) -> String {
+    let mut out = format!(
+        "{}: {}",
+        Level::Warning.paint(Level::Warning.name()),
+        message.bright_white()
+    );
+    if let Some(help) = help {
+        out += &format!("\n{}", format!("  = help: {help}").bright_cyan().bold());
+    }
+    out
+}
+
+/// 生成lint的输出内容
+///
+/// # 参数
+/// * `content` - 表达式的源字符串
+/// * `content_type` - 表达式的来源
+/// * `lint` - lint结果
+/// * `deny` - 为true时作为错误输出，否则作为警告输出
+pub fn render_lint(content: &str, content_type: &str, lint: &DSLItem<Lint>, deny: bool) -> String {
+    let (message, tips, help) = match lint.content {
+        Lint::MixedUnits => (
+            "frame indexes and timestamps are mixed, the result depends on the frame rate"
+                .to_string(),
+            "frame index",
+            "use only timestamps (or only frame indexes) so the expression means the same at any frame rate"
+                .to_string(),
+        ),
+        Lint::UnanchoredMixedUnits => (
+            "frame indexes and timestamps are mixed without a keyword anchor, the result is not a meaningful position"
+                .to_string(),
+            "frame index",
+            "anchor the offsets to a position, for example `from + ...` or `end - ...`".to_string(),
+        ),
+        Lint::LargeFrameIndex(index) => (
+            format!("frame index `{index}f` is unusually large"),
+            "too large",
+            format!("did you mean `{index}ms`?"),
+        ),
+    };
+    let level = if deny { Level::Error } else { Level::Warning };
+    render_diagnostic(
+        level,
+        &message,
+        &location(content_type, content, lint.offset),
+        content,
+        lint.offset,
+        lint.length,
+        Some(tips),
+        Some(help),
+    )
+}
+
+/// 检查表达式中的变量是否都已定义，存在未定义的变量时输出错误并退出
+///
+/// # 参数
+/// * `content` - 表达式的源字符串
+/// * `content_type` - 表达式的来源，例如 `from`
+/// * `expr` - 解析出的表达式
+/// * `names` - 已定义的变量名
+pub fn check_vars(content: &str, content_type: &str, expr: &Expr, names: &[&str]) {
+    for item in expr.items() {
+        let name = match item.content {
+            DSLType::Var(ref name) => name,
+            DSLType::Call { ref args, .. } => {
+                for arg in args {
+                    check_vars(content, content_type, arg, names);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if names.contains(&name.as_str()) {
+            continue;
+        }
+        let keywords = keyword_spellings().collect::<Vec<_>>();
+        show_error(
+            &mut std::io::stderr(),
+            use_color(),
+            &format!("unknown variable: `{name}`"),
+            &location(content_type, content, item.offset),
+            content,
+            item.offset,
+            item.length,
+            Some("not defined by `--let`"),
+            did_you_mean(name, names.iter().chain(&keywords).copied()),
+        );
+        std::process::exit(1);
     }
-    println!();
 }
 
+/// 生成诊断信息的输出内容
+///
+/// # 参数
+/// * `content` - 表达式的源字符串
+/// * `content_type` - 表达式的来源
+/// * `diagnostic` - 诊断信息
+pub fn render_diagnostic_info(
+    content: &str,
+    content_type: &str,
+    diagnostic: &Diagnostic,
+) -> String {
+    render_error(
+        &diagnostic.message,
+        &location(content_type, content, diagnostic.offset),
+        content,
+        diagnostic.offset,
+        diagnostic.length,
+        diagnostic.label.as_deref(),
+        diagnostic.help.as_deref(),
+    )
+}
+
+/// 将诊断信息写入 `writer`，`color` 为false时不输出颜色
+pub fn show_diagnostic(
+    writer: &mut dyn Write,
+    color: bool,
+    content: &str,
+    content_type: &str,
+    diagnostic: &Diagnostic,
+) {
+    write_diagnostic(
+        writer,
+        &render_diagnostic_info(content, content_type, diagnostic),
+        color,
+    );
+}
+
+/// 为无法解析的词生成修改建议
+///
+/// - 数字后跟未知的单位时（例如 `100d`），建议改为帧数或毫秒（例如 `100f`、`100ms`）
+/// - 以 `:` 分隔的字段超过三个时（例如 `100:2:3:4`），建议去掉多余的字段
+/// - 其他单词按拼写距离建议最接近的关键字
+///
+/// # 参数
+/// * `bad` - 无法解析的词
+///
+/// # 返回值
+/// 形如 ``did you mean `100f`?`` 的提示，没有合适的建议时返回 `None`
+pub fn suggest_token(bad: &str) -> Option<String> {
+    const UNITS: [&str; 6] = ["f", "s", "ms", "us", "µs", "ns"];
+    let number_len = bad
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(bad.len());
+    let (number, unit) = bad.split_at(number_len);
+    if number.chars().any(|c| c.is_ascii_digit())
+        && !unit.is_empty()
+        && unit.chars().all(char::is_alphabetic)
+    {
+        if UNITS.contains(&unit) {
+            return None;
+        }
+        return Some(if number.contains('.') {
+            format!("did you mean `{number}s` or `{number}ms`?")
+        } else {
+            format!("did you mean `{number}f` (frames) or `{number}ms`?")
+        });
+    }
+    let fields = bad.split(':').collect::<Vec<_>>();
+    if fields.len() > 3 && fields.iter().all(|field| !field.is_empty()) {
+        let kept = fields[..3].join(":");
+        return Some(format!("did you mean `{kept}`?"));
+    }
+    did_you_mean(bad, keyword_spellings())
+}
+
+/// 获取 `offset` 处的完整单词，用于生成修改建议
+fn token_at(content: &str, offset: usize) -> &str {
+    let offset = offset.min(content.len());
+    let is_token_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '.' | ':');
+    let start = content[..offset]
+        .rfind(|c: char| !is_token_char(c))
+        .map_or(0, |index| {
+            index + content[index..].chars().next().map_or(1, char::len_utf8)
+        });
+    let end = content[offset..]
+        .find(|c: char| !is_token_char(c))
+        .map_or(content.len(), |index| offset + index);
+    &content[start..end]
+}
+
+/// 将表达式的解析错误写入 `writer`，没有提示时根据出错的词生成修改建议
+///
+/// # 参数
+/// * `writer` - 输出位置
+/// * `color` - 是否输出颜色
+/// * `content` - 表达式的源字符串
+/// * `content_type` - 表达式的来源，例如 `from`
+/// * `err` - 解析错误
+pub fn show_parse_error(
+    writer: &mut dyn Write,
+    color: bool,
+    content: &str,
+    content_type: &str,
+    err: ParseError<nom::error::Error<Span>>,
+) {
+    let err = err.with_line(content.into());
+    let mut diagnostic = err.to_diagnostic();
+    if diagnostic.help.is_none() {
+        diagnostic.help = suggest_token(token_at(content, diagnostic.offset));
+    }
+    show_diagnostic(writer, color, content, content_type, &diagnostic);
+}
+
+/// 解析成功时返回结果，否则将错误写入 `writer` 后以退出码1退出
 pub fn handle_error<'a>(
+    writer: &mut dyn Write,
     content: &str,
     content_type: &str,
     res: ParseExprResult<Span<'a>, Expr>,
 ) -> (Span<'a>, Expr) {
-    use crate::lexer::error::ParseErrorKind;
     match res {
         Ok(res) => return res,
-        Err(e) => match e {
-            nom::Err::Error(err) | nom::Err::Failure(err) => match err.source.code {
-                nom::error::ErrorKind::Count => show_error::<&str>(
-                    "too many args, the time num must lower than 3",
-                    &format!(
-                        "{content_type}:{}:{}",
-                        err.source.input.location_line(),
-                        err.offset + 1
-                    ),
-                    content,
-                    err.offset,
-                    err.length,
-                    Some("too many args"),
-                    None,
-                ),
-                nom::error::ErrorKind::Tag => match err.kind {
-                    ParseErrorKind::Op => {
-                        show_error::<&str>(
-                            "missing operation, expected `+` or `-`",
-                            &format!(
-                                "{content_type}:{}:{}",
-                                err.source.input.location_line(),
-                                err.offset + 1
-                            ),
-                            content,
-                            err.offset,
-                            1,
-                            Some("here"),
-                            None,
-                        );
-                    }
-                    _ => {
-                        let word =
-                            nom::character::complete::alpha1::<Span, nom::error::Error<Span>>(
-                                err.source.input,
-                            )
-                            .map(|(_, word)| Some(word.to_string()))
-                            .unwrap_or(None);
-                        let suggests = if let Some(ref word) = word
-                            && err.kind == ParseErrorKind::Keywords
-                        {
-                            let mut temp = KEYWORDS
-                                .iter()
-                                .map(|words| {
-                                    (
-                                        words,
-                                        strsim::damerau_levenshtein(word, words)
-                                            - if words.chars().next() == word.chars().next() {
-                                                1
-                                            } else {
-                                                0
-                                            },
-                                    )
-                                })
-                                .filter(|(_, dist)| *dist <= 2)
-                                .collect::<Vec<_>>();
-                            temp.sort_by(|(_, dist1), (_, dist2)| dist1.cmp(dist2));
-                            temp
-                        } else {
-                            vec![]
-                        };
-                        let help = if !suggests.is_empty() {
-                            match suggests.len() {
-                                1 => Some(format!("did you mean `{}`?", suggests[0].0)),
-                                x if x > 1 => {
-                                    if suggests[0].1 < suggests[1].1 {
-                                        Some(format!("did you mean `{}`?", suggests[0].0))
-                                    } else {
-                                        None
-                                    }
-                                }
-                                _ => None,
-                            }
-                        } else {
-                            None
-                        };
-                        let word = word.map(|word| format!(": `{word}`")).unwrap_or_default();
-                        let msg = if err.kind == ParseErrorKind::Keywords {
-                            "unknown keyword"
-                        } else {
-                            "invalid token"
-                        };
-                        show_error(
-                            &format!("{msg}{word}"),
-                            &format!(
-                                "{content_type}:{}:{}",
-                                err.source.input.location_line(),
-                                err.offset + 1
-                            ),
-                            content,
-                            err.offset + err.length,
-                            word.len().saturating_sub(4).max(1),
-                            Some(msg),
-                            help.as_ref(),
-                        );
-                    }
-                },
-                nom::error::ErrorKind::Escaped => show_error::<&str>(
-                    &format!(
-                        "escaped operation: `{}`",
-                        content.chars().nth(err.offset).unwrap_or_default()
-                    ),
-                    &format!(
-                        "{content_type}:{}:{}",
-                        err.source.input.location_line(),
-                        err.offset + 1
-                    ),
-                    content,
-                    err.offset,
-                    err.length,
-                    Some("escaped operation"),
-                    None,
-                ),
-                _ => {}
-            },
-            _ => {}
-        },
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => {
+            show_parse_error(writer, use_color(), content, content_type, err);
+        }
+        Err(nom::Err::Incomplete(..)) => {}
     }
     std::process::exit(1);
 }
 
 #[cfg(test)]
 mod tests {
-    use super::handle_error;
-    use crate::lexer::parse_expr;
+    use super::{
+        color_enabled, did_you_mean, line_column, location, render_diagnostic_info, render_error,
+        render_lint, render_warning, show_error, show_parse_error, strip_ansi, suggest_token,
+        token_at, write_diagnostic,
+    };
+    use crate::lexer::{keyword_spellings, parse_expr};
+    use colored::Colorize;
+
+    #[test]
+    fn test_line_column() {
+        let content = "end\n  - 5s\n  + 1f";
+        assert_eq!(line_column(content, 0), (1, 1));
+        assert_eq!(line_column(content, 6), (2, 3));
+        assert_eq!(line_column(content, 15), (3, 5));
+        assert_eq!(location("cuts/from.expr", content, 6), "cuts/from.expr:2:3");
+    }
 
     #[test]
     fn test_show_error() {
         let from = r#"end - 1d"#;
-        let res = parse_expr(from.into());
-        let (_, expr) = handle_error(from, "from", res);
-        println!("{expr:?}");
+        let Err(nom::Err::Error(err) | nom::Err::Failure(err)) = parse_expr(from.into()) else {
+            panic!("expected an error");
+        };
+        let mut out = Vec::new();
+        show_parse_error(&mut out, false, from, "from", err);
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("error: invalid token: `d`\n"), "{text}");
+        assert!(text.contains("--> from:1:8\n"), "{text}");
+        assert!(
+            text.contains("did you mean `1f` (frames) or `1ms`?"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn test_show_parse_error() {
+        let content = "end - 1:2:3:4";
+        let Err(nom::Err::Failure(err)) = parse_expr(content.into()) else {
+            panic!("expected failure");
+        };
+        let mut out = Vec::new();
+        show_parse_error(&mut out, false, content, "from", err);
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.starts_with("error: too many `:`-separated fields"),
+            "{text}"
+        );
+        assert!(text.contains("--> from:1:7\n"), "{text}");
+        assert!(text.contains("|       ^^^^^^^ too many fields\n"), "{text}");
+    }
+
+    #[test]
+    fn test_no_color() {
+        assert!(color_enabled(None));
+        assert!(color_enabled(Some("".into())));
+        assert!(!color_enabled(Some("1".into())));
+
+        let mut out = Vec::new();
+        show_error(
+            &mut out,
+            false,
+            "invalid token: `1d`",
+            "from:1:7",
+            "end - 1d",
+            6,
+            2,
+            Some("invalid token"),
+            Some("use `1s`"),
+        );
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("error: invalid token: `1d`\n"));
+
+        let colored = "error".bright_red().bold().to_string();
+        let mut out = Vec::new();
+        write_diagnostic(&mut out, &colored, false);
+        assert_eq!(out, b"error\n");
+        let mut out = Vec::new();
+        write_diagnostic(&mut out, &colored, true);
+        assert_eq!(out, format!("{colored}\n").as_bytes());
+    }
+
+    #[test]
+    fn test_wide_char_columns() {
+        let render = |content, offset, length| {
+            strip_ansi(&render_error::<&str>(
+                "msg",
+                "from",
+                content,
+                offset,
+                length,
+                Some("here"),
+                None,
+            ))
+        };
+        // 全角的 `＋` 占3个字节，显示宽度为2
+        let text = render("end ＋ 5s", 4, 3);
+        assert!(text.contains(" 1 | end ＋ 5s\n"));
+        assert!(text.contains("  |     ^^ here\n"));
+        let text = render("片头 + 5d", 9, 2);
+        assert!(text.contains("  |        ^^ here\n"));
+        assert_eq!(location("from", "片头 + 5d", 9), "from:1:6");
+        // 长度不在字符边界上时扩展到整个字符
+        let text = render("片头 + 5s", 0, 1);
+        assert!(text.contains("  | ^^ here\n"));
+        let text = render("end\n  - 片头", 8, 6);
+        assert!(text.contains(" 2 |   - 片头\n"));
+        assert!(text.contains("  |     ^^^^ here\n"));
+    }
+
+    #[test]
+    fn test_time_fields_error() {
+        let content = "end - 1:02:03:04";
+        let Err(nom::Err::Failure(err)) = parse_expr(content.into()) else {
+            panic!("expected failure");
+        };
+        let text = strip_ansi(&render_diagnostic_info(content, "to", &err.to_diagnostic()));
+        assert!(
+            text.starts_with("error: too many `:`-separated fields (expected mm:ss or hh:mm:ss)\n")
+        );
+        assert!(text.contains("--> to:1:7\n"));
+        assert!(text.contains("|       ^^^^^^^^^^ too many fields\n"));
+        assert!(text.contains("e.g. `1:02:03`"));
+    }
+
+    #[test]
+    fn test_render_warning() {
+        assert_eq!(
+            strip_ansi(&render_warning(
+                "thread count 60000 is too large",
+                Some("lower it")
+            )),
+            "warning: thread count 60000 is too large\n  = help: lower it"
+        );
+        assert_eq!(
+            strip_ansi(&render_warning("careful", None)),
+            "warning: careful"
+        );
+    }
+
+    #[test]
+    fn test_render_lint() {
+        let content = "from + 100f + 5s";
+        let (_, expr) = parse_expr(content.into()).unwrap();
+        let lints = crate::lexer::lint_expr(&expr);
+        assert_eq!(lints.len(), 1);
+        let text = strip_ansi(&render_lint(content, "to", &lints[0], false));
+        assert!(text.starts_with(
+            "warning: frame indexes and timestamps are mixed, the result depends on the frame rate\n"
+        ));
+        assert!(text.contains("--> to:1:8\n"));
+        assert!(text.contains("|        ^^^^ frame index\n"));
+        let text = strip_ansi(&render_lint(content, "to", &lints[0], true));
+        assert!(text.starts_with("error: frame indexes and timestamps are mixed"));
+
+        let content = "100f - 5s";
+        let (_, expr) = parse_expr(content.into()).unwrap();
+        let lints = crate::lexer::lint_expr(&expr);
+        let text = strip_ansi(&render_lint(content, "from", &lints[0], false));
+        assert!(text.starts_with("warning: frame indexes and timestamps are mixed without"));
+        assert!(text.contains("`from + ...`"));
+    }
+
+    #[test]
+    fn test_keyword_suggestion() {
+        assert_eq!(
+            did_you_mean("duraton", keyword_spellings()),
+            Some("did you mean `duration`?".to_string())
+        );
+        assert_eq!(
+            did_you_mean("ned", keyword_spellings()),
+            Some("did you mean `end`?".to_string())
+        );
+    }
+
+    #[test]
+    fn test_suggest_token() {
+        assert_eq!(
+            suggest_token("100d"),
+            Some("did you mean `100f` (frames) or `100ms`?".to_string())
+        );
+        assert_eq!(
+            suggest_token("1.5x"),
+            Some("did you mean `1.5s` or `1.5ms`?".to_string())
+        );
+        assert_eq!(
+            suggest_token("100:2:3:4"),
+            Some("did you mean `100:2:3`?".to_string())
+        );
+        assert_eq!(
+            suggest_token("frim"),
+            Some("did you mean `from`?".to_string())
+        );
+        assert_eq!(suggest_token("100ms"), None);
+        assert_eq!(suggest_token("xyzzy"), None);
+        assert_eq!(token_at("end - 100d + 1s", 9), "100d");
+        assert_eq!(token_at("100d", 3), "100d");
     }
 }
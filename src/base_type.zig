@@ -9,6 +9,8 @@ pub const VideoInfo = struct {
     frame_count: usize,
     /// 当前帧索引
     frame_index: usize,
+    /// 视频流数量
+    stream_count: u32,
     /// 视频持续时间（以时间基为单位）
     duration: u64,
     /// 视频宽度（像素）
@@ -17,6 +19,8 @@ pub const VideoInfo = struct {
     height: u32,
     /// 视频帧率
     fps: f64,
+    /// 分数形式的视频帧率，与 fps 相同但没有浮点误差
+    frame_rate: av.AVRational,
     /// 像素格式
     fmt: av.AVPixelFormat,
     /// 时间基，用于时间戳转换
@@ -6,7 +6,7 @@ pub const ffmpeg_err = error{
     AllocateFrameFailed,
 };
 
-pub const cli_err = error{ CannotFoundFile, InvalidRange };
+pub const cli_err = error{ CannotFoundFile, InvalidRange, InvalidStreamIndex, InvalidVideoInfo };
 
 pub const VideoReadFrameError = error{
     EOF,
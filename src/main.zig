@@ -4,6 +4,8 @@ const arg = @cImport({
     @cInclude("arg.h");
 });
 
+const av = @import("cimport.zig").av;
+
 const util = @import("util.zig");
 const errs = @import("error.zig");
 const to_img = @import("frame_to_image.zig");
@@ -37,36 +39,54 @@ pub fn main() !void {
 
     const input: []const u8 = std.mem.sliceTo(arg.get_input(arg_ctx), 0);
     const output: []const u8 = std.mem.sliceTo(arg.get_output(arg_ctx), 0);
-    const format: []const u8 = std.mem.sliceTo(arg.get_format(arg_ctx), 0);
+    const format = arg.get_format(arg_ctx);
 
     // 检查输入文件是否存在
     std.fs.cwd().access(input, .{}) catch return errs.cli_err.CannotFoundFile;
 
-    const out = try std.fs.cwd().makeOpenPath(output, .{});
-    const info = try read_info.get_video_info(input);
+    const info = try read_info.get_video_info(input, arg.get_stream_index(arg_ctx));
     try stdout.print("info: {f}\n", .{info});
     try stdout.flush();
 
     // zig fmt: off
-    const arg_info = arg.create_video_info(
-        info.fps, 
+    const arg_info = arg.create_video_info_rational_extended(
+        info.frame_rate.num,
+        info.frame_rate.den,
         @intCast(info.time_base.den), 
         @intCast(info.time_base.num), 
         info.start_time, 
-        @intCast(info.duration)
+        @intCast(info.duration),
+        info.stream_count
     );
+    // 帧率、时间基或时长无效时返回空指针
+    if (arg_info == null) {
+        std.debug.print("error: invalid video info (code {d})\n", .{arg.video_info_last_error()});
+        return errs.cli_err.InvalidVideoInfo;
+    }
     defer arg.free_video_info(arg_info);
+    // 已知帧数时用于检查帧序号，为0表示未知
+    arg.set_video_info_nb_frames(arg_info, @intCast(info.frame_count));
 
-    // 根据起始时间类型转换为时间戳
-    const from = arg.get_from_timestamp(
-        arg_ctx,
-        arg_info
-    );
+    // 只打印解析出的时间戳，不创建输出目录也不提取帧
+    // 成功时直接退出，表达式无法求值时返回错误
+    if (arg.get_dry_run(arg_ctx) and arg.print_dry_run(arg_ctx, arg_info) != 0) {
+        arg.print_last_error(arg_ctx);
+        return errs.cli_err.InvalidRange;
+    }
 
-    // 根据结束时间类型转换为时间戳
-    const to = arg.get_to_timestamp(arg_ctx, arg_info);
+    const out = try std.fs.cwd().makeOpenPath(output, .{});
 
-    if (from > to)
+    // 同时计算起止时间戳，to 引用 from 时 from 只会求值一次
+    // 无法求值或超出视频范围时输出指向出错的项的错误
+    var from: i64 = 0;
+    var to: i64 = 0;
+    if (arg.get_from_to_timestamps_checked(arg_ctx, arg_info, &from, &to) != 0) {
+        arg.print_last_error(arg_ctx);
+        return errs.cli_err.InvalidRange;
+    }
+
+    // 起止时间依赖视频信息，在这里检查 from 是否晚于 to、范围内是否有帧
+    if (arg.validate_range(arg_ctx, arg_info) != 0)
         return errs.cli_err.InvalidRange;
     
     if (from < 0)
@@ -87,9 +107,58 @@ pub fn main() !void {
     var saver = try to_img.ToImage.init(@bitCast(info.width), @bitCast(info.height), info.fmt, .{});
     defer saver.deinit();
 
+    // 提取 --at 指定的时间点，或均匀选取指定数量的帧，每一帧都单独跳转
+    const at_count = arg.get_at_count(arg_ctx);
+    const sample_count = if (at_count > 0) at_count else arg.get_sample_count(arg_ctx);
+    if (sample_count > 0) {
+        var sample_index: u64 = 0;
+        while (sample_index < sample_count) : (sample_index += 1) {
+            var target: i64 = 0;
+            if (at_count > 0) {
+                if (arg.get_at_timestamp_checked(arg_ctx, sample_index, arg_info, &target) != 0) {
+                    arg.print_last_error(arg_ctx);
+                    return errs.cli_err.InvalidRange;
+                }
+            } else {
+                target = arg.get_sample_timestamp(arg_ctx, sample_index, arg_info);
+            }
+            try reader.seek(target);
+            while (true) {
+                var frame = reader.read_frame() catch |err| {
+                    switch (err) {
+                        errs.VideoReadFrameError.EOF => break,
+                        else => return err,
+                    }
+                };
+                defer frame.deinit();
+
+                if (frame.frame.*.pts < target)
+                    continue;
+
+                const timestamp_ms = av.av_rescale_q(frame.frame.*.pts, info.time_base, .{ .num = 1, .den = 1000 });
+                const index = arg.timestamp_to_frame(arg_info, frame.frame.*.pts);
+                const name_ptr = arg.format_filename_c(format, @intCast(index), @intCast(@max(timestamp_ms, 0)), info.fps);
+                defer arg.free_cstring(name_ptr);
+                const name: []const u8 = std.mem.sliceTo(name_ptr, 0);
+
+                try stdout.print("Save: {s}\n", .{name});
+                try stdout.flush();
+
+                try saver.save(frame.frame, out, name);
+                break;
+            }
+        }
+        return;
+    }
+
     try reader.seek(from);
 
-    var frame_index = util.timestamp_to_frame(from, &info);
+    var frame_index = arg.timestamp_to_frame(arg_info, from);
+    const first_index = frame_index;
+    // 指定了 --interval 时按累计的时间戳采样，否则每隔 step 帧保存一帧
+    const interval = arg.get_interval_timestamp(arg_ctx, arg_info);
+    const step = arg.get_step_frames(arg_ctx, arg_info);
+    var next_pts = from;
 
     // 循环读取视频帧并保存为图片
     while (true) {
@@ -107,9 +176,23 @@ pub fn main() !void {
         if (frame.frame.*.pts < from)
             continue;
 
-        var buf: [PATH_MAX]u8 = undefined;
-        try util.format_str(format, &buf, @as(c_ulonglong, @intCast(frame_index)));
-        const name: []const u8 = std.mem.sliceTo(&buf, 0);
+        if (interval > 0) {
+            if (frame.frame.*.pts < next_pts) {
+                frame_index += 1;
+                continue;
+            }
+            while (next_pts <= frame.frame.*.pts)
+                next_pts += interval;
+        } else if ((frame_index - first_index) % step != 0) {
+            frame_index += 1;
+            continue;
+        }
+
+        // 生成文件名，时间戳换算为毫秒
+        const timestamp_ms = av.av_rescale_q(frame.frame.*.pts, info.time_base, .{ .num = 1, .den = 1000 });
+        const name_ptr = arg.format_filename_c(format, @intCast(frame_index), @intCast(@max(timestamp_ms, 0)), info.fps);
+        defer arg.free_cstring(name_ptr);
+        const name: []const u8 = std.mem.sliceTo(name_ptr, 0);
 
         try stdout.print("Save: {s}\n", .{name});
         try stdout.flush();
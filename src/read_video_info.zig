@@ -10,13 +10,15 @@ const base_type = @import("base_type.zig");
 ///
 /// 参数:
 ///   path - 视频文件路径
+///   stream_index - 要读取的视频流序号（只计算视频流，不包括封面图片）
 ///
 /// 返回值:
 ///   VideoInfo - 包含视频基本信息的结构体
 ///
 /// 错误:
 ///   当无法找到最佳流、解码器或分配解码器上下文时返回相应错误
-pub fn get_video_info(path: []const u8) !base_type.VideoInfo {
+///   当 stream_index 超出视频流数量时返回 InvalidStreamIndex
+pub fn get_video_info(path: []const u8, stream_index: u32) !base_type.VideoInfo {
     const alloc = std.heap.page_allocator;
 
     _ = av.avformat_network_init();
@@ -45,10 +47,22 @@ pub fn get_video_info(path: []const u8) !base_type.VideoInfo {
 
     try util.error_handle(av.avformat_find_stream_info(context, null));
 
-    // 查找最佳视频流
-    const index: usize = @intCast(av.av_find_best_stream(context, av.AVMEDIA_TYPE_VIDEO, -1, -1, null, 0));
-    if (index < 0)
+    // 查找指定的视频流，并统计视频流数量
+    // 封面图片（attached picture）也是视频流，但只有一帧，不计入视频流
+    var stream_count: u32 = 0;
+    var found: ?usize = null;
+    for (0..context.?.nb_streams) |i| {
+        if (context.?.streams[i].*.codecpar.*.codec_type != av.AVMEDIA_TYPE_VIDEO)
+            continue;
+        if ((context.?.streams[i].*.disposition & av.AV_DISPOSITION_ATTACHED_PIC) != 0)
+            continue;
+        if (stream_count == stream_index)
+            found = i;
+        stream_count += 1;
+    }
+    if (stream_count == 0)
         return err.ffmpeg_err.CannotFoundBestStream;
+    const index = found orelse return err.cli_err.InvalidStreamIndex;
 
     const stream = context.?.streams[index];
     const codec_params = stream.*.codecpar;
@@ -74,7 +88,9 @@ pub fn get_video_info(path: []const u8) !base_type.VideoInfo {
         .width = @intCast(codec_params.*.width),
         .height = @intCast(codec_params.*.height),
         .fps = num / den,
+        .frame_rate = stream.*.avg_frame_rate,
         .frame_index = index,
+        .stream_count = stream_count,
         .fmt = codec_context.*.pix_fmt,
         .time_base = stream.*.time_base,
         .start_time = stream.*.start_time,